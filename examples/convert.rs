@@ -0,0 +1,66 @@
+use std::{
+    env,
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    process,
+};
+
+use providence_io::{
+    convert::{self, Format},
+    recording::{Header, RecordingReader, RecordingWriter, TrackerInfo},
+};
+
+const USAGE: &str = "usage: convert export <recording> <output.jsonl|output.csv> [--textures <dir>]
+       convert import <input.jsonl> <recording> [--textures <dir>]";
+
+fn main() -> io::Result<()> {
+    let args = env::args_os().skip(1).collect::<Vec<_>>();
+    let (command, input, output, textures) = match &*args {
+        [command, input, output] => (command, input, output, None),
+        [command, input, output, flag, dir] if flag == "--textures" => {
+            (command, input, output, Some(PathBuf::from(dir)))
+        }
+        _ => usage(),
+    };
+    let (input, output) = (Path::new(input), Path::new(output));
+
+    if command == "export" {
+        let format = match output.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Format::Csv,
+            _ => Format::JsonLines,
+        };
+        let mut recording = RecordingReader::open(input)?;
+        let count = convert::export(
+            &mut recording,
+            format,
+            File::create(output)?,
+            textures.as_deref(),
+        )?;
+        for damage in recording.damage() {
+            eprintln!("skipped damaged part of recording: {damage}");
+        }
+        eprintln!("exported {count} messages to {}", output.display());
+    } else if command == "import" {
+        let tracker = TrackerInfo {
+            name: String::from("convert"),
+            version: String::new(),
+        };
+        let mut recording = RecordingWriter::create(output, &Header::new(tracker))?;
+        let count = convert::import(
+            BufReader::new(File::open(input)?),
+            &mut recording,
+            textures.as_deref(),
+        )?;
+        recording.finish()?;
+        eprintln!("imported {count} messages into {}", output.display());
+    } else {
+        usage();
+    }
+    Ok(())
+}
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    process::exit(1);
+}
@@ -0,0 +1,89 @@
+use std::{
+    env,
+    ffi::OsString,
+    fs::File,
+    io::{self, BufWriter},
+    process,
+    time::Duration,
+};
+
+use providence_io::{
+    edit::{self, Edit},
+    recording::RecordingReader,
+};
+
+const USAGE: &str = "usage: edit <input> <output> [--trim <start> <end>] [--cut <start> <end>]...
+                                [--strip-textures] [--fps <rate>]
+       edit --concat <output> <input>... [--gap <seconds>]
+times are given in seconds";
+
+fn main() -> io::Result<()> {
+    let mut args = env::args_os().skip(1);
+    let Some(first) = args.next() else { usage() };
+
+    if first == "--concat" {
+        let Some(output) = args.next() else { usage() };
+        let mut inputs = Vec::new();
+        let mut gap = None;
+        while let Some(arg) = args.next() {
+            if arg == "--gap" {
+                gap = Some(seconds(args.next()));
+            } else {
+                inputs.push(RecordingReader::open(arg)?);
+            }
+        }
+        if inputs.is_empty() {
+            usage();
+        }
+        let output_file = BufWriter::new(File::create(&output)?);
+        edit::concat(&mut inputs, output_file, gap)?.into_inner()?;
+        eprintln!(
+            "joined {} recordings into {}",
+            inputs.len(),
+            output.to_string_lossy()
+        );
+        return Ok(());
+    }
+
+    let Some(output) = args.next() else { usage() };
+    let mut edit = Edit::new();
+    while let Some(arg) = args.next() {
+        if arg == "--trim" {
+            edit = edit.with_range(seconds(args.next()), seconds(args.next()));
+        } else if arg == "--cut" {
+            edit = edit.with_cut(seconds(args.next()), seconds(args.next()));
+        } else if arg == "--strip-textures" {
+            edit = edit.with_textures_stripped();
+        } else if arg == "--fps" {
+            match args
+                .next()
+                .and_then(|fps| fps.to_str()?.parse::<f64>().ok())
+            {
+                Some(fps) if fps > 0.0 && fps.is_finite() => edit = edit.with_frame_rate(fps),
+                _ => usage(),
+            }
+        } else {
+            usage();
+        }
+    }
+
+    let mut input = RecordingReader::open(first)?;
+    let output_file = BufWriter::new(File::create(&output)?);
+    edit.apply(&mut input, output_file)?.into_inner()?;
+    for damage in input.damage() {
+        eprintln!("skipped damaged part of recording: {damage}");
+    }
+    eprintln!("wrote {}", output.to_string_lossy());
+    Ok(())
+}
+
+fn seconds(arg: Option<OsString>) -> Duration {
+    arg.and_then(|arg| arg.to_str()?.parse::<f64>().ok())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .unwrap_or_else(|| usage())
+}
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    process::exit(1);
+}
@@ -1,34 +1,79 @@
 use std::{
     env,
-    fs::File,
-    io::{self, stdout, Write},
+    io::{self, stdin, stdout, Read, Write},
     process,
-    time::Instant,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use providence_io::net::Subscriber;
+use providence_io::{
+    net::Subscriber,
+    recording::{Header, RecordingWriter, TrackerInfo},
+};
+
+const USAGE: &str = "usage: record <dest-path> [--duration <seconds>]
+recording stops after the duration, or when stdin is closed (Ctrl-D) or a line is entered";
 
 fn main() -> io::Result<()> {
-    let path = match env::args_os().skip(1).next() {
-        Some(path) => path,
-        None => {
-            eprintln!("usage: record <dest-path>");
-            process::exit(1);
-        }
+    let mut args = env::args_os().skip(1);
+    let Some(path) = args.next() else { usage() };
+    let duration = match (args.next(), args.next()) {
+        (None, _) => None,
+        (Some(flag), Some(secs)) if flag == "--duration" => Some(
+            secs.to_str()
+                .and_then(|secs| secs.parse::<f64>().ok())
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .unwrap_or_else(|| usage()),
+        ),
+        _ => usage(),
     };
-    let mut file = File::create(path)?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop2 = stop.clone();
+    thread::spawn(move || {
+        // Any input, or EOF, stops the recording.
+        let _ = stdin().read(&mut [0]);
+        stop2.store(true, Ordering::Relaxed);
+    });
 
     let mut sub = Subscriber::autoconnect_blocking()?;
-    let mut last = Instant::now();
+    let mut msg = sub.block()?;
+    let start = Instant::now();
+
+    // The handshake has completed once the first message arrives.
+    let tracker = TrackerInfo {
+        name: sub
+            .tracker()
+            .map_or(String::new(), |hello| hello.name.clone()),
+        version: String::new(),
+    };
+    let mut recording = RecordingWriter::create(&path, &Header::new(tracker))?;
     loop {
-        let msg = sub.block()?;
-        let now = Instant::now();
-        let dur: u64 = now.duration_since(last).as_micros().try_into().unwrap();
-        last = now;
-        file.write_all(&dur.to_le_bytes())?;
-        msg.write(&mut file)?;
-        file.flush()?;
+        recording.write(start.elapsed(), &msg)?;
+        // If we get killed instead of stopped, the recording lacks its index, but flushing after
+        // every message keeps everything up to that point readable.
+        recording.flush()?;
         print!(".");
         stdout().flush()?;
+
+        msg = sub.block()?;
+        if stop.load(Ordering::Relaxed) || duration.is_some_and(|d| start.elapsed() >= d) {
+            break;
+        }
     }
+
+    let messages = recording.len();
+    recording.finish()?;
+    println!();
+    eprintln!("wrote {messages} messages to {}", path.to_string_lossy());
+    Ok(())
+}
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    process::exit(1);
 }
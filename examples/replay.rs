@@ -1,48 +1,55 @@
 use std::{
     env,
-    fs::File,
-    io::{self, stdout, BufReader, Read, Seek, Write},
+    io::{self, stderr, stdin},
     process, thread,
-    time::Duration,
 };
 
-use providence_io::{data::TrackingMessage, net::Publisher};
+use providence_io::{net::Publisher, recording::RecordingReader, replay::Replayer};
+
+const HELP: &str = "commands: play, pause, toggle, speed <factor>, seek <time>, step, back, \
+                    loop [<start> <end>], quit";
 
 fn main() -> io::Result<()> {
-    let path = match env::args_os().skip(1).next() {
-        Some(path) => path,
-        None => {
-            eprintln!("usage: replay <dest-path>");
+    let mut args = env::args_os().skip(1);
+    let (path, control) = match (args.next(), args.next(), args.next()) {
+        (Some(path), None, None) => (path, None),
+        (Some(path), Some(flag), Some(addr)) if flag == "--control" => (path, Some(addr)),
+        _ => {
+            eprintln!("usage: replay <path> [--control <addr>]");
             process::exit(1);
         }
     };
-    let mut file = BufReader::new(File::open(path)?);
-    let mut publisher = Publisher::spawn()?;
-
-    loop {
-        match replay(&mut file, &mut publisher) {
-            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {}
-            Err(e) => {
-                return Err(e);
-            }
-            Ok(()) => {}
-        }
 
-        println!();
-        file.seek(io::SeekFrom::Start(0))?;
+    let recording = RecordingReader::open(path)?;
+    if !recording.is_finished() {
+        eprintln!("recording is incomplete, the index was rebuilt");
     }
-}
-
-fn replay(mut file: &mut BufReader<File>, publisher: &mut Publisher) -> io::Result<()> {
-    loop {
-        let mut buf = [0; 8];
-        file.read_exact(&mut buf)?;
-        let micros = u64::from_le_bytes(buf);
-        let dur = Duration::from_micros(micros);
-        let msg = TrackingMessage::read(&mut file)?;
-        thread::sleep(dur);
-        publisher.publish(msg);
-        print!(".");
-        stdout().flush()?;
+    for damage in recording.damage() {
+        eprintln!("skipping damaged part of recording: {damage}");
     }
+    eprintln!(
+        "replaying {} messages ({:.1?}) recorded from `{}`",
+        recording.len(),
+        recording.duration(),
+        recording.header().tracker.name,
+    );
+
+    let mut replayer = Replayer::new(recording);
+    let controls = replayer.controls();
+    let _socket = match control {
+        Some(addr) => {
+            let addr = addr.to_str().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "invalid control address")
+            })?;
+            let socket = controls.listen(addr)?;
+            eprintln!("accepting commands on {}", socket.local_addr());
+            Some(socket)
+        }
+        None => None,
+    };
+    eprintln!("{HELP}");
+    thread::spawn(move || controls.serve(stdin().lock(), stderr()));
+
+    let mut publisher = Publisher::spawn()?;
+    replayer.run(&mut publisher)
 }
@@ -0,0 +1,14 @@
+use std::env;
+
+use providence_io::data::TrackingMessage;
+
+fn main() {
+    let schema = TrackingMessage::schema();
+    if env::args().skip(1).any(|arg| arg == "--json") {
+        println!("{}", schema.to_json());
+    } else {
+        println!("fingerprint: {:016x}", TrackingMessage::fingerprint());
+        println!();
+        println!("{schema}");
+    }
+}
@@ -1,14 +1,28 @@
-use std::io;
+use std::{env, io};
 
 use macroquad::{models::Vertex, prelude::*, texture::Texture2D};
-use providence_io::{data::Eye, net::Subscriber};
+use providence_io::{
+    data::Eye,
+    discovery,
+    net::{ConnectionEvent, ReconnectPolicy, Subscriber, SubscriberOptions},
+};
 use zaru::linalg::Quat;
 
 const SCALE: f32 = 120.0;
 
 #[macroquad::main("Providence Viewer")]
 async fn main() -> io::Result<()> {
-    let mut sub = Subscriber::autoconnect_blocking()?;
+    // The tracker goes idle when nobody is watching, so survive short disconnects.
+    let options = SubscriberOptions::new().with_reconnect(ReconnectPolicy::new());
+    // With several trackers on the network, the one to view can be picked by name.
+    let mut sub = match env::args().nth(1) {
+        Some(name) => {
+            println!("waiting for tracker `{name}`");
+            let tracker = discovery::find_blocking(&name, None)?;
+            Subscriber::connect_to_tracker(&tracker, options)
+        }
+        None => Subscriber::autoconnect_blocking_with_options(options)?,
+    };
     println!("connected to tracker");
 
     let mut msg = sub.block()?;
@@ -24,6 +38,11 @@ async fn main() -> io::Result<()> {
         if let Some(next) = sub.next()? {
             msg = next;
         }
+        for event in sub.events() {
+            if let ConnectionEvent::Reconnecting { reason, .. } = event {
+                println!("connection lost ({reason}), reconnecting");
+            }
+        }
 
         clear_background(BLACK);
 
@@ -0,0 +1,18 @@
+//! Feeds arbitrary bytes to [`TrackingMessage::async_read`].
+//!
+//! ```text
+//! cargo +nightly fuzz run async_read -- -malloc_limit_mb=8
+//! ```
+
+#![no_main]
+
+use futures_lite::future::block_on;
+use libfuzzer_sys::fuzz_target;
+use providence_io::data::TrackingMessage;
+
+fuzz_target!(|data: &[u8]| {
+    let mut data = data;
+    while let Ok(mut msg) = block_on(TrackingMessage::async_read(&mut data)) {
+        msg.decode_images().ok();
+    }
+});
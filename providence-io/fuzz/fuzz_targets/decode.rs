@@ -0,0 +1,16 @@
+//! Feeds arbitrary frame payloads to every [`Codec`].
+//!
+//! ```text
+//! cargo +nightly fuzz run decode -- -malloc_limit_mb=8
+//! ```
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use providence_io::data::Codec;
+
+fuzz_target!(|data: &[u8]| {
+    for codec in Codec::ALL {
+        codec.decode(data).ok();
+    }
+});
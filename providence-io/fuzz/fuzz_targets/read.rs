@@ -0,0 +1,22 @@
+//! Feeds arbitrary bytes to [`TrackingMessage::read`].
+//!
+//! Run with an allocation limit well below the default 16 MiB frame size limit to catch any
+//! allocation that trusts the length prefix:
+//!
+//! ```text
+//! cargo +nightly fuzz run read -- -malloc_limit_mb=8
+//! ```
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use providence_io::data::TrackingMessage;
+
+fuzz_target!(|data: &[u8]| {
+    let mut data = data;
+    // Decode frames until the input is exhausted or malformed, like a subscriber would.
+    while let Ok(mut msg) = TrackingMessage::read(&mut data) {
+        // Compressed eye textures are untrusted input too.
+        msg.decode_images().ok();
+    }
+});
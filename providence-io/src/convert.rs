@@ -0,0 +1,671 @@
+//! Conversion of recordings to and from formats understood by other tools.
+//!
+//! [`export`] writes the messages of a recording as [JSON Lines](https://jsonlines.org/) or CSV,
+//! for analysis in tools like pandas or spreadsheets. Eye textures can be written to a directory
+//! as numbered PNG files alongside. [`import`] turns JSON Lines written by [`export`] back into a
+//! recording.
+//!
+//! # JSON Lines
+//!
+//! Every line holds one message, as an object with these fields:
+//!
+//! | Field       | Contents                                                                  |
+//! |-------------|---------------------------------------------------------------------------|
+//! | `time`      | Seconds since the start of the recording                                  |
+//! | `timestamp` | [`TrackingMessage::timestamp`]                                            |
+//! | `timing`    | [`TrackingMessage::timing`] (omitted if the tracker did not provide it)   |
+//! | `faces`     | The faces in view, as [`FaceData`] objects                                |
+//!
+//! Faces additionally contain `head_euler`, the [head rotation as Euler
+//! angles](FaceData::head_rotation_euler) in degrees. Eyes contain the file name of their
+//! `texture` instead of the image data, if textures were exported. Fields that don't affect the
+//! message (like `head_euler`) are ignored when importing.
+//!
+//! JSON has no representation for non-finite floats, so NaN and infinities are written as the
+//! strings `"NaN"`, `"inf"` and `"-inf"`.
+//!
+//! # CSV
+//!
+//! Every row holds one face, so messages with several faces in view take up several rows, and
+//! messages without any take up one row with only the message columns filled in. The columns
+//! are:
+//!
+//! - `time`, `timestamp`: like in JSON Lines.
+//! - `face`: the index of the face in the message.
+//! - `ephemeral_id`: [`FaceData::ephemeral_id`].
+//! - `persistent_id`: the identity name, if available.
+//! - `head_x`, `head_y`: [`FaceData::head_position`].
+//! - `head_qx`, `head_qy`, `head_qz`, `head_qw`: [`FaceData::head_rotation`].
+//! - `head_euler_x`, `head_euler_y`, `head_euler_z`: the head rotation as Euler angles in degrees.
+//! - For each of `left` and `right`:
+//!   - `<eye>_iris_x`, `<eye>_iris_y`, `<eye>_iris_z`, `<eye>_iris_radius`: [`Eye::iris_center`]
+//!     and [`Eye::iris_radius`].
+//!   - `<eye>_texture`: the file name of the texture, if textures were exported.
+//!   - `<eye>_v<n>_x`, `<eye>_v<n>_y`, `<eye>_v<n>_z`: the position of mesh vertex `n`, for as many
+//!     vertices as the largest mesh in the recording has.
+//!
+//! Floats are written with full precision. Columns without a value are left empty.
+
+use std::{
+    fmt::{self, Write as _},
+    fs,
+    io::{self, BufRead, Read, Seek, Write},
+    path::Path,
+    time::Duration,
+};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    data::{
+        Eye, FaceData, Image, ImageEncoding, Mesh, PersistentId, Timing, TrackingMessage, Vertex,
+    },
+    recording::{RecordingReader, RecordingWriter},
+};
+
+/// The PNG file signature, followed by the length and type of the `IHDR` chunk.
+const PNG_HEADER: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR";
+
+/// A format [`export`] can write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// [JSON Lines](https://jsonlines.org/), with one message per line.
+    ///
+    /// This contains everything needed to [`import`] the messages again, except for the eye
+    /// textures, which are only preserved if they are exported alongside.
+    JsonLines,
+    /// CSV, with one face per row.
+    ///
+    /// This only contains the data useful for analysis, and can't be imported again.
+    Csv,
+}
+
+/// Exports all messages of a recording to `writer`, returning the number of messages exported.
+///
+/// If `textures` is given, the eye textures are written to that directory as PNG files, named
+/// after the number of the message, the index of the face and the side of the eye (for example,
+/// `000042-0-left.png`). The directory is created if necessary. This requires the `png` feature.
+///
+/// Damaged messages are skipped (see [`RecordingReader::next_message`]).
+pub fn export<R: Read + Seek, W: Write>(
+    reader: &mut RecordingReader<R>,
+    format: Format,
+    writer: W,
+    textures: Option<&Path>,
+) -> io::Result<usize> {
+    let mut writer = io::BufWriter::new(writer);
+    if let Some(dir) = textures {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut csv = None;
+    if format == Format::Csv {
+        let columns = CsvColumns::scan(reader)?;
+        writer.write_all(columns.header().as_bytes())?;
+        csv = Some(columns);
+    }
+
+    reader.seek(Duration::ZERO)?;
+    let mut count = 0;
+    while let Some(record) = reader.next_message()? {
+        let mut line = Line::new(record.time, &record.message);
+        if let Some(dir) = textures {
+            write_textures(&record.message, &mut line, count, dir)?;
+        }
+        match &csv {
+            Some(columns) => writer.write_all(columns.rows(&line).as_bytes())?,
+            None => {
+                serde_json::to_writer(&mut writer, &line)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Imports messages from JSON Lines written by [`export`] into a recording, returning the number
+/// of messages imported.
+///
+/// Texture file names are looked up in the `textures` directory, or relative to the working
+/// directory if it is [`None`]. Textures are stored as [`ImageEncoding::Png`]. Eyes without a
+/// texture get an empty one. Empty lines are ignored.
+///
+/// Fails with [`io::ErrorKind::InvalidData`] if a line can't be parsed or refers to a texture
+/// that isn't a PNG file, and with [`io::ErrorKind::InvalidInput`] if the times of the messages
+/// aren't in order.
+pub fn import<R: BufRead, W: Write>(
+    reader: R,
+    writer: &mut RecordingWriter<W>,
+    textures: Option<&Path>,
+) -> io::Result<usize> {
+    let mut count = 0;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = |e: &dyn std::fmt::Display| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {e}", i + 1))
+        };
+
+        let line: Line = serde_json::from_str(&line).map_err(|e| invalid(&e))?;
+        Duration::try_from_secs_f64(line.time).map_err(|e| invalid(&e))?;
+        // Recordings store microseconds. Round, so that times written by `export` come out the same.
+        let time = Duration::from_micros((line.time * 1e6).round() as u64);
+        let message = line.into_message(textures).map_err(|e| invalid(&e))?;
+        writer.write(time, &message)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn write_textures(
+    message: &TrackingMessage,
+    line: &mut Line,
+    number: usize,
+    dir: &Path,
+) -> io::Result<()> {
+    for (index, (face, line)) in message.faces.iter().zip(&mut line.faces).enumerate() {
+        let eyes = [
+            ("left", &face.left_eye, &mut line.left_eye),
+            ("right", &face.right_eye, &mut line.right_eye),
+        ];
+        for (side, eye, line) in eyes {
+            let (Some(eye), Some(line)) = (eye, line) else {
+                continue;
+            };
+            if eye.texture.width == 0 || eye.texture.height == 0 {
+                continue;
+            }
+            let name = format!("{number:06}-{index}-{side}.png");
+            fs::write(
+                dir.join(&name),
+                eye.texture.encode(ImageEncoding::Png)?.data,
+            )?;
+            line.texture = Some(name);
+        }
+    }
+    Ok(())
+}
+
+/// Reads a texture written by [`export`].
+fn read_texture(path: &Path) -> io::Result<Image> {
+    let data = fs::read(path)?;
+    // The size is stored in the `IHDR` chunk, which comes first.
+    let size = data
+        .strip_prefix(PNG_HEADER)
+        .and_then(|ihdr| Some((ihdr.get(0..4)?, ihdr.get(4..8)?)));
+    let Some((width, height)) = size else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a PNG file", path.display()),
+        ));
+    };
+    Ok(Image {
+        width: u32::from_be_bytes(width.try_into().unwrap()),
+        height: u32::from_be_bytes(height.try_into().unwrap()),
+        encoding: ImageEncoding::Png,
+        data,
+    })
+}
+
+/// A message, as written to JSON Lines.
+#[derive(Serialize, Deserialize)]
+struct Line {
+    time: f64,
+    timestamp: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timing: Option<Timing>,
+    faces: Vec<FaceLine>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FaceLine {
+    ephemeral_id: u32,
+    persistent_id: PersistentId,
+    head_position: [Float; 2],
+    head_rotation: [Float; 4],
+    #[serde(default, skip_deserializing)]
+    head_euler: [Float; 3],
+    left_eye: Option<EyeLine>,
+    right_eye: Option<EyeLine>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EyeLine {
+    iris_center: [Float; 3],
+    iris_radius: Float,
+    mesh: MeshLine,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    texture: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MeshLine {
+    vertices: Vec<VertexLine>,
+    indices: Vec<u16>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct VertexLine {
+    position: [Float; 3],
+    uv: [Float; 2],
+}
+
+/// An `f32` that is written as a string if it isn't finite, since JSON has no representation for
+/// that.
+#[derive(Default, Clone, Copy)]
+struct Float(f32);
+
+impl Float {
+    fn wrap<const N: usize>(floats: [f32; N]) -> [Self; N] {
+        floats.map(Self)
+    }
+
+    fn unwrap<const N: usize>(floats: [Self; N]) -> [f32; N] {
+        floats.map(|f| f.0)
+    }
+}
+
+impl fmt::Display for Float {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Serialize for Float {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.0.is_finite() {
+            serializer.serialize_f32(self.0)
+        } else {
+            serializer.collect_str(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Float {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = Float;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a number, \"NaN\", \"inf\" or \"-inf\"")
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Float, E> {
+                Ok(Float(v as f32))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Float, E> {
+                Ok(Float(v as f32))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Float, E> {
+                Ok(Float(v as f32))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Float, E> {
+                match v.parse::<f32>() {
+                    Ok(f) if !f.is_finite() => Ok(Float(f)),
+                    _ => Err(E::invalid_value(de::Unexpected::Str(v), &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl Line {
+    fn new(time: Duration, message: &TrackingMessage) -> Self {
+        let eye = |eye: &Option<Eye>| {
+            eye.as_ref().map(|eye| EyeLine {
+                iris_center: Float::wrap(eye.iris_center),
+                iris_radius: Float(eye.iris_radius),
+                mesh: MeshLine {
+                    vertices: eye
+                        .mesh
+                        .vertices
+                        .iter()
+                        .map(|vertex| VertexLine {
+                            position: Float::wrap(vertex.position),
+                            uv: Float::wrap(vertex.uv),
+                        })
+                        .collect(),
+                    indices: eye.mesh.indices.clone(),
+                },
+                texture: None,
+            })
+        };
+        Self {
+            time: time.as_secs_f64(),
+            timestamp: message.timestamp,
+            timing: message.timing,
+            faces: message
+                .faces
+                .iter()
+                .map(|face| FaceLine {
+                    ephemeral_id: face.ephemeral_id,
+                    persistent_id: face.persistent_id.clone(),
+                    head_position: Float::wrap(face.head_position),
+                    head_rotation: Float::wrap(face.head_rotation),
+                    head_euler: Float::wrap(face.head_rotation_euler().map(f32::to_degrees)),
+                    left_eye: eye(&face.left_eye),
+                    right_eye: eye(&face.right_eye),
+                })
+                .collect(),
+        }
+    }
+
+    fn into_message(self, textures: Option<&Path>) -> io::Result<TrackingMessage> {
+        let eye = |eye: Option<EyeLine>| {
+            eye.map(|eye| {
+                let texture = match eye.texture {
+                    Some(name) => read_texture(&textures.unwrap_or(Path::new("")).join(name))?,
+                    None => Image::from_rgba(0, 0, Vec::new()),
+                };
+                Ok::<_, io::Error>(Eye {
+                    texture,
+                    mesh: Mesh {
+                        vertices: eye
+                            .mesh
+                            .vertices
+                            .into_iter()
+                            .map(|vertex| Vertex {
+                                position: Float::unwrap(vertex.position),
+                                uv: Float::unwrap(vertex.uv),
+                            })
+                            .collect(),
+                        indices: eye.mesh.indices,
+                    },
+                    iris_center: Float::unwrap(eye.iris_center),
+                    iris_radius: eye.iris_radius.0,
+                })
+            })
+            .transpose()
+        };
+        let mut faces = Vec::with_capacity(self.faces.len());
+        for face in self.faces {
+            faces.push(FaceData {
+                ephemeral_id: face.ephemeral_id,
+                persistent_id: face.persistent_id,
+                head_position: Float::unwrap(face.head_position),
+                head_rotation: Float::unwrap(face.head_rotation),
+                left_eye: eye(face.left_eye)?,
+                right_eye: eye(face.right_eye)?,
+            });
+        }
+        Ok(TrackingMessage {
+            timestamp: self.timestamp,
+            timing: self.timing,
+            faces,
+        })
+    }
+}
+
+/// The variable part of the CSV columns.
+struct CsvColumns {
+    /// The number of vertex columns per eye.
+    vertices: usize,
+}
+
+impl CsvColumns {
+    /// Determines the columns needed for the messages in a recording.
+    fn scan<R: Read + Seek>(reader: &mut RecordingReader<R>) -> io::Result<Self> {
+        let mut vertices = 0;
+        reader.seek(Duration::ZERO)?;
+        while let Some(record) = reader.next_message()? {
+            for face in &record.message.faces {
+                for eye in [&face.left_eye, &face.right_eye].into_iter().flatten() {
+                    vertices = vertices.max(eye.mesh.vertices.len());
+                }
+            }
+        }
+        Ok(Self { vertices })
+    }
+
+    fn header(&self) -> String {
+        let mut header = String::from(
+            "time,timestamp,face,ephemeral_id,persistent_id,head_x,head_y,\
+             head_qx,head_qy,head_qz,head_qw,head_euler_x,head_euler_y,head_euler_z",
+        );
+        for side in ["left", "right"] {
+            for column in ["iris_x", "iris_y", "iris_z", "iris_radius", "texture"] {
+                write!(header, ",{side}_{column}").unwrap();
+            }
+            for n in 0..self.vertices {
+                write!(header, ",{side}_v{n}_x,{side}_v{n}_y,{side}_v{n}_z").unwrap();
+            }
+        }
+        header.push('\n');
+        header
+    }
+
+    /// Returns the rows for a message.
+    fn rows(&self, line: &Line) -> String {
+        let mut rows = String::new();
+        let prefix = format!("{},{}", line.time, line.timestamp);
+        if line.faces.is_empty() {
+            let empty_columns = 12 + 2 * (5 + 3 * self.vertices);
+            rows.push_str(&prefix);
+            rows.push_str(&",".repeat(empty_columns));
+            rows.push('\n');
+        }
+        for (index, face) in line.faces.iter().enumerate() {
+            let name = match &face.persistent_id {
+                PersistentId::Available(name) => csv_field(name),
+                _ => String::new(),
+            };
+            let [x, y] = face.head_position;
+            let [qx, qy, qz, qw] = face.head_rotation;
+            let [ex, ey, ez] = face.head_euler;
+            write!(
+                rows,
+                "{prefix},{index},{},{name},{x},{y},{qx},{qy},{qz},{qw},{ex},{ey},{ez}",
+                face.ephemeral_id,
+            )
+            .unwrap();
+            for eye in [&face.left_eye, &face.right_eye] {
+                self.write_eye(&mut rows, eye.as_ref());
+            }
+            rows.push('\n');
+        }
+        rows
+    }
+
+    fn write_eye(&self, row: &mut String, eye: Option<&EyeLine>) {
+        let Some(eye) = eye else {
+            row.push_str(&",".repeat(5 + 3 * self.vertices));
+            return;
+        };
+        let [x, y, z] = eye.iris_center;
+        let texture = eye.texture.as_deref().map(csv_field).unwrap_or_default();
+        write!(row, ",{x},{y},{z},{},{texture}", eye.iris_radius).unwrap();
+        for n in 0..self.vertices {
+            match eye.mesh.vertices.get(n) {
+                Some(vertex) => {
+                    let [x, y, z] = vertex.position;
+                    write!(row, ",{x},{y},{z}").unwrap();
+                }
+                None => row.push_str(",,,"),
+            }
+        }
+    }
+}
+
+/// Quotes a CSV field if necessary.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::recording::tests::header;
+
+    use super::*;
+
+    fn eye(shade: u8) -> Eye {
+        Eye {
+            texture: Image::from_rgba(2, 1, vec![shade; 8]),
+            mesh: Mesh {
+                vertices: vec![
+                    Vertex {
+                        position: [0.0, 0.5, 1.0],
+                        uv: [0.0, 1.0],
+                    };
+                    3
+                ],
+                indices: vec![0, 1, 2],
+            },
+            iris_center: [0.25, 0.5, 0.0],
+            iris_radius: 0.125,
+        }
+    }
+
+    fn recording() -> RecordingReader<Cursor<Vec<u8>>> {
+        let mut writer = RecordingWriter::new(Vec::new(), &header()).unwrap();
+        let messages = [
+            TrackingMessage {
+                timestamp: 1,
+                timing: None,
+                faces: Vec::new(),
+            },
+            TrackingMessage {
+                timestamp: 2,
+                timing: Some(Timing {
+                    timestamp: 2,
+                    epoch_offset: -1,
+                }),
+                faces: vec![FaceData {
+                    ephemeral_id: 7,
+                    persistent_id: PersistentId::Available("a, \"b\"".into()),
+                    head_position: [0.5, 0.25],
+                    head_rotation: [0.0, 0.0, 0.0, 1.0],
+                    left_eye: Some(eye(10)),
+                    right_eye: Some(eye(20)),
+                }],
+            },
+        ];
+        for (i, message) in messages.iter().enumerate() {
+            writer
+                .write(Duration::from_micros(i as u64 * 33_333), message)
+                .unwrap();
+        }
+        RecordingReader::new(Cursor::new(writer.finish().unwrap())).unwrap()
+    }
+
+    #[test]
+    fn csv() {
+        let mut out = Vec::new();
+        let count = export(&mut recording(), Format::Csv, &mut out, None).unwrap();
+        assert_eq!(count, 2);
+
+        let csv = String::from_utf8(out).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        let columns = lines[0].split(',').count();
+        assert_eq!(columns, 14 + 2 * (5 + 3 * 3));
+        assert!(lines[0].ends_with(",right_v2_x,right_v2_y,right_v2_z"));
+        assert_eq!(lines[1], format!("0,1{}", ",".repeat(columns - 2)));
+        assert!(
+            lines[2].starts_with("0.033333,2,0,7,\"a, \"\"b\"\"\",0.5,0.25,0,0,0,1,0,0,0,0.25,"),
+            "{}",
+            lines[2],
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn json_lines_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("providence-convert-{}", std::process::id()));
+        let mut out = Vec::new();
+        let count = export(&mut recording(), Format::JsonLines, &mut out, Some(&dir)).unwrap();
+        assert_eq!(count, 2);
+        assert!(dir.join("000001-0-left.png").exists());
+        assert!(dir.join("000001-0-right.png").exists());
+
+        let mut writer = RecordingWriter::new(Vec::new(), &header()).unwrap();
+        let count = import(&*out, &mut writer, Some(&dir)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(count, 2);
+
+        let mut original = recording();
+        let mut imported = RecordingReader::new(Cursor::new(writer.finish().unwrap())).unwrap();
+        while let Some(expected) = original.next_message().unwrap() {
+            let mut record = imported.next_message().unwrap().unwrap();
+            assert_eq!(record.time, expected.time);
+            record.message.decode_images().unwrap();
+            assert_eq!(
+                format!("{:?}", record.message),
+                format!("{:?}", expected.message)
+            );
+        }
+        assert!(imported.next_message().unwrap().is_none());
+    }
+
+    #[test]
+    fn json_lines_non_finite() {
+        let mut eye = eye(0);
+        eye.texture = Image::from_rgba(0, 0, Vec::new());
+        eye.iris_radius = f32::INFINITY;
+        eye.mesh.vertices[1].uv = [f32::NEG_INFINITY, f32::NAN];
+        let message = TrackingMessage {
+            timestamp: 1,
+            timing: None,
+            faces: vec![FaceData {
+                ephemeral_id: 0,
+                persistent_id: PersistentId::Unavailable,
+                head_position: [f32::NAN, 0.5],
+                head_rotation: [0.0, 0.0, 0.0, 1.0],
+                left_eye: Some(eye),
+                right_eye: None,
+            }],
+        };
+        let header = header();
+        let mut writer = RecordingWriter::new(Vec::new(), &header).unwrap();
+        writer.write(Duration::ZERO, &message).unwrap();
+        let mut reader = RecordingReader::new(Cursor::new(writer.finish().unwrap())).unwrap();
+
+        let mut out = Vec::new();
+        export(&mut reader, Format::JsonLines, &mut out, None).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains(r#""head_position":["NaN",0.5]"#), "{json}");
+        assert!(json.contains(r#""iris_radius":"inf""#), "{json}");
+
+        let mut writer = RecordingWriter::new(Vec::new(), &header).unwrap();
+        import(json.as_bytes(), &mut writer, None).unwrap();
+        let mut imported = RecordingReader::new(Cursor::new(writer.finish().unwrap())).unwrap();
+        let record = imported.next_message().unwrap().unwrap();
+        assert_eq!(format!("{:?}", record.message), format!("{message:?}"));
+
+        // Only non-finite floats may be written as strings.
+        let mut writer = RecordingWriter::new(Vec::new(), &header).unwrap();
+        let json = json.replace(r#""NaN""#, r#""1""#);
+        let err = import(json.as_bytes(), &mut writer, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn import_errors() {
+        let mut writer = RecordingWriter::new(Vec::new(), &header()).unwrap();
+        let line = r#"{"time":1,"timestamp":0,"faces":[]}"#;
+        let err = import(&b"{}"[..], &mut writer, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = import(format!("\n{line}\n{{").as_bytes(), &mut writer, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 3:"), "{err}");
+        let early = line.replace("\"time\":1", "\"time\":0.5");
+        let err = import(early.as_bytes(), &mut writer, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
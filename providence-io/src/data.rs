@@ -1,11 +1,14 @@
-use std::io::{self, BufRead, Write};
+use std::io::{self, BufRead, Read as _, Write};
 use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use bincode::Options as _;
 use futures_lite::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
 use serde::{Deserialize, Serialize};
 
-use crate::fingerprint::serde_fingerprint;
+use crate::{fingerprint::Schema, texture};
 
+static SCHEMA: OnceLock<Schema> = OnceLock::new();
 static FINGERPRINT: OnceLock<u64> = OnceLock::new();
 
 /// The top-level protocol message.
@@ -13,7 +16,8 @@ static FINGERPRINT: OnceLock<u64> = OnceLock::new();
 pub struct TrackingMessage {
     /// Timestamp in microseconds since an unspecified point in time.
     ///
-    /// Wraps to zero after `u32::MAX`. Clients must handle this correctly.
+    /// Wraps to zero after `u32::MAX`. Clients must handle this correctly, either by using
+    /// [`TrackingMessage::timing`] if the tracker provides it, or with a [`TimestampUnwrapper`].
     ///
     /// The tracker should choose a timestamp source that allows precisely relating two subsequent
     /// tracking messages in time. The time stamp should be captured as early in the tracking
@@ -21,84 +25,579 @@ pub struct TrackingMessage {
     /// timing jitter during processing.
     pub timestamp: u32,
 
+    /// Non-wrapping timestamp and wall-clock time of the message.
+    ///
+    /// [`None`] if the tracker does not provide this information.
+    pub timing: Option<Timing>,
+
     /// The list of tracked faces that are currently in view.
     pub faces: Vec<FaceData>,
 }
 
 impl TrackingMessage {
-    pub fn read<R: BufRead>(mut read: R) -> io::Result<Self> {
-        let mut fingerprint = [0; 8];
-        read.read_exact(&mut fingerprint)?;
-        let fingerprint = u64::from_le_bytes(fingerprint);
+    /// Reads a length-prefixed [`TrackingMessage`] frame encoded with [`Codec::Bincode`].
+    ///
+    /// Frames don't carry any version information. Peers are expected to check that they speak the
+    /// same protocol beforehand (see [`crate::handshake`]).
+    ///
+    /// Frames are subject to the default [`DecodeLimits`].
+    pub fn read<R: BufRead>(read: R) -> io::Result<Self> {
+        Self::read_with_limits(read, &DecodeLimits::default())
+    }
+
+    /// Reads a length-prefixed [`TrackingMessage`] frame encoded with [`Codec::Bincode`],
+    /// enforcing the given [`DecodeLimits`].
+    ///
+    /// Fails in the same way as [`Codec::read`].
+    pub fn read_with_limits<R: BufRead>(read: R, limits: &DecodeLimits) -> io::Result<Self> {
+        Codec::Bincode.read(read, limits)
+    }
 
-        if Self::fingerprint() != fingerprint {
+    /// Writes a length-prefixed [`TrackingMessage`] frame encoded with [`Codec::Bincode`].
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the message is too large to be framed.
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
+        Codec::Bincode.write(self, writer)
+    }
+
+    /// Asynchronously reads a length-prefixed [`TrackingMessage`] frame encoded with
+    /// [`Codec::Bincode`].
+    ///
+    /// Frames are subject to the default [`DecodeLimits`].
+    pub async fn async_read<R: AsyncRead + Unpin>(read: R) -> io::Result<Self> {
+        Self::async_read_with_limits(read, &DecodeLimits::default()).await
+    }
+
+    /// Asynchronously reads a length-prefixed [`TrackingMessage`] frame encoded with
+    /// [`Codec::Bincode`], enforcing the given [`DecodeLimits`].
+    ///
+    /// Fails in the same way as [`Codec::read`].
+    pub async fn async_read_with_limits<R: AsyncRead + Unpin>(
+        read: R,
+        limits: &DecodeLimits,
+    ) -> io::Result<Self> {
+        Codec::Bincode.async_read(read, limits).await
+    }
+
+    /// Asynchronously writes a length-prefixed [`TrackingMessage`] frame encoded with
+    /// [`Codec::Bincode`].
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the message is too large to be framed.
+    pub async fn async_write<W: AsyncWrite + Unpin>(&self, writer: W) -> io::Result<()> {
+        Codec::Bincode.async_write(self, writer).await
+    }
+
+    /// Sets [`TrackingMessage::timing`], and [`TrackingMessage::timestamp`] to match it.
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timestamp = timing.timestamp as u32;
+        self.timing = Some(timing);
+    }
+
+    /// Re-encodes all eye textures in this message with the given [`ImageEncoding`].
+    pub fn encode_images(&mut self, encoding: ImageEncoding) -> io::Result<()> {
+        self.for_each_image(|image| {
+            *image = image.encode(encoding)?;
+            Ok(())
+        })
+    }
+
+    /// Decodes all eye textures in this message to [`ImageEncoding::Rgba`].
+    ///
+    /// Compressed images are subject to the default [`DecodeLimits`].
+    pub fn decode_images(&mut self) -> io::Result<()> {
+        self.decode_images_with_limits(&DecodeLimits::default())
+    }
+
+    /// Decodes all eye textures in this message to [`ImageEncoding::Rgba`], enforcing the given
+    /// [`DecodeLimits`].
+    pub fn decode_images_with_limits(&mut self, limits: &DecodeLimits) -> io::Result<()> {
+        self.for_each_image(|image| {
+            *image = image.to_rgba_with_limits(limits)?;
+            Ok(())
+        })
+    }
+
+    /// Checks that all floats in this message are finite.
+    #[cfg(feature = "json")]
+    fn check_finite(&self) -> io::Result<()> {
+        let mut floats = Vec::new();
+        for face in &self.faces {
+            floats.extend(face.head_position);
+            floats.extend(face.head_rotation);
+            for eye in [&face.left_eye, &face.right_eye].into_iter().flatten() {
+                floats.extend(eye.iris_center);
+                floats.push(eye.iris_radius);
+                for vertex in &eye.mesh.vertices {
+                    floats.extend(vertex.position);
+                    floats.extend(vertex.uv);
+                }
+            }
+        }
+        if floats.iter().any(|f| !f.is_finite()) {
             return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "message fingerprint mismatch",
+                io::ErrorKind::InvalidInput,
+                "message contains non-finite floats",
             ));
         }
+        Ok(())
+    }
 
-        let mut size = [0; 4];
-        read.read_exact(&mut size)?;
-        let size = u32::from_le_bytes(size);
+    /// Replaces all eye textures with empty images.
+    pub(crate) fn strip_textures(&mut self) {
+        self.for_each_image(|image| {
+            *image = Image::from_rgba(0, 0, Vec::new());
+            Ok(())
+        })
+        .unwrap();
+    }
 
-        let val = bincode::deserialize_from(&mut read.take(size.into())).map_err(convert_error)?;
-        Ok(val)
+    fn for_each_image(
+        &mut self,
+        mut f: impl FnMut(&mut Image) -> io::Result<()>,
+    ) -> io::Result<()> {
+        for face in &mut self.faces {
+            for eye in [&mut face.left_eye, &mut face.right_eye]
+                .into_iter()
+                .flatten()
+            {
+                f(&mut eye.texture)?;
+            }
+        }
+        Ok(())
     }
 
-    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
-        writer.write_all(&Self::fingerprint().to_le_bytes())?;
+    /// Returns the [`Schema`] describing the structure of [`TrackingMessage`]s.
+    pub fn schema() -> &'static Schema {
+        SCHEMA.get_or_init(Schema::of::<Self>)
+    }
 
-        let size = bincode::serialized_size(self).map_err(convert_error)?;
-        writer.write_all(&u32::try_from(size).unwrap().to_le_bytes())?;
+    /// Returns the fingerprint of [`TrackingMessage::schema`].
+    ///
+    /// Trackers and clients can only communicate if their fingerprints match.
+    pub fn fingerprint() -> u64 {
+        *FINGERPRINT.get_or_init(|| Self::schema().fingerprint())
+    }
+}
 
-        bincode::serialize_into(&mut writer, self).map_err(convert_error)?;
+/// Non-wrapping timing information of a [`TrackingMessage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timing {
+    /// Timestamp in microseconds since an unspecified point in time.
+    ///
+    /// This uses the same clock as [`TrackingMessage::timestamp`], which holds the lower 32 bits of
+    /// this value.
+    pub timestamp: u64,
 
-        Ok(())
+    /// Offset from [`Timing::timestamp`] to wall-clock time, in microseconds.
+    ///
+    /// `timestamp + epoch_offset` is the wall-clock time the message was captured at, in
+    /// microseconds since the Unix epoch. Trackers should determine the offset once on startup, so
+    /// that adjustments to the system clock don't disturb the timeline.
+    pub epoch_offset: i64,
+}
+
+impl Timing {
+    /// Returns [`Timing::timestamp`] as a [`Duration`].
+    pub fn duration(&self) -> Duration {
+        Duration::from_micros(self.timestamp)
+    }
+
+    /// Returns the wall-clock time the message was captured at.
+    pub fn wall_clock(&self) -> SystemTime {
+        let micros = i128::from(self.timestamp) + i128::from(self.epoch_offset);
+        let offset = Duration::from_micros(micros.unsigned_abs().try_into().unwrap_or(u64::MAX));
+        if micros < 0 {
+            UNIX_EPOCH - offset
+        } else {
+            UNIX_EPOCH + offset
+        }
+    }
+}
+
+/// Converts the wrapping [`TrackingMessage::timestamp`]s of a message stream into a monotonic
+/// 64-bit timeline.
+///
+/// The first timestamp is returned unchanged. Every following timestamp is interpreted relative to
+/// the previous one: if it is less than half the `u32` range (about 35 minutes) ahead, it is
+/// assumed to follow the previous timestamp, even if the counter wrapped around in between.
+/// Anything else is a discontinuity (for example, because the tracker was restarted): the output
+/// stays the same, and the timeline continues from the new timestamp. The output never decreases.
+///
+/// Gaps of more than 35 minutes between messages can't be told apart from discontinuities by
+/// looking at the timestamps alone. [`TimestampUnwrapper::unwrap_after`] can handle them, by
+/// taking the locally measured time between messages into account.
+#[derive(Debug, Clone, Default)]
+pub struct TimestampUnwrapper {
+    last: Option<u32>,
+    current: u64,
+}
+
+impl TimestampUnwrapper {
+    const RANGE: u64 = 1 << 32;
+
+    /// Creates a new [`TimestampUnwrapper`].
+    pub const fn new() -> Self {
+        Self {
+            last: None,
+            current: 0,
+        }
+    }
+
+    /// Unwraps the next timestamp, returning the monotonic timestamp in microseconds.
+    pub fn unwrap(&mut self, timestamp: u32) -> u64 {
+        self.unwrap_after(timestamp, Duration::ZERO)
+    }
+
+    /// Unwraps the next timestamp, returning the monotonic timestamp as a [`Duration`].
+    pub fn unwrap_duration(&mut self, timestamp: u32) -> Duration {
+        Duration::from_micros(self.unwrap(timestamp))
     }
 
-    pub async fn async_read<R: AsyncRead + Unpin>(mut read: R) -> io::Result<Self> {
-        let mut fingerprint = [0; 8];
-        read.read_exact(&mut fingerprint).await?;
-        let fingerprint = u64::from_le_bytes(fingerprint);
+    /// Unwraps the next timestamp, given the time that has passed since the previous one was
+    /// received.
+    ///
+    /// `elapsed` only has to be accurate to within a few minutes. It is used to determine how often
+    /// the timestamp has wrapped around since the previous message, which makes it possible to
+    /// handle arbitrarily long gaps.
+    pub fn unwrap_after(&mut self, timestamp: u32, elapsed: Duration) -> u64 {
+        let Some(last) = self.last.replace(timestamp) else {
+            self.current = timestamp.into();
+            return self.current;
+        };
+
+        let delta = u64::from(timestamp.wrapping_sub(last));
+        let elapsed = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        if delta > (Self::RANGE / 2).saturating_add(elapsed) {
+            // Closer to a step backwards than to a step forwards.
+            return self.current;
+        }
+
+        let wraps = (elapsed.saturating_sub(delta) + Self::RANGE / 2) / Self::RANGE;
+        self.current = self
+            .current
+            .saturating_add(delta + wraps.saturating_mul(Self::RANGE));
+        self.current
+    }
+
+    /// Resets the [`TimestampUnwrapper`] to its initial state.
+    ///
+    /// This should be called when connecting to a different tracker.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Limits applied when decoding [`TrackingMessage`] frames received from a peer.
+///
+/// Frames are prefixed with their length, which must not be trusted blindly: without a limit, a
+/// misbehaving peer could make the receiver allocate up to 4 GiB per frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    max_frame_size: u32,
+    max_image_pixels: u32,
+}
+
+impl DecodeLimits {
+    /// The default maximum frame size (16 MiB).
+    ///
+    /// This leaves plenty of room for several faces with uncompressed eye textures.
+    pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+    /// The default maximum number of pixels in a compressed [`Image`] (1024x1024).
+    pub const DEFAULT_MAX_IMAGE_PIXELS: u32 = 1024 * 1024;
 
-        if Self::fingerprint() != fingerprint {
+    /// Creates the default set of limits.
+    pub const fn new() -> Self {
+        Self {
+            max_frame_size: Self::DEFAULT_MAX_FRAME_SIZE,
+            max_image_pixels: Self::DEFAULT_MAX_IMAGE_PIXELS,
+        }
+    }
+
+    /// Sets the maximum size of a frame's payload in bytes, excluding the length prefix.
+    pub const fn with_max_frame_size(mut self, bytes: u32) -> Self {
+        self.max_frame_size = bytes;
+        self
+    }
+
+    /// Returns the maximum size of a frame's payload in bytes.
+    #[inline]
+    pub const fn max_frame_size(&self) -> u32 {
+        self.max_frame_size
+    }
+
+    /// Sets the maximum number of pixels a compressed [`Image`] may decode to.
+    ///
+    /// Compressed images can be much smaller than their decoded pixel data, so this is checked
+    /// independently of the frame size.
+    pub const fn with_max_image_pixels(mut self, pixels: u32) -> Self {
+        self.max_image_pixels = pixels;
+        self
+    }
+
+    /// Returns the maximum number of pixels a compressed [`Image`] may decode to.
+    #[inline]
+    pub const fn max_image_pixels(&self) -> u32 {
+        self.max_image_pixels
+    }
+
+    pub(crate) fn check_frame_size(&self, size: u32) -> io::Result<u32> {
+        if size > self.max_frame_size {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                "message fingerprint mismatch",
+                format!(
+                    "frame size of {size} bytes exceeds limit of {} bytes",
+                    self.max_frame_size
+                ),
             ));
         }
+        Ok(size)
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The serialization format of [`TrackingMessage`] frames.
+///
+/// Every frame consists of the payload length in bytes (as a little-endian `u32`), followed by the
+/// [`TrackingMessage`] serialized with the codec. [`Codec::Bincode`] is always available and used
+/// by default. The others are self-describing formats meant for consumers not written in Rust, and
+/// have to be enabled via the cargo feature of the same name (`json`, `cbor` and `msgpack`).
+///
+/// Like [`ImageEncoding`]s, clients announce the codecs they would like to receive via
+/// [`Hello::capabilities`] (see [`Codec::capability`]), in order of preference.
+///
+/// [`Hello::capabilities`]: crate::handshake::Hello::capabilities
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// [bincode](https://docs.rs/bincode/1) with fixed-size little-endian integers.
+    Bincode,
+    /// JSON, with structs encoded as objects.
+    ///
+    /// JSON cannot represent NaN or infinite floats. Encoding a message that contains any fails
+    /// with [`io::ErrorKind::InvalidInput`].
+    Json,
+    /// [CBOR](https://cbor.io/), with structs encoded as maps.
+    Cbor,
+    /// [MessagePack](https://msgpack.org/), with structs encoded as maps.
+    MessagePack,
+}
+
+impl Codec {
+    /// All codecs, supported or not.
+    pub const ALL: [Self; 4] = [Self::Bincode, Self::Json, Self::Cbor, Self::MessagePack];
+
+    /// Returns the [`Hello::capabilities`] entry announcing support for this codec.
+    ///
+    /// [`Hello::capabilities`]: crate::handshake::Hello::capabilities
+    pub fn capability(self) -> &'static str {
+        match self {
+            Self::Bincode => "codec/bincode",
+            Self::Json => "codec/json",
+            Self::Cbor => "codec/cbor",
+            Self::MessagePack => "codec/msgpack",
+        }
+    }
+
+    /// Looks up the codec announced by a [`Hello::capabilities`] entry.
+    ///
+    /// [`Hello::capabilities`]: crate::handshake::Hello::capabilities
+    pub fn from_capability(capability: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|codec| codec.capability() == capability)
+    }
+
+    /// Returns whether this build of `providence-io` can encode and decode this codec.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Self::Bincode => true,
+            Self::Json => cfg!(feature = "json"),
+            Self::Cbor => cfg!(feature = "cbor"),
+            Self::MessagePack => cfg!(feature = "msgpack"),
+        }
+    }
 
+    /// Serializes a [`TrackingMessage`], returning the frame payload.
+    pub fn encode(self, message: &TrackingMessage) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Bincode => bincode::serialize(message).map_err(convert_error),
+            #[cfg(feature = "json")]
+            Self::Json => {
+                message.check_finite()?;
+                serde_json::to_vec(message).map_err(io::Error::from)
+            }
+            #[cfg(feature = "cbor")]
+            Self::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(message, &mut buf).map_err(|e| match e {
+                    ciborium::ser::Error::Io(e) => e,
+                    e => io::Error::new(io::ErrorKind::InvalidInput, e.to_string()),
+                })?;
+                Ok(buf)
+            }
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => rmp_serde::to_vec_named(message)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)),
+            #[allow(unreachable_patterns)]
+            codec => Err(codec.unsupported()),
+        }
+    }
+
+    /// Deserializes a frame payload, which must contain exactly one [`TrackingMessage`].
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the payload is malformed.
+    pub fn decode(self, payload: &[u8]) -> io::Result<TrackingMessage> {
+        match self {
+            Self::Bincode => {
+                // Same encoding as `bincode::serialize`, but bounded. `bincode` only enforces the
+                // limit when deserializing from a reader, and only rejects trailing bytes when
+                // deserializing from a slice, so the latter is checked manually.
+                let mut rest = payload;
+                let message = bincode::DefaultOptions::new()
+                    .with_fixint_encoding()
+                    .with_limit(payload.len() as u64)
+                    .deserialize_from(&mut rest)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                expect_end(rest)?;
+                Ok(message)
+            }
+            #[cfg(feature = "json")]
+            Self::Json => serde_json::from_slice(payload)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            #[cfg(feature = "cbor")]
+            Self::Cbor => {
+                let mut rest = payload;
+                let message = ciborium::from_reader(&mut rest)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                expect_end(rest)?;
+                Ok(message)
+            }
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => {
+                let mut rest = payload;
+                let message = rmp_serde::from_read(&mut rest)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                expect_end(rest)?;
+                Ok(message)
+            }
+            #[allow(unreachable_patterns)]
+            codec => Err(codec.unsupported()),
+        }
+    }
+
+    /// Reads a length-prefixed [`TrackingMessage`] frame, enforcing the given [`DecodeLimits`].
+    ///
+    /// # Errors
+    ///
+    /// - [`io::ErrorKind::UnexpectedEof`] if the stream ends before the frame is complete.
+    /// - [`io::ErrorKind::InvalidData`] if the length prefix exceeds the limits, or if the payload
+    ///   is not a valid [`TrackingMessage`] of exactly that length.
+    /// - [`io::ErrorKind::Unsupported`] if the codec is not supported by this build.
+    /// - Any other error returned by the underlying reader.
+    pub fn read<R: BufRead>(
+        self,
+        mut read: R,
+        limits: &DecodeLimits,
+    ) -> io::Result<TrackingMessage> {
         let mut size = [0; 4];
-        read.read_exact(&mut size).await?;
-        let size = u32::from_le_bytes(size);
+        read.read_exact(&mut size)?;
+        let size = limits.check_frame_size(u32::from_le_bytes(size))?;
 
-        let mut buf = vec![0; size as usize];
-        read.read_exact(&mut buf).await?;
-        let val = bincode::deserialize_from(&*buf).map_err(convert_error)?;
+        // Grow the buffer as data arrives instead of trusting the length prefix.
+        let mut buf = Vec::new();
+        read.take(size.into()).read_to_end(&mut buf)?;
+        self.decode_frame(&buf, size)
+    }
 
-        Ok(val)
+    /// Asynchronously reads a length-prefixed [`TrackingMessage`] frame, enforcing the given
+    /// [`DecodeLimits`].
+    ///
+    /// Fails in the same way as [`Codec::read`].
+    pub async fn async_read<R: AsyncRead + Unpin>(
+        self,
+        mut read: R,
+        limits: &DecodeLimits,
+    ) -> io::Result<TrackingMessage> {
+        let mut size = [0; 4];
+        read.read_exact(&mut size).await?;
+        let size = limits.check_frame_size(u32::from_le_bytes(size))?;
+
+        let mut buf = Vec::new();
+        (&mut read).take(size.into()).read_to_end(&mut buf).await?;
+        self.decode_frame(&buf, size)
     }
 
-    pub async fn async_write<W: AsyncWrite + Unpin>(&self, mut writer: W) -> io::Result<()> {
-        writer.write_all(&Self::fingerprint().to_le_bytes()).await?;
+    /// Writes a length-prefixed [`TrackingMessage`] frame.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the message is too large to be framed, or
+    /// cannot be represented by the codec.
+    pub fn write<W: Write>(self, message: &TrackingMessage, mut writer: W) -> io::Result<()> {
+        let payload = self.encode(message)?;
+        writer.write_all(&frame_size(&payload)?.to_le_bytes())?;
+        writer.write_all(&payload)
+    }
 
-        let size = bincode::serialized_size(self).map_err(convert_error)?;
+    /// Asynchronously writes a length-prefixed [`TrackingMessage`] frame.
+    ///
+    /// Fails in the same way as [`Codec::write`].
+    pub async fn async_write<W: AsyncWrite + Unpin>(
+        self,
+        message: &TrackingMessage,
+        mut writer: W,
+    ) -> io::Result<()> {
+        let payload = self.encode(message)?;
         writer
-            .write_all(&u32::try_from(size).unwrap().to_le_bytes())
+            .write_all(&frame_size(&payload)?.to_le_bytes())
             .await?;
+        writer.write_all(&payload).await
+    }
 
-        let buf = bincode::serialize(self).map_err(convert_error)?;
-        writer.write_all(&buf).await?;
-        Ok(())
+    /// Decodes a frame payload that should be `size` bytes long.
+    fn decode_frame(self, buf: &[u8], size: u32) -> io::Result<TrackingMessage> {
+        if buf.len() != size as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.decode(buf)
+    }
+
+    #[allow(dead_code)] // unused if all codecs are enabled
+    fn unsupported(self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("codec {self:?} is not supported by this build"),
+        )
     }
+}
 
-    fn fingerprint() -> u64 {
-        *FINGERPRINT.get_or_init(|| serde_fingerprint::<Self>())
+fn frame_size(payload: &[u8]) -> io::Result<u32> {
+    u32::try_from(payload.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "message of {} bytes is too large to be framed",
+                payload.len()
+            ),
+        )
+    })
+}
+
+fn expect_end(rest: &[u8]) -> io::Result<()> {
+    if !rest.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} trailing bytes after message", rest.len()),
+        ));
     }
+    Ok(())
 }
 
-fn convert_error(e: bincode::Error) -> io::Error {
+#[allow(clippy::boxed_local)] // `bincode::Error` is a `Box`
+pub(crate) fn convert_error(e: bincode::Error) -> io::Error {
     match *e {
         bincode::ErrorKind::Io(io) => io,
         kind => io::Error::new(io::ErrorKind::InvalidData, kind),
@@ -133,6 +632,19 @@ pub struct FaceData {
     pub right_eye: Option<Eye>,
 }
 
+impl FaceData {
+    /// Returns [`FaceData::head_rotation`] as Euler angles in radians.
+    ///
+    /// The angles are the rotations around the X, Y and Z axes, applied in that order.
+    pub fn head_rotation_euler(&self) -> [f32; 3] {
+        let [x, y, z, w] = self.head_rotation;
+        let about_x = f32::atan2(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y));
+        let about_y = f32::asin((2.0 * (w * y - z * x)).clamp(-1.0, 1.0));
+        let about_z = f32::atan2(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z));
+        [about_x, about_y, about_z]
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PersistentId {
     /// Person has not yet been identified. The system is either waiting for the face to be in a
@@ -172,5 +684,545 @@ pub struct Vertex {
 pub struct Image {
     pub width: u32,
     pub height: u32,
-    pub data: Vec<u8>, // RGBA
+    /// How [`Image::data`] is encoded.
+    ///
+    /// [`Subscriber`][crate::net::Subscriber]s always decode images to [`ImageEncoding::Rgba`]
+    /// before handing out messages.
+    pub encoding: ImageEncoding,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// Creates an [`Image`] from uncompressed 8-bit RGBA pixel data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` does not contain exactly `width * height` RGBA pixels.
+    pub fn from_rgba(width: u32, height: u32, data: Vec<u8>) -> Self {
+        assert_eq!(
+            data.len() as u64,
+            u64::from(width) * u64::from(height) * 4,
+            "RGBA data does not match image size {width}x{height}",
+        );
+        Self {
+            width,
+            height,
+            encoding: ImageEncoding::Rgba,
+            data,
+        }
+    }
+
+    /// Returns a copy of this [`Image`] encoded with `encoding`.
+    ///
+    /// Fails with [`io::ErrorKind::Unsupported`] if this build of `providence-io` does not support
+    /// the requested encoding (see [`ImageEncoding::is_supported`]).
+    ///
+    /// Empty images (like stripped eye textures) are returned unchanged, since not every encoding
+    /// can represent them.
+    pub fn encode(&self, encoding: ImageEncoding) -> io::Result<Self> {
+        if self.encoding == encoding || self.width == 0 || self.height == 0 {
+            return Ok(self.clone());
+        }
+        let rgba = self.to_rgba()?;
+        Ok(Self {
+            width: self.width,
+            height: self.height,
+            encoding,
+            data: texture::encode(&rgba, encoding)?,
+        })
+    }
+
+    /// Decodes this [`Image`] to [`ImageEncoding::Rgba`].
+    ///
+    /// Compressed images are subject to the default [`DecodeLimits`].
+    pub fn to_rgba(&self) -> io::Result<Self> {
+        self.to_rgba_with_limits(&DecodeLimits::default())
+    }
+
+    /// Decodes this [`Image`] to [`ImageEncoding::Rgba`], enforcing the given [`DecodeLimits`].
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the image data is malformed or does not match
+    /// the image's size, and with [`io::ErrorKind::Unsupported`] if the encoding is not supported
+    /// by this build of `providence-io`.
+    pub fn to_rgba_with_limits(&self, limits: &DecodeLimits) -> io::Result<Self> {
+        if self.encoding == ImageEncoding::Rgba {
+            // Still validate the size, consumers rely on it.
+            texture::check_size(self, 4)?;
+            return Ok(self.clone());
+        }
+        if u64::from(self.width) * u64::from(self.height) > u64::from(limits.max_image_pixels) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "image size {}x{} exceeds limit of {} pixels",
+                    self.width, self.height, limits.max_image_pixels,
+                ),
+            ));
+        }
+        Ok(Self {
+            width: self.width,
+            height: self.height,
+            encoding: ImageEncoding::Rgba,
+            data: texture::decode(self)?,
+        })
+    }
+}
+
+/// The encoding of an [`Image`]'s pixel data.
+///
+/// Clients announce the encodings they would like to receive via [`Hello::capabilities`] (see
+/// [`ImageEncoding::capability`]), in order of preference. The tracker uses the first one it
+/// supports, or [`ImageEncoding::Rgba`] if there is none.
+///
+/// [`Hello::capabilities`]: crate::handshake::Hello::capabilities
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ImageEncoding {
+    /// Uncompressed 8-bit RGBA pixels in row-major order.
+    Rgba,
+    /// Uncompressed 8-bit grayscale pixels in row-major order.
+    ///
+    /// Encoding an image as grayscale discards its color and alpha channels.
+    Gray,
+    /// A lossless [QOI](https://qoiformat.org/) image.
+    Qoi,
+    /// A lossless PNG image. Requires the `png` feature.
+    Png,
+    /// A lossy JPEG image. Discards the alpha channel. Requires the `jpeg` feature.
+    Jpeg,
+}
+
+impl ImageEncoding {
+    /// All image encodings, supported or not.
+    pub const ALL: [Self; 5] = [Self::Rgba, Self::Gray, Self::Qoi, Self::Png, Self::Jpeg];
+
+    /// Returns the [`Hello::capabilities`] entry announcing support for this encoding.
+    ///
+    /// [`Hello::capabilities`]: crate::handshake::Hello::capabilities
+    pub fn capability(self) -> &'static str {
+        match self {
+            Self::Rgba => "image/rgba",
+            Self::Gray => "image/gray",
+            Self::Qoi => "image/qoi",
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+        }
+    }
+
+    /// Looks up the encoding announced by a [`Hello::capabilities`] entry.
+    ///
+    /// [`Hello::capabilities`]: crate::handshake::Hello::capabilities
+    pub fn from_capability(capability: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|encoding| encoding.capability() == capability)
+    }
+
+    /// Returns whether this build of `providence-io` can encode and decode this encoding.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Self::Rgba | Self::Gray | Self::Qoi => true,
+            Self::Png => cfg!(feature = "png"),
+            Self::Jpeg => cfg!(feature = "jpeg"),
+        }
+    }
+
+    /// Returns whether encoding an image with this encoding loses information.
+    pub fn is_lossy(self) -> bool {
+        matches!(self, Self::Gray | Self::Jpeg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+    use proptest::{collection::vec, option, prelude::*};
+
+    use super::*;
+
+    fn msg() -> TrackingMessage {
+        TrackingMessage {
+            timestamp: 42,
+            timing: None,
+            faces: vec![FaceData {
+                ephemeral_id: 0,
+                persistent_id: PersistentId::Available("someone".into()),
+                head_position: [0.5, 0.5],
+                head_rotation: [0.0, 0.0, 0.0, 1.0],
+                left_eye: None,
+                right_eye: None,
+            }],
+        }
+    }
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut buf = (payload.len() as u32).to_le_bytes().to_vec();
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    #[test]
+    fn euler() {
+        let mut face = msg().faces.remove(0);
+        assert_eq!(face.head_rotation_euler(), [0.0; 3]);
+
+        // 90° around Y.
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        face.head_rotation = [0.0, half, 0.0, half];
+        let [x, y, z] = face.head_rotation_euler();
+        assert!(x.abs() < 1e-3 && z.abs() < 1e-3, "{x} {z}");
+        assert!((y - std::f32::consts::FRAC_PI_2).abs() < 1e-3, "{y}");
+
+        // 30° around X, then 45° around Z.
+        let (x, z) = (30f32.to_radians() / 2.0, 45f32.to_radians() / 2.0);
+        let (qx, qz) = ([x.sin(), 0.0, 0.0, x.cos()], [0.0, 0.0, z.sin(), z.cos()]);
+        // qz * qx
+        face.head_rotation = [qz[3] * qx[0], qz[2] * qx[0], qz[2] * qx[3], qz[3] * qx[3]];
+        let angles = face.head_rotation_euler().map(f32::to_degrees);
+        for (angle, expected) in angles.into_iter().zip([30.0, 0.0, 45.0]) {
+            assert!((angle - expected).abs() < 1e-3, "{angles:?}");
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut buf = Vec::new();
+        msg().write(&mut buf).unwrap();
+        let sync = TrackingMessage::read(&*buf).unwrap();
+        let async_ = block_on(TrackingMessage::async_read(&*buf)).unwrap();
+        assert_eq!(sync.timestamp, 42);
+        assert_eq!(async_.timestamp, 42);
+    }
+
+    #[test]
+    fn frame_size_limit() {
+        let mut buf = Vec::new();
+        msg().write(&mut buf).unwrap();
+        let size = buf.len() as u32 - 4;
+
+        let exact = DecodeLimits::new().with_max_frame_size(size);
+        TrackingMessage::read_with_limits(&*buf, &exact).unwrap();
+        block_on(TrackingMessage::async_read_with_limits(&*buf, &exact)).unwrap();
+
+        let tight = DecodeLimits::new().with_max_frame_size(size - 1);
+        let err = TrackingMessage::read_with_limits(&*buf, &tight).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = block_on(TrackingMessage::async_read_with_limits(&*buf, &tight)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn huge_length_prefix() {
+        // Must be rejected up front instead of attempting a 4 GiB allocation.
+        let buf = u32::MAX.to_le_bytes();
+        let err = TrackingMessage::read(&buf[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = block_on(TrackingMessage::async_read(&buf[..])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut payload = bincode::serialize(&msg()).unwrap();
+        payload.push(0);
+        let buf = frame(&payload);
+        let err = TrackingMessage::read(&*buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = block_on(TrackingMessage::async_read(&*buf)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn codec_trailing_bytes() {
+        for codec in Codec::ALL.into_iter().filter(|c| c.is_supported()) {
+            let mut payload = codec.encode(&msg()).unwrap();
+            codec.decode(&payload).unwrap();
+            payload.push(0);
+            let err = codec.decode(&payload).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{codec:?}");
+        }
+    }
+
+    #[test]
+    fn unwrap_timestamps() {
+        let mut u = TimestampUnwrapper::new();
+        assert_eq!(u.unwrap(100), 100);
+        assert_eq!(u.unwrap(100), 100);
+        assert_eq!(u.unwrap(250), 250);
+        assert_eq!(u.unwrap(1 << 31), 1 << 31);
+        assert_eq!(u.unwrap(u32::MAX - 10), u64::from(u32::MAX) - 10);
+        // Wraparound
+        assert_eq!(u.unwrap(5), (1 << 32) + 5);
+        assert_eq!(u.unwrap(1 << 31), (1 << 32) + (1 << 31));
+        assert_eq!(u.unwrap(u32::MAX), (2 << 32) - 1);
+        assert_eq!(u.unwrap(0), 2 << 32);
+        assert_eq!(
+            u.unwrap_duration(1_000_000),
+            Duration::from_micros((2 << 32) + 1_000_000)
+        );
+
+        u.reset();
+        assert_eq!(u.unwrap(1000), 1000);
+    }
+
+    #[test]
+    fn unwrap_discontinuity() {
+        let mut u = TimestampUnwrapper::new();
+        assert_eq!(u.unwrap(1_000_000), 1_000_000);
+        // Tracker restarted: output holds, then continues monotonically.
+        assert_eq!(u.unwrap(10), 1_000_000);
+        assert_eq!(u.unwrap(20), 1_000_010);
+        // Backwards across the wrap point.
+        assert_eq!(u.unwrap(u32::MAX - 100), 1_000_010);
+        assert_eq!(u.unwrap(u32::MAX), 1_000_110);
+    }
+
+    #[test]
+    fn unwrap_gaps() {
+        const HOUR: Duration = Duration::from_secs(60 * 60);
+        const HOUR_MICROS: u64 = 60 * 60 * 1_000_000;
+
+        // Without knowing how much time has passed, a gap of 40 minutes looks like a step back.
+        let mut u = TimestampUnwrapper::new();
+        u.unwrap(0);
+        assert_eq!(u.unwrap((HOUR_MICROS * 2 / 3) as u32), 0);
+
+        // Gaps are handled correctly if the elapsed time is known, even if it's imprecise.
+        for gap in [HOUR * 2 / 3, HOUR, HOUR * 2, HOUR * 10, HOUR * 1000] {
+            let start = u64::from(u32::MAX) - 1;
+            let end = start + gap.as_micros() as u64;
+            let jitter = Duration::from_secs(120);
+            for elapsed in [gap, gap + jitter, gap - jitter] {
+                let mut u = TimestampUnwrapper::new();
+                u.unwrap(start as u32);
+                assert_eq!(
+                    u.unwrap_after(end as u32, elapsed),
+                    end,
+                    "gap={gap:?}, elapsed={elapsed:?}",
+                );
+            }
+        }
+
+        // A restart is still detected if the elapsed time is small.
+        let mut u = TimestampUnwrapper::new();
+        u.unwrap(1 << 30);
+        assert_eq!(u.unwrap_after(5, Duration::from_millis(33)), 1 << 30);
+    }
+
+    #[test]
+    fn timing() {
+        let mut msg = msg();
+        let timing = Timing {
+            timestamp: (7 << 32) + 12,
+            epoch_offset: 1_700_000_000_000_000 - (7 << 32),
+        };
+        msg.set_timing(timing);
+        assert_eq!(msg.timestamp, 12);
+        assert_eq!(
+            timing.wall_clock(),
+            UNIX_EPOCH + Duration::from_micros(1_700_000_000_000_012)
+        );
+        assert_eq!(timing.duration(), Duration::from_micros((7 << 32) + 12));
+
+        let before_epoch = Timing {
+            timestamp: 5,
+            epoch_offset: -10,
+        };
+        assert_eq!(
+            before_epoch.wall_clock(),
+            UNIX_EPOCH - Duration::from_micros(5)
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let mut buf = Vec::new();
+        msg().write(&mut buf).unwrap();
+        buf.pop();
+        let err = TrackingMessage::read(&*buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = block_on(TrackingMessage::async_read(&*buf)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    fn arb_image() -> impl Strategy<Value = Image> {
+        (0..4u32, 0..4u32).prop_flat_map(|(width, height)| {
+            vec(any::<u8>(), (width * height * 4) as usize)
+                .prop_map(move |data| Image::from_rgba(width, height, data))
+        })
+    }
+
+    fn arb_eye() -> impl Strategy<Value = Eye> {
+        let vertex = (any::<[f32; 3]>(), any::<[f32; 2]>())
+            .prop_map(|(position, uv)| Vertex { position, uv });
+        (
+            arb_image(),
+            vec(vertex, 0..8),
+            vec(any::<u16>(), 0..16),
+            any::<[f32; 3]>(),
+            any::<f32>(),
+        )
+            .prop_map(
+                |(texture, vertices, indices, iris_center, iris_radius)| Eye {
+                    texture,
+                    mesh: Mesh { vertices, indices },
+                    iris_center,
+                    iris_radius,
+                },
+            )
+    }
+
+    fn arb_face() -> impl Strategy<Value = FaceData> {
+        let persistent_id = prop_oneof![
+            Just(PersistentId::InProgress),
+            Just(PersistentId::Unavailable),
+            Just(PersistentId::Unknown),
+            any::<String>().prop_map(PersistentId::Available),
+        ];
+        (
+            any::<u32>(),
+            persistent_id,
+            any::<[f32; 2]>(),
+            any::<[f32; 4]>(),
+            option::of(arb_eye()),
+            option::of(arb_eye()),
+        )
+            .prop_map(
+                |(
+                    ephemeral_id,
+                    persistent_id,
+                    head_position,
+                    head_rotation,
+                    left_eye,
+                    right_eye,
+                )| {
+                    FaceData {
+                        ephemeral_id,
+                        persistent_id,
+                        head_position,
+                        head_rotation,
+                        left_eye,
+                        right_eye,
+                    }
+                },
+            )
+    }
+
+    fn arb_message() -> impl Strategy<Value = TrackingMessage> {
+        let timing = option::of((any::<u64>(), any::<i64>()).prop_map(
+            |(timestamp, epoch_offset)| Timing {
+                timestamp,
+                epoch_offset,
+            },
+        ));
+        (any::<u32>(), timing, vec(arb_face(), 0..4)).prop_map(|(timestamp, timing, faces)| {
+            TrackingMessage {
+                timestamp,
+                timing,
+                faces,
+            }
+        })
+    }
+
+    // Messages contain floats (which may be NaN), so they are compared by their encoding.
+    fn encode(msg: &TrackingMessage) -> Vec<u8> {
+        let mut buf = Vec::new();
+        msg.write(&mut buf).unwrap();
+        buf
+    }
+
+    fn async_encode(msg: &TrackingMessage) -> Vec<u8> {
+        let mut buf = Vec::new();
+        block_on(msg.async_write(&mut buf)).unwrap();
+        buf
+    }
+
+    proptest! {
+        #[test]
+        fn sync_write_async_read(msg in arb_message()) {
+            let buf = encode(&msg);
+            prop_assert_eq!(&buf, &async_encode(&msg));
+            let decoded = block_on(TrackingMessage::async_read(&*buf)).unwrap();
+            prop_assert_eq!(encode(&decoded), buf);
+        }
+
+        #[test]
+        fn async_write_sync_read(msg in arb_message()) {
+            let buf = async_encode(&msg);
+            let decoded = TrackingMessage::read(&*buf).unwrap();
+            prop_assert_eq!(async_encode(&decoded), buf);
+        }
+
+        #[test]
+        fn truncated_frames(msg in arb_message(), cut in any::<prop::sample::Index>()) {
+            let buf = encode(&msg);
+            let buf = &buf[..cut.index(buf.len())];
+            let err = TrackingMessage::read(buf).unwrap_err();
+            prop_assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+            let err = block_on(TrackingMessage::async_read(buf)).unwrap_err();
+            prop_assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+
+        #[test]
+        fn hostile_input(buf in vec(any::<u8>(), 0..256)) {
+            let sync = TrackingMessage::read(&*buf);
+            let async_ = block_on(TrackingMessage::async_read(&*buf));
+            match (sync, async_) {
+                (Ok(a), Ok(b)) => prop_assert_eq!(encode(&a), encode(&b)),
+                (Err(a), Err(b)) => {
+                    prop_assert_eq!(a.kind(), b.kind());
+                    prop_assert!(matches!(
+                        a.kind(),
+                        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+                    ));
+                }
+                (a, b) => prop_assert!(false, "decoders disagree: {:?} vs {:?}", a, b),
+            }
+        }
+
+        #[test]
+        fn codec_roundtrip(msg in arb_message()) {
+            let expected = encode(&msg);
+            for codec in Codec::ALL.into_iter().filter(|c| c.is_supported()) {
+                let mut buf = Vec::new();
+                match codec.write(&msg, &mut buf) {
+                    // NaN and infinities can't be represented in JSON.
+                    Err(e) if codec == Codec::Json => {
+                        prop_assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+                        continue;
+                    }
+                    res => res.unwrap(),
+                }
+                let decoded = codec.read(&*buf, &DecodeLimits::default()).unwrap();
+                prop_assert_eq!(&encode(&decoded), &expected, "{:?}", codec);
+                let decoded = block_on(codec.async_read(&*buf, &DecodeLimits::default())).unwrap();
+                prop_assert_eq!(&encode(&decoded), &expected, "{:?}", codec);
+            }
+        }
+
+        #[test]
+        fn hostile_payloads(buf in vec(any::<u8>(), 0..256)) {
+            for codec in Codec::ALL.into_iter().filter(|c| c.is_supported()) {
+                if let Err(e) = codec.decode(&buf) {
+                    prop_assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{:?}: {}", codec, e);
+                }
+            }
+        }
+
+        #[test]
+        fn corrupted_frames(
+            msg in arb_message(),
+            at in any::<prop::sample::Index>(),
+            byte in any::<u8>(),
+        ) {
+            let mut buf = encode(&msg);
+            let at = at.index(buf.len());
+            buf[at] = byte;
+            if let Err(e) = TrackingMessage::read(&*buf) {
+                prop_assert!(matches!(
+                    e.kind(),
+                    io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+                ));
+            }
+        }
+    }
 }
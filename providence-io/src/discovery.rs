@@ -0,0 +1,499 @@
+//! Discovery of the trackers on the local network.
+//!
+//! Trackers advertise themselves via mDNS (see [`PublisherBuilder`]). Discovery runs over IPv4 and
+//! IPv6 at the same time, and reports every tracker once, as a [`DiscoveredTracker`]:
+//!
+//! - [`discover_blocking`] and [`discover_async`] invoke a callback with every tracker as soon as
+//!   it is found.
+//! - [`list_blocking`] and [`list_async`] collect the trackers found within a timeout.
+//! - [`find_blocking`] and [`find_async`] wait for the tracker with a specific name to appear.
+//!
+//! Besides its name and addresses, a [`DiscoveredTracker`] carries the metadata the tracker
+//! advertises (like its protocol version, camera, and whether it sends eye textures), so clients
+//! can filter trackers before connecting to them. To connect to a discovered tracker, pass it to
+//! [`Subscriber::connect_to_tracker`] along with the [`SubscriberOptions`] to use. That way, the
+//! subscriber can look the tracker up again if it has to reconnect (see
+//! [`SubscriberOptions::with_reconnect`]).
+//!
+//! [`PublisherBuilder`]: crate::net::PublisherBuilder
+//! [`Subscriber::connect_to_tracker`]: crate::net::Subscriber::connect_to_tracker
+//! [`SubscriberOptions`]: crate::net::SubscriberOptions
+//! [`SubscriberOptions::with_reconnect`]: crate::net::SubscriberOptions::with_reconnect
+
+use std::{
+    collections::HashSet,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use async_io::{Async, Timer};
+use futures_lite::future;
+use tracing::{debug, info};
+use uwuhi_async::{
+    name::{DomainName, Label},
+    packet::{records::Record, QType},
+    resolver::AsyncResolver,
+    service::{
+        discovery::{decode_answer, encode_query, AsyncDiscoverer},
+        Service, ServiceInstance, ServiceTransport,
+    },
+    MDNS_BUFFER_SIZE,
+};
+
+use crate::{
+    data::TrackingMessage,
+    handshake::PROTOCOL_VERSION,
+    mdns::{self, Family, Metadata, SERVICE},
+    net::CameraInfo,
+};
+
+/// How long to wait for a discovered tracker to answer the queries for its details.
+const DETAILS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long to wait for an answer before repeating a query for the details of a tracker.
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// A tracker that was discovered on the local network.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredTracker {
+    name: String,
+    host: String,
+    port: u16,
+    addrs: Vec<SocketAddr>,
+    metadata: Metadata,
+}
+
+impl DiscoveredTracker {
+    /// Returns the instance name the tracker advertises itself with.
+    ///
+    /// This is the same name the tracker announces in its [`Hello`](crate::handshake::Hello)
+    /// once connected.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the host name of the tracker, in the `.local` domain.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Returns the TCP port the tracker accepts connections on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns the addresses the tracker can be reached on, in order of preference.
+    ///
+    /// IPv4 addresses come first, since they work on more networks.
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// Returns the human-readable name the tracker advertises, if any.
+    ///
+    /// See [`PublisherBuilder::with_friendly_name`](crate::net::PublisherBuilder::with_friendly_name).
+    pub fn friendly_name(&self) -> Option<&str> {
+        self.metadata.friendly_name.as_deref()
+    }
+
+    /// Returns the protocol version the tracker speaks, if it advertises one.
+    pub fn protocol_version(&self) -> Option<u32> {
+        self.metadata.version
+    }
+
+    /// Returns the [`TrackingMessage::fingerprint`] of the tracker, if it advertises one.
+    pub fn fingerprint(&self) -> Option<u64> {
+        self.metadata.fingerprint
+    }
+
+    /// Returns a [`bool`] indicating whether a connection to the tracker would pass the handshake.
+    ///
+    /// This is `false` if the tracker advertises a different protocol version or fingerprint than
+    /// this library's. Trackers that don't advertise them are assumed to be compatible.
+    pub fn is_compatible(&self) -> bool {
+        let metadata = &self.metadata;
+        metadata.version.is_none_or(|v| v == PROTOCOL_VERSION)
+            && metadata
+                .fingerprint
+                .is_none_or(|fp| fp == TrackingMessage::fingerprint())
+    }
+
+    /// Returns the camera the tracker captures from, if it advertises one.
+    pub fn camera(&self) -> Option<CameraInfo> {
+        self.metadata.camera
+    }
+
+    /// Returns the capabilities the tracker advertises.
+    ///
+    /// These are the same as the [`Hello::capabilities`](crate::handshake::Hello::capabilities)
+    /// it sends when a client connects.
+    pub fn capabilities(&self) -> &[String] {
+        &self.metadata.capabilities
+    }
+
+    /// Returns a [`bool`] indicating whether the tracker advertises support for `capability`.
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.metadata.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Returns a [`bool`] indicating whether the tracker sends eye textures.
+    ///
+    /// Trackers that don't advertise any metadata report `false`.
+    pub fn has_eye_textures(&self) -> bool {
+        self.metadata.eye_textures
+    }
+}
+
+/// Discovers trackers, and invokes `callback` with every tracker as soon as it is found.
+///
+/// Discovery runs until `callback` returns [`ControlFlow::Break`], or until `timeout` has elapsed.
+/// Without a `timeout`, it only stops when `callback` says so.
+///
+/// # Errors
+///
+/// Fails if mDNS can't be used over either IPv4 or IPv6. Finding no trackers is not an error.
+pub fn discover_blocking<C>(timeout: Option<Duration>, callback: C) -> io::Result<()>
+where
+    C: FnMut(&DiscoveredTracker) -> ControlFlow<()>,
+{
+    future::block_on(discover_async(timeout, callback))
+}
+
+/// Discovers trackers, and invokes `callback` with every tracker as soon as it is found.
+///
+/// This is the asynchronous version of [`discover_blocking`].
+pub async fn discover_async<C>(timeout: Option<Duration>, callback: C) -> io::Result<()>
+where
+    C: FnMut(&DiscoveredTracker) -> ControlFlow<()>,
+{
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+    let seen = Mutex::new(HashSet::new());
+    let callback = Mutex::new(callback);
+
+    // Stop as soon as the callback says so. Otherwise, wait for both families to finish, and only
+    // fail if both failed.
+    let finished = AtomicUsize::new(0);
+    let succeeded = AtomicBool::new(false);
+    let attempt = |family| {
+        let (seen, callback) = (&seen, &callback);
+        let (finished, succeeded) = (&finished, &succeeded);
+        async move {
+            let error = match discover_family(family, deadline, seen, callback).await {
+                Ok(ControlFlow::Break(())) => return Ok(()),
+                Ok(ControlFlow::Continue(())) => {
+                    succeeded.store(true, Ordering::Relaxed);
+                    None
+                }
+                Err(e) => {
+                    debug!("mDNS discovery over {family:?} failed: {e}");
+                    Some(e)
+                }
+            };
+            if finished.fetch_add(1, Ordering::Relaxed) + 1 < Family::ALL.len() {
+                future::pending::<()>().await;
+            }
+            match error {
+                Some(e) if !succeeded.load(Ordering::Relaxed) => Err(e),
+                _ => Ok(()),
+            }
+        }
+    };
+    future::or(attempt(Family::V4), attempt(Family::V6)).await
+}
+
+/// Collects the trackers that are found within `timeout`.
+pub fn list_blocking(timeout: Duration) -> io::Result<Vec<DiscoveredTracker>> {
+    future::block_on(list_async(timeout))
+}
+
+/// Collects the trackers that are found within `timeout`.
+///
+/// This is the asynchronous version of [`list_blocking`].
+pub async fn list_async(timeout: Duration) -> io::Result<Vec<DiscoveredTracker>> {
+    let mut trackers = Vec::new();
+    discover_async(Some(timeout), |tracker| {
+        trackers.push(tracker.clone());
+        ControlFlow::Continue(())
+    })
+    .await?;
+    Ok(trackers)
+}
+
+/// Waits for the tracker called `name` to appear, and returns it.
+///
+/// Without a `timeout`, this waits until the tracker is found.
+///
+/// # Errors
+///
+/// Returns an error of kind [`io::ErrorKind::TimedOut`] if the tracker wasn't found within
+/// `timeout`.
+pub fn find_blocking(name: &str, timeout: Option<Duration>) -> io::Result<DiscoveredTracker> {
+    future::block_on(find_async(name, timeout))
+}
+
+/// Waits for the tracker called `name` to appear, and returns it.
+///
+/// This is the asynchronous version of [`find_blocking`].
+pub async fn find_async(name: &str, timeout: Option<Duration>) -> io::Result<DiscoveredTracker> {
+    let mut found = None;
+    discover_async(timeout, |tracker| {
+        if tracker.name() == name {
+            found = Some(tracker.clone());
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })
+    .await?;
+    found.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("timed out while waiting for tracker `{name}` to appear"),
+        )
+    })
+}
+
+/// Returns the first tracker that is found within `timeout`.
+pub(crate) async fn first(timeout: Option<Duration>) -> io::Result<DiscoveredTracker> {
+    let mut found = None;
+    discover_async(timeout, |tracker| {
+        found = Some(tracker.clone());
+        ControlFlow::Break(())
+    })
+    .await?;
+    found.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("timed out while discovering `{}` network service", SERVICE),
+        )
+    })
+}
+
+/// Runs discovery over one IP address family until `deadline`.
+///
+/// Trackers whose names are in `seen` were already reported, and are skipped.
+async fn discover_family<C>(
+    family: Family,
+    deadline: Option<Instant>,
+    seen: &Mutex<HashSet<String>>,
+    callback: &Mutex<C>,
+) -> io::Result<ControlFlow<()>>
+where
+    C: FnMut(&DiscoveredTracker) -> ControlFlow<()>,
+{
+    let service = Service::new(Label::new(SERVICE), ServiceTransport::TCP);
+    let mut discoverer = AsyncDiscoverer::new(family.group(), local_domain()).await?;
+
+    loop {
+        // The discoverer only reports instances once per query, so every new instance ends the
+        // query, and a new one is sent after it has been looked up.
+        let timeout = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(timeout) if !timeout.is_zero() => timeout,
+                _ => return Ok(ControlFlow::Continue(())),
+            },
+            None => Duration::MAX,
+        };
+        discoverer.set_discovery_timeout(timeout)?;
+        let mut instance = None;
+        discoverer
+            .discover_instances(&service, |new| {
+                let name = instance_name(new);
+                if seen.lock().unwrap().insert(name) {
+                    instance = Some(new.clone());
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })
+            .await?;
+        let Some(instance) = instance else {
+            return Ok(ControlFlow::Continue(()));
+        };
+
+        match details(family, &instance).await {
+            Ok(tracker) => {
+                info!(
+                    "discovered tracker `{}` on {}:{}",
+                    tracker.name, tracker.host, tracker.port,
+                );
+                if let ControlFlow::Break(()) = (callback.lock().unwrap())(&tracker) {
+                    return Ok(ControlFlow::Break(()));
+                }
+            }
+            Err(e) => {
+                // Give it another chance with the next query, or on the other family.
+                debug!(
+                    "failed to look up tracker `{}` over {family:?}: {e}",
+                    instance.instance_name(),
+                );
+                seen.lock().unwrap().remove(&instance_name(&instance));
+            }
+        }
+    }
+}
+
+/// Looks up the host, port, addresses and metadata of a discovered tracker instance.
+async fn details(family: Family, instance: &ServiceInstance) -> io::Result<DiscoveredTracker> {
+    let (host, port, metadata) = lookup_instance(family, instance).await?;
+
+    let mut resolver = AsyncResolver::new(family.group()).await?;
+    let ips = resolver.resolve_domain(&host).await?;
+    let mut addrs = addrs(ips, port);
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("`{host}` has no usable addresses"),
+        ));
+    }
+    addrs.sort_by_key(|addr| addr.is_ipv6());
+
+    Ok(DiscoveredTracker {
+        name: instance_name(instance),
+        host: host.to_string(),
+        port,
+        addrs,
+        metadata,
+    })
+}
+
+/// Queries the SRV and TXT records of a tracker instance.
+///
+/// `uwuhi`'s `load_instance_details` stops reading the answer at the SRV record, which drops the
+/// TXT record when it comes second (as it does with our advertiser), so this waits for both.
+async fn lookup_instance(
+    family: Family,
+    instance: &ServiceInstance,
+) -> io::Result<(DomainName, u16, Metadata)> {
+    let mut domain = DomainName::from_iter([
+        instance.instance_name(),
+        instance.service_name(),
+        &instance.service_transport().to_label(),
+    ]);
+    domain.extend(local_domain().labels());
+    let mut buf = [0; MDNS_BUFFER_SIZE];
+    let query = encode_query(&mut buf, &domain, &[QType::SRV, QType::TXT]).to_vec();
+
+    let socket = match family {
+        Family::V4 => Async::<UdpSocket>::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+        Family::V6 => Async::<UdpSocket>::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+    };
+    let deadline = Instant::now() + DETAILS_TIMEOUT;
+    let (mut srv, mut txt) = (None, None);
+    'retransmit: while Instant::now() < deadline {
+        socket.send_to(&query, family.group()).await?;
+        let timeout = Instant::now() + RETRANSMIT_TIMEOUT;
+        loop {
+            let recv = async { Some(socket.recv_from(&mut buf).await) };
+            let timeout = async {
+                Timer::at(timeout.min(deadline)).await;
+                None
+            };
+            let Some(recv) = future::or(recv, timeout).await else {
+                continue 'retransmit;
+            };
+            let (len, addr) = recv?;
+            let res = decode_answer(&buf[..len], &mut |record| {
+                match record {
+                    Record::SRV(record) => srv = Some((record.target().clone(), record.port())),
+                    Record::TXT(record) => txt = Some(Metadata::from_txt(record.entries())),
+                    _ => {}
+                }
+                ControlFlow::Continue(())
+            });
+            if let Err(e) = res {
+                debug!("failed to decode mDNS answer from {addr}: {e:?}");
+            }
+            if let (Some((host, port)), Some(metadata)) = (&srv, &txt) {
+                return Ok((host.clone(), *port, metadata.clone()));
+            }
+        }
+    }
+
+    match srv {
+        // Trackers are required to send a TXT record, but tolerate it missing.
+        Some((host, port)) => Ok((host, port, txt.unwrap_or_default())),
+        None => Err(io::ErrorKind::TimedOut.into()),
+    }
+}
+
+/// Turns the resolved IPs of a tracker into the addresses to connect to.
+fn addrs(ips: impl Iterator<Item = IpAddr>, port: u16) -> Vec<SocketAddr> {
+    ips.filter(|ip| !mdns::is_link_local_v6(ip))
+        .map(|ip| SocketAddr::from((ip, port)))
+        .collect()
+}
+
+/// Returns the name of a tracker instance.
+///
+/// (`Label`'s `Display` implementation escapes non-ASCII names)
+fn instance_name(instance: &ServiceInstance) -> String {
+    String::from_utf8_lossy(instance.instance_name().as_bytes()).into_owned()
+}
+
+fn local_domain() -> DomainName {
+    DomainName::from_str("local").unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net::{Publisher, Subscriber};
+
+    use super::*;
+
+    #[test]
+    fn discovery() {
+        let _a = Publisher::builder()
+            .with_name("discovery-test-a")
+            .spawn()
+            .unwrap();
+        let camera = CameraInfo {
+            width: 1280,
+            height: 720,
+            fps: 30.0,
+        };
+        let mut b = Publisher::builder()
+            .with_name("discovery-test-b")
+            .with_friendly_name("Streamer B's tracker")
+            .with_camera(camera)
+            .with_eye_textures(false)
+            .spawn()
+            .unwrap();
+
+        let tracker = find_blocking("discovery-test-b", Some(Duration::from_secs(10))).unwrap();
+        assert_eq!(tracker.name(), "discovery-test-b");
+        assert_eq!(tracker.port(), b.port());
+        assert!(!tracker.addrs().is_empty());
+        assert_eq!(tracker.friendly_name(), Some("Streamer B's tracker"));
+        assert_eq!(tracker.camera(), Some(camera));
+        assert!(!tracker.has_eye_textures());
+        assert!(tracker.has_capability("codec/bincode"));
+        assert_eq!(tracker.protocol_version(), Some(PROTOCOL_VERSION));
+        assert!(tracker.is_compatible());
+        let _s = Subscriber::connect(tracker.addrs()).unwrap();
+        b.block_until_connected();
+
+        let trackers = list_blocking(Duration::from_secs(2)).unwrap();
+        let a = trackers
+            .iter()
+            .find(|tracker| tracker.name() == "discovery-test-a")
+            .unwrap();
+        assert_eq!(a.friendly_name(), None);
+        assert_eq!(a.camera(), None);
+        assert!(a.has_eye_textures());
+        assert!(trackers
+            .iter()
+            .any(|tracker| tracker.name() == "discovery-test-b"));
+
+        let err = find_blocking("discovery-test-c", Some(Duration::from_millis(500)))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
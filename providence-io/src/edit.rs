@@ -0,0 +1,548 @@
+//! Editing of recordings.
+//!
+//! An [`Edit`] copies a recording while keeping only a time range, splicing out parts of it,
+//! stripping eye textures, and/or re-timing it to a constant frame rate. [`concat()`] joins several
+//! recordings into one.
+//!
+//! Edited recordings keep the [`Header`](crate::recording::Header) of the original, so they still
+//! identify the tracker and message schema they were recorded with.
+
+use std::{
+    io::{self, Read, Seek, Write},
+    time::Duration,
+};
+
+use crate::{
+    data::{TimestampUnwrapper, Timing, TrackingMessage},
+    recording::{Record, RecordingReader, RecordingWriter},
+};
+
+/// A set of changes to apply to a recording.
+///
+/// The changes are applied in the order of the methods below: the recording is trimmed first, and
+/// the frame rate applies to the trimmed recording. All times refer to the original recording.
+#[derive(Debug, Clone, Default)]
+pub struct Edit {
+    range: Option<(Duration, Duration)>,
+    cuts: Vec<(Duration, Duration)>,
+    strip_textures: bool,
+    frame_rate: Option<f64>,
+}
+
+impl Edit {
+    /// Creates an [`Edit`] that copies a recording unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps only the messages between `start` and `end` (inclusive).
+    ///
+    /// The edited recording starts at `start`: message times are moved back by `start`, and
+    /// [`Header::start_time`](crate::recording::Header::start_time) forward.
+    pub fn with_range(mut self, start: Duration, end: Duration) -> Self {
+        self.range = Some((start, end));
+        self
+    }
+
+    /// Splices out the messages between `start` and `end` (inclusive).
+    ///
+    /// The messages after the cut are moved back to where the first message that was cut out
+    /// would have been, and their timestamps are rebased to match, so that the recording plays
+    /// without a gap. This can be called several times to make several cuts.
+    pub fn with_cut(mut self, start: Duration, end: Duration) -> Self {
+        self.cuts.push((start, end));
+        self
+    }
+
+    /// Replaces all eye textures with empty images, which makes recordings much smaller.
+    pub fn with_textures_stripped(mut self) -> Self {
+        self.strip_textures = true;
+        self
+    }
+
+    /// Re-times the recording to a constant frame rate.
+    ///
+    /// The edited recording has one message every `1 / fps` seconds, which is the latest message
+    /// of the original recording at that point. Messages are repeated or dropped as needed, and
+    /// their timestamps are changed to match.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fps` isn't positive and finite.
+    pub fn with_frame_rate(mut self, fps: f64) -> Self {
+        assert!(
+            fps > 0.0 && fps.is_finite(),
+            "invalid frame rate {fps} (must be positive)"
+        );
+        self.frame_rate = Some(fps);
+        self
+    }
+
+    /// Applies the edit to the recording read by `reader`, writing the result to `writer`.
+    ///
+    /// Returns `writer` once the edited recording is [finished](RecordingWriter::finish). Damaged
+    /// messages are skipped (see [`RecordingReader::next_message`]).
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the range or a cut ends before it starts.
+    pub fn apply<R: Read + Seek, W: Write>(
+        &self,
+        reader: &mut RecordingReader<R>,
+        writer: W,
+    ) -> io::Result<W> {
+        let (start, end) = self.range.unwrap_or((Duration::ZERO, Duration::MAX));
+        for (what, (start, end)) in [("range", (start, end))]
+            .into_iter()
+            .chain(self.cuts.iter().map(|&cut| ("cut", cut)))
+        {
+            if end < start {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{what} end {end:?} is before its start {start:?}"),
+                ));
+            }
+        }
+
+        let mut header = reader.header().clone();
+        header.start_time += start;
+        let mut writer = RecordingWriter::new(writer, &header)?;
+        let mut retimer = self.frame_rate.map(Retimer::new);
+        let mut rebase = Rebase::new();
+        // The total time spliced out so far.
+        let mut removed = Duration::ZERO;
+        // The time and target timestamp of the first message of the current cut.
+        let mut cut: Option<(Duration, u64)> = None;
+
+        reader.seek(start)?;
+        while let Some(Record { time, mut message }) = reader.next_message()? {
+            if time > end {
+                break;
+            }
+            let timestamp = rebase.timestamp(&message);
+            if self
+                .cuts
+                .iter()
+                .any(|&(start, end)| (start..=end).contains(&time))
+            {
+                cut.get_or_insert((time, rebase.target(timestamp)));
+                continue;
+            }
+            if let Some((cut_time, target)) = cut.take() {
+                removed += time - cut_time;
+                rebase.start(timestamp, target);
+            }
+            if rebase.is_started() {
+                rebase.apply(&mut message, timestamp);
+            }
+            if self.strip_textures {
+                message.strip_textures();
+            }
+            let time = time - start - removed;
+            match &mut retimer {
+                Some(retimer) => retimer.push(time, message, &mut writer)?,
+                None => writer.write(time, &message)?,
+            }
+        }
+        if let Some(retimer) = retimer {
+            retimer.finish(&mut writer)?;
+        }
+        writer.finish()
+    }
+}
+
+/// Joins several recordings into one, writing the result to `writer`.
+///
+/// Every recording starts `gap` after the last message of the one before it, or after the average
+/// interval between its messages if `gap` is [`None`]. Message timestamps are rebased to continue
+/// from those of the previous recording, so that the joined recording looks like it was recorded
+/// in one go. The joined recording has the [`Header`](crate::recording::Header) of the first.
+///
+/// Returns `writer` once the joined recording is [finished](RecordingWriter::finish). Fails with
+/// [`io::ErrorKind::InvalidInput`] if `readers` is empty.
+pub fn concat<R: Read + Seek, W: Write>(
+    readers: &mut [RecordingReader<R>],
+    writer: W,
+    gap: Option<Duration>,
+) -> io::Result<W> {
+    let Some(first) = readers.first() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no recordings to concatenate",
+        ));
+    };
+    let header = first.header().clone();
+    let mut writer = RecordingWriter::new(writer, &header)?;
+
+    // The time and timestamp of the last message written, and the gap after it.
+    let mut last: Option<(Duration, u64, Duration)> = None;
+    for reader in readers {
+        reader.seek(Duration::ZERO)?;
+        let mut offset = None;
+        let mut rebase = Rebase::new();
+        while let Some(Record { time, mut message }) = reader.next_message()? {
+            let offset = *offset.get_or_insert_with(|| match last {
+                Some((time, _, gap)) => time + gap,
+                None => Duration::ZERO,
+            });
+            let time = offset + time;
+            let timestamp = rebase.timestamp(&message);
+            if !rebase.is_started() {
+                let target = match last {
+                    Some((last_time, last_timestamp, _)) => {
+                        last_timestamp.wrapping_add(micros(time - last_time))
+                    }
+                    None => timestamp,
+                };
+                rebase.start(timestamp, target);
+            }
+            rebase.apply(&mut message, timestamp);
+            writer.write(time, &message)?;
+            last = Some((time, rebase.target(timestamp), Duration::ZERO));
+        }
+
+        if let Some((time, timestamp, _)) = last {
+            let gap = gap.unwrap_or_else(|| average_interval(reader));
+            last = Some((time, timestamp, gap));
+        }
+    }
+    writer.finish()
+}
+
+fn average_interval<R: Read + Seek>(reader: &RecordingReader<R>) -> Duration {
+    match reader.len() {
+        0 | 1 => Duration::ZERO,
+        len => reader.duration() / (len - 1) as u32,
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+/// Shifts the timestamps of a message stream by a fixed amount.
+///
+/// Timestamps are taken from [`TrackingMessage::timing`] if it is available, and unwrapped from
+/// [`TrackingMessage::timestamp`] otherwise.
+struct Rebase {
+    unwrapper: TimestampUnwrapper,
+    /// The amount to add to the timestamps (wrapping).
+    shift: Option<u64>,
+}
+
+impl Rebase {
+    fn new() -> Self {
+        Self {
+            unwrapper: TimestampUnwrapper::new(),
+            shift: None,
+        }
+    }
+
+    fn is_started(&self) -> bool {
+        self.shift.is_some()
+    }
+
+    /// Makes the message with the given `timestamp` end up at `target`.
+    fn start(&mut self, timestamp: u64, target: u64) {
+        self.shift = Some(target.wrapping_sub(timestamp));
+    }
+
+    /// Returns the (unwrapped) timestamp of a message.
+    ///
+    /// Must be called for every message in order, so that wrapping timestamps are tracked.
+    fn timestamp(&mut self, message: &TrackingMessage) -> u64 {
+        let unwrapped = self.unwrapper.unwrap(message.timestamp);
+        match message.timing {
+            Some(timing) => timing.timestamp,
+            None => unwrapped,
+        }
+    }
+
+    fn target(&self, timestamp: u64) -> u64 {
+        timestamp.wrapping_add(self.shift.unwrap_or(0))
+    }
+
+    /// Changes the timestamp of a message from `timestamp` to [`Rebase::target`].
+    ///
+    /// The wall-clock time in [`TrackingMessage::timing`] is left unchanged.
+    fn apply(&self, message: &mut TrackingMessage, timestamp: u64) {
+        let target = self.target(timestamp);
+        match message.timing {
+            Some(timing) => message.set_timing(Timing {
+                timestamp: target,
+                epoch_offset: timing
+                    .epoch_offset
+                    .wrapping_sub(target.wrapping_sub(timestamp) as i64),
+            }),
+            None => message.timestamp = target as u32,
+        }
+    }
+}
+
+/// Resamples a message stream to a constant frame rate.
+struct Retimer {
+    interval: Duration,
+    /// The number of the next frame.
+    frame: u32,
+    /// The latest message, and its time.
+    held: Option<(Duration, TrackingMessage)>,
+    /// The time and timestamp of the first message, which the timestamps are based on.
+    first: Option<(Duration, u64)>,
+    rebase: Rebase,
+}
+
+impl Retimer {
+    fn new(fps: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / fps),
+            frame: 0,
+            held: None,
+            first: None,
+            rebase: Rebase::new(),
+        }
+    }
+
+    fn push<W: Write>(
+        &mut self,
+        time: Duration,
+        message: TrackingMessage,
+        writer: &mut RecordingWriter<W>,
+    ) -> io::Result<()> {
+        self.emit_until(time, false, writer)?;
+        self.held = Some((time, message));
+        Ok(())
+    }
+
+    fn finish<W: Write>(mut self, writer: &mut RecordingWriter<W>) -> io::Result<()> {
+        if let Some((time, _)) = self.held {
+            self.emit_until(time, true, writer)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the held message for every frame before `time` (or at it, if `inclusive`).
+    fn emit_until<W: Write>(
+        &mut self,
+        time: Duration,
+        inclusive: bool,
+        writer: &mut RecordingWriter<W>,
+    ) -> io::Result<()> {
+        loop {
+            let frame_time = self.interval * self.frame;
+            if frame_time > time || (frame_time == time && !inclusive) {
+                return Ok(());
+            }
+            if let Some((held_time, message)) = &self.held {
+                let mut message = message.clone();
+                let timestamp = self.rebase.timestamp(&message);
+                let &mut (first_time, first_timestamp) =
+                    self.first.get_or_insert((*held_time, timestamp));
+                // Timestamps advance along with the frames, from the first message's.
+                let target =
+                    first_timestamp.wrapping_add(micros(frame_time.saturating_sub(first_time)));
+                self.rebase.start(timestamp, target);
+                self.rebase.apply(&mut message, timestamp);
+                writer.write(frame_time, &message)?;
+            }
+            self.frame += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{
+        data::{Eye, FaceData, Image, Mesh, PersistentId},
+        recording::{tests::header, Header},
+    };
+
+    use super::*;
+
+    fn message(timestamp: u32) -> TrackingMessage {
+        let eye = Eye {
+            texture: Image::from_rgba(32, 32, vec![0; 32 * 32 * 4]),
+            mesh: Mesh {
+                vertices: Vec::new(),
+                indices: Vec::new(),
+            },
+            iris_center: [0.0; 3],
+            iris_radius: 0.0,
+        };
+        TrackingMessage {
+            timestamp,
+            timing: None,
+            faces: vec![FaceData {
+                ephemeral_id: 0,
+                persistent_id: PersistentId::Unknown,
+                head_position: [0.5, 0.5],
+                head_rotation: [0.0, 0.0, 0.0, 1.0],
+                left_eye: Some(eye.clone()),
+                right_eye: Some(eye),
+            }],
+        }
+    }
+
+    /// Creates a recording with messages at the given times (in ms), with timestamps starting at
+    /// `timestamp` (in µs).
+    fn recording(header: &Header, times: &[u64], timestamp: u32) -> Cursor<Vec<u8>> {
+        let mut writer = RecordingWriter::new(Vec::new(), header).unwrap();
+        for &time in times {
+            let message = message(timestamp.wrapping_add(time as u32 * 1000));
+            writer.write(Duration::from_millis(time), &message).unwrap();
+        }
+        Cursor::new(writer.finish().unwrap())
+    }
+
+    /// Reads all messages of a recording, returning their times (in ms) and timestamps.
+    fn read(buf: Vec<u8>) -> (Header, Vec<(u64, u32)>) {
+        let mut reader = RecordingReader::new(Cursor::new(buf)).unwrap();
+        let mut messages = Vec::new();
+        while let Some(record) = reader.next_message().unwrap() {
+            messages.push((record.time.as_millis() as u64, record.message.timestamp));
+        }
+        (reader.header().clone(), messages)
+    }
+
+    #[test]
+    fn trim() {
+        let header = header();
+        let mut reader = RecordingReader::new(recording(&header, &[0, 10, 20, 30, 40], 0)).unwrap();
+        let edit = Edit::new().with_range(Duration::from_millis(10), Duration::from_millis(30));
+        let (edited, messages) = read(edit.apply(&mut reader, Vec::new()).unwrap());
+
+        assert_eq!(messages, [(0, 10_000), (10, 20_000), (20, 30_000)]);
+        assert_eq!(edited.fingerprint, header.fingerprint);
+        assert_eq!(edited.tracker, header.tracker);
+        assert_eq!(
+            edited.start_time,
+            header.start_time + Duration::from_millis(10)
+        );
+
+        let edit = Edit::new().with_range(Duration::from_millis(10), Duration::ZERO);
+        let err = edit.apply(&mut reader, Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn cut() {
+        let header = header();
+        let times = [0, 10, 20, 30, 40, 50, 60];
+        let mut reader = RecordingReader::new(recording(&header, &times, 0)).unwrap();
+        let edit = Edit::new()
+            .with_cut(Duration::from_millis(10), Duration::from_millis(20))
+            .with_cut(Duration::from_millis(45), Duration::from_millis(50));
+        let (_, messages) = read(edit.apply(&mut reader, Vec::new()).unwrap());
+        assert_eq!(messages, [(0, 0), (10, 10_000), (20, 20_000), (30, 30_000)]);
+
+        // Cuts combine with the range, and may start before it.
+        let edit = Edit::new()
+            .with_range(Duration::from_millis(10), Duration::from_millis(50))
+            .with_cut(Duration::ZERO, Duration::from_millis(20));
+        let (_, messages) = read(edit.apply(&mut reader, Vec::new()).unwrap());
+        assert_eq!(messages, [(0, 10_000), (10, 20_000), (20, 30_000)]);
+
+        let edit = Edit::new().with_cut(Duration::from_millis(10), Duration::ZERO);
+        let err = edit.apply(&mut reader, Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn strip() {
+        let original = recording(&header(), &[0, 10], 0);
+        let len = original.get_ref().len();
+        let mut reader = RecordingReader::new(original).unwrap();
+        let buf = Edit::new()
+            .with_textures_stripped()
+            .apply(&mut reader, Vec::new())
+            .unwrap();
+        // 2 messages with 2 textures each.
+        assert!(
+            len - buf.len() >= 4 * 32 * 32 * 4,
+            "{} -> {}",
+            len,
+            buf.len()
+        );
+
+        let mut reader = RecordingReader::new(Cursor::new(buf)).unwrap();
+        let message = reader.next_message().unwrap().unwrap().message;
+        let eye = message.faces[0].left_eye.as_ref().unwrap();
+        assert_eq!((eye.texture.width, eye.texture.height), (0, 0));
+    }
+
+    #[test]
+    fn retime() {
+        // Irregular messages, resampled to 100 fps.
+        let mut reader =
+            RecordingReader::new(recording(&header(), &[0, 5, 12, 38, 40], 1000)).unwrap();
+        let buf = Edit::new()
+            .with_frame_rate(100.0)
+            .apply(&mut reader, Vec::new())
+            .unwrap();
+        let (_, messages) = read(buf);
+        let times = messages.iter().map(|&(time, _)| time).collect::<Vec<_>>();
+        assert_eq!(times, [0, 10, 20, 30, 40]);
+        let timestamps = messages.iter().map(|&(_, ts)| ts).collect::<Vec<_>>();
+        assert_eq!(timestamps, [1000, 11_000, 21_000, 31_000, 41_000]);
+    }
+
+    #[test]
+    fn concatenate() {
+        let header = header();
+        // The second recording's timestamps wrap around.
+        let mut readers = [
+            RecordingReader::new(recording(&header, &[0, 10, 20], 5000)).unwrap(),
+            RecordingReader::new(recording(&header, &[0, 10], u32::MAX - 4999)).unwrap(),
+        ];
+        let buf = concat(&mut readers, Vec::new(), None).unwrap();
+        let (joined, messages) = read(buf);
+        assert_eq!(joined, header);
+        assert_eq!(
+            messages,
+            [
+                (0, 5000),
+                (10, 15_000),
+                (20, 25_000),
+                (30, 35_000),
+                (40, 45_000)
+            ]
+        );
+
+        let buf = concat(&mut readers, Vec::new(), Some(Duration::from_millis(100))).unwrap();
+        let (_, messages) = read(buf);
+        assert_eq!(messages[3], (120, 125_000));
+
+        let err = concat::<Cursor<Vec<u8>>, _>(&mut [], Vec::new(), None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn concat_timing() {
+        let header = header();
+        let mut writer = RecordingWriter::new(Vec::new(), &header).unwrap();
+        let mut message = message(0);
+        message.set_timing(Timing {
+            timestamp: 1 << 40,
+            epoch_offset: 1000,
+        });
+        writer.write(Duration::ZERO, &message).unwrap();
+        let buf = Cursor::new(writer.finish().unwrap());
+
+        let mut readers = [
+            RecordingReader::new(buf.clone()).unwrap(),
+            RecordingReader::new(buf).unwrap(),
+        ];
+        let buf = concat(&mut readers, Vec::new(), Some(Duration::from_secs(1))).unwrap();
+        let mut reader = RecordingReader::new(Cursor::new(buf)).unwrap();
+        reader.seek_to_message(1).unwrap();
+        let timing = reader
+            .next_message()
+            .unwrap()
+            .unwrap()
+            .message
+            .timing
+            .unwrap();
+        assert_eq!(timing.timestamp, (1 << 40) + 1_000_000);
+        // The wall-clock time is preserved.
+        assert_eq!(timing.epoch_offset, 1000 - 1_000_000);
+    }
+}
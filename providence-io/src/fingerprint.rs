@@ -1,29 +1,922 @@
-use std::{
-    collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
-};
+//! Structural fingerprints and schema descriptions of serde types.
+//!
+//! A [`Schema`] describes the structure of a type as seen by serde: the fields of its structs, the
+//! variants of its enums, the element types of its sequences and so on. Its
+//! [fingerprint](Schema::fingerprint) changes whenever that structure changes, which allows
+//! detecting protocol version mismatches.
+
+use std::{collections::BTreeMap, fmt, slice};
 
 use serde::{
-    de::{value::Error, EnumAccess, Error as _, SeqAccess, VariantAccess, Visitor},
-    Deserialize, Deserializer,
+    de::{
+        value::Error, DeserializeSeed, EnumAccess, Error as _, IntoDeserializer, MapAccess,
+        SeqAccess, VariantAccess, Visitor,
+    },
+    Deserialize, Deserializer, Serialize,
 };
 
 /// Computes a fingerprint for a deserializable type that changes whenever the type's structure changes.
 ///
-/// This allows detecting when a type's serialization has changed, for example to detect version
-/// mismatches.
+/// This is a shorthand for `Schema::of::<S>().fingerprint()`. See [`Schema::of`] and
+/// [`Schema::fingerprint`] for details.
 pub fn serde_fingerprint<'de, S: Deserialize<'de>>() -> u64 {
-    let mut hasher = DefaultHasher::new();
-    S::deserialize(Deser {
-        hasher: &mut hasher,
-    })
-    .unwrap();
-    hasher.finish()
+    Schema::of::<S>().fingerprint()
+}
+
+/// A description of the structure of a deserializable type.
+///
+/// A [`Schema`] can be printed as Rust-like text via its [`Display`][fmt::Display] implementation,
+/// or converted to JSON via [`Schema::to_json`] (for example, to generate decoders in other
+/// languages).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schema {
+    /// The format of the type the schema was created from.
+    pub root: Format,
+    /// All named types reachable from [`Schema::root`], by name.
+    pub types: BTreeMap<String, Container>,
+}
+
+impl Schema {
+    /// Computes the [`Schema`] of a deserializable type.
+    ///
+    /// The schema covers every type reachable from `S`: element types of sequences and maps, the
+    /// payloads of [`Option`]s, and every variant of every enum.
+    ///
+    /// Named types (structs and enums) are identified by the name they report to serde, so two
+    /// different types with the same name (for example, two instantiations of a generic struct)
+    /// that are both reachable from `S` will be conflated.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `S` cannot be traced, for example because it uses `deserialize_any` or
+    /// because it is infinitely recursive (contains itself without an [`Option`], sequence, map or
+    /// enum variant that can break the cycle).
+    pub fn of<'de, S: Deserialize<'de>>() -> Self {
+        match trace::<S>() {
+            Ok(schema) => schema,
+            Err(e) => panic!("failed to trace type: {e}"),
+        }
+    }
+
+    /// Computes the fingerprint of this schema.
+    ///
+    /// This allows detecting when a type's serialization has changed, for example to detect
+    /// version mismatches.
+    ///
+    /// The fingerprint is computed with [FNV-1a], which is fully specified, so it does not depend
+    /// on the Rust version or platform used to build the program. Type names do not contribute to
+    /// the fingerprint, only the structure of the types does (since that is all that matters for
+    /// non-self-describing formats like bincode).
+    ///
+    /// [FNV-1a]: http://www.isthe.com/chongo/tech/comp/fnv/index.html
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = Fnv1a::new();
+        hash_format(&mut hasher, &self.root, &self.types, &mut Vec::new());
+        hasher.finish()
+    }
+
+    /// Serializes this schema as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("failed to serialize schema")
+    }
+
+    /// Parses a schema from JSON produced by [`Schema::to_json`].
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// Compares this schema to `other` and returns a list of the places where they differ.
+    ///
+    /// Like [`Schema::fingerprint`], this ignores type names. If the returned list is empty, both
+    /// schemas have the same fingerprint.
+    pub fn diff(&self, other: &Schema) -> Vec<Difference> {
+        let mut diff = Diff {
+            expected: self,
+            found: other,
+            stack: Vec::new(),
+            differences: Vec::new(),
+        };
+        let path = match &self.root {
+            Format::TypeName(name) => name.clone(),
+            _ => String::new(),
+        };
+        diff.format(&path, &self.root, &other.root);
+        diff.differences
+    }
+
+    /// Returns the names of all types reachable from the root, in the order they are first
+    /// referenced.
+    fn type_names(&self) -> Vec<&str> {
+        fn visit<'a>(schema: &'a Schema, format: &'a Format, names: &mut Vec<&'a str>) {
+            format.for_each_type_name(&mut |name| {
+                if !names.contains(&name) {
+                    names.push(name);
+                    if let Some(container) = schema.types.get(name) {
+                        container.for_each_format(&mut |format| visit(schema, format, names));
+                    }
+                }
+            });
+        }
+
+        let mut names = Vec::new();
+        visit(self, &self.root, &mut names);
+        names
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.root)?;
+        for name in self.type_names() {
+            let Some(container) = self.types.get(name) else {
+                continue;
+            };
+            write!(f, "\n\n")?;
+            match container {
+                Container::UnitStruct => write!(f, "struct {name};")?,
+                Container::NewtypeStruct(inner) => write!(f, "struct {name}({inner});")?,
+                Container::TupleStruct(elems) => {
+                    write!(f, "struct {name}(")?;
+                    write_list(f, elems)?;
+                    write!(f, ");")?;
+                }
+                Container::Struct(fields) => {
+                    writeln!(f, "struct {name} {{")?;
+                    for field in fields {
+                        writeln!(f, "    {}: {},", field.name, field.value)?;
+                    }
+                    write!(f, "}}")?;
+                }
+                Container::Enum(variants) => {
+                    writeln!(f, "enum {name} {{")?;
+                    for variant in variants {
+                        write!(f, "    {}", variant.name)?;
+                        match &variant.value {
+                            VariantFormat::Unit => {}
+                            VariantFormat::Newtype(inner) => write!(f, "({inner})")?,
+                            VariantFormat::Tuple(elems) => {
+                                write!(f, "(")?;
+                                write_list(f, elems)?;
+                                write!(f, ")")?;
+                            }
+                            VariantFormat::Struct(fields) => {
+                                write!(f, " {{ ")?;
+                                for (i, field) in fields.iter().enumerate() {
+                                    if i != 0 {
+                                        write!(f, ", ")?;
+                                    }
+                                    write!(f, "{}: {}", field.name, field.value)?;
+                                }
+                                write!(f, " }}")?;
+                            }
+                        }
+                        writeln!(f, ",")?;
+                    }
+                    write!(f, "}}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter<'_>, formats: &[Format]) -> fmt::Result {
+    for (i, format) in formats.iter().enumerate() {
+        if i != 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{format}")?;
+    }
+    Ok(())
+}
+
+/// Describes the structure of a (possibly unnamed) type.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// Placeholder for a type that could not be traced.
+    Unknown,
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+    Char,
+    /// A UTF-8 string.
+    Str,
+    /// A byte array.
+    Bytes,
+    /// The unit type `()`.
+    Unit,
+    Option(Box<Format>),
+    /// A variable-length sequence of elements.
+    Seq(Box<Format>),
+    Map {
+        key: Box<Format>,
+        value: Box<Format>,
+    },
+    /// A fixed-length sequence of elements.
+    Tuple(Vec<Format>),
+    /// Reference to a named [`Container`] in [`Schema::types`].
+    TypeName(String),
+}
+
+impl Format {
+    fn for_each_type_name<'a>(&'a self, f: &mut impl FnMut(&'a str)) {
+        match self {
+            Format::Option(inner) | Format::Seq(inner) => inner.for_each_type_name(f),
+            Format::Map { key, value } => {
+                key.for_each_type_name(f);
+                value.for_each_type_name(f);
+            }
+            Format::Tuple(elems) => elems.iter().for_each(|elem| elem.for_each_type_name(f)),
+            Format::TypeName(name) => f(name),
+            _ => {}
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Format::Unknown => "unknown",
+            Format::Bool => "bool",
+            Format::I8 => "i8",
+            Format::I16 => "i16",
+            Format::I32 => "i32",
+            Format::I64 => "i64",
+            Format::U8 => "u8",
+            Format::U16 => "u16",
+            Format::U32 => "u32",
+            Format::U64 => "u64",
+            Format::F32 => "f32",
+            Format::F64 => "f64",
+            Format::Char => "char",
+            Format::Str => "str",
+            Format::Bytes => "bytes",
+            Format::Unit => "unit",
+            Format::Option(_) => "option",
+            Format::Seq(_) => "seq",
+            Format::Map { .. } => "map",
+            Format::Tuple(_) => "tuple",
+            Format::TypeName(_) => "type_name",
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Unknown => f.write_str("?"),
+            Format::Str => f.write_str("String"),
+            Format::Bytes => f.write_str("Bytes"),
+            Format::Unit => f.write_str("()"),
+            Format::Option(inner) => write!(f, "Option<{inner}>"),
+            Format::Seq(inner) => write!(f, "Vec<{inner}>"),
+            Format::Map { key, value } => write!(f, "Map<{key}, {value}>"),
+            Format::Tuple(elems) => {
+                write!(f, "(")?;
+                write_list(f, elems)?;
+                if elems.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Format::TypeName(name) => f.write_str(name),
+            _ => f.write_str(self.kind()),
+        }
+    }
+}
+
+/// Describes the structure of a named type.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Container {
+    UnitStruct,
+    NewtypeStruct(Format),
+    TupleStruct(Vec<Format>),
+    Struct(Vec<Named<Format>>),
+    /// An enum, with all of its variants in declaration order.
+    Enum(Vec<Named<VariantFormat>>),
+}
+
+impl Container {
+    fn for_each_format<'a>(&'a self, f: &mut impl FnMut(&'a Format)) {
+        match self {
+            Container::UnitStruct => {}
+            Container::NewtypeStruct(inner) => f(inner),
+            Container::TupleStruct(elems) => elems.iter().for_each(f),
+            Container::Struct(fields) => fields.iter().for_each(|field| f(&field.value)),
+            Container::Enum(variants) => variants
+                .iter()
+                .for_each(|variant| variant.value.for_each_format(f)),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Container::UnitStruct => "unit struct",
+            Container::NewtypeStruct(_) => "newtype struct",
+            Container::TupleStruct(_) => "tuple struct",
+            Container::Struct(_) => "struct",
+            Container::Enum(_) => "enum",
+        }
+    }
+}
+
+/// Describes the contents of an enum variant.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VariantFormat {
+    Unit,
+    Newtype(Format),
+    Tuple(Vec<Format>),
+    Struct(Vec<Named<Format>>),
+}
+
+impl VariantFormat {
+    fn for_each_format<'a>(&'a self, f: &mut impl FnMut(&'a Format)) {
+        match self {
+            VariantFormat::Unit => {}
+            VariantFormat::Newtype(inner) => f(inner),
+            VariantFormat::Tuple(elems) => elems.iter().for_each(f),
+            VariantFormat::Struct(fields) => fields.iter().for_each(|field| f(&field.value)),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            VariantFormat::Unit => "unit variant",
+            VariantFormat::Newtype(_) => "newtype variant",
+            VariantFormat::Tuple(_) => "tuple variant",
+            VariantFormat::Struct(_) => "struct variant",
+        }
+    }
+}
+
+/// A struct field or enum variant.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Named<T> {
+    pub name: String,
+    pub value: T,
+}
+
+impl<T> Named<T> {
+    fn new(name: &str, value: T) -> Self {
+        Self {
+            name: name.to_string(),
+            value,
+        }
+    }
+}
+
+/// A place where two [`Schema`]s differ, as returned by [`Schema::diff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// Path to the differing item, starting at the root type (eg. `TrackingMessage.faces[].ephemeral_id`).
+    pub path: String,
+    /// Description of the item in the schema [`Schema::diff`] was called on.
+    pub expected: String,
+    /// Description of the item in the schema passed to [`Schema::diff`].
+    pub found: String,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}`: expected {}, found {}",
+            self.path, self.expected, self.found
+        )
+    }
+}
+
+struct Diff<'a> {
+    expected: &'a Schema,
+    found: &'a Schema,
+    /// Pairs of type names that are currently being compared (recursion guard).
+    stack: Vec<(&'a str, &'a str)>,
+    differences: Vec<Difference>,
+}
+
+impl<'a> Diff<'a> {
+    fn push(&mut self, path: &str, expected: impl fmt::Display, found: impl fmt::Display) {
+        self.differences.push(Difference {
+            path: path.to_string(),
+            expected: expected.to_string(),
+            found: found.to_string(),
+        });
+    }
+
+    fn format(&mut self, path: &str, expected: &'a Format, found: &'a Format) {
+        match (expected, found) {
+            (Format::Option(a), Format::Option(b)) => self.format(&format!("{path}?"), a, b),
+            (Format::Seq(a), Format::Seq(b)) => self.format(&format!("{path}[]"), a, b),
+            (Format::Map { key: ka, value: va }, Format::Map { key: kb, value: vb }) => {
+                self.format(&format!("{path}{{key}}"), ka, kb);
+                self.format(&format!("{path}{{value}}"), va, vb);
+            }
+            (Format::Tuple(a), Format::Tuple(b)) => self.formats(path, a, b),
+            (Format::TypeName(a), Format::TypeName(b)) => {
+                if self.stack.contains(&(a, b)) {
+                    return;
+                }
+                let (Some(ca), Some(cb)) = (self.expected.types.get(a), self.found.types.get(b))
+                else {
+                    if a != b {
+                        self.push(path, a, b);
+                    }
+                    return;
+                };
+                self.stack.push((a, b));
+                self.container(path, ca, cb);
+                self.stack.pop();
+            }
+            (a, b) if a.kind() == b.kind() => {}
+            (a, b) => self.push(path, format_args!("`{a}`"), format_args!("`{b}`")),
+        }
+    }
+
+    fn formats(&mut self, path: &str, expected: &'a [Format], found: &'a [Format]) {
+        if expected.len() != found.len() {
+            self.push(
+                path,
+                format_args!("{} elements", expected.len()),
+                format_args!("{} elements", found.len()),
+            );
+        }
+        for (i, (a, b)) in expected.iter().zip(found).enumerate() {
+            self.format(&format!("{path}.{i}"), a, b);
+        }
+    }
+
+    fn fields(&mut self, path: &str, expected: &'a [Named<Format>], found: &'a [Named<Format>]) {
+        for (a, b) in expected.iter().zip(found) {
+            if a.name != b.name {
+                self.push(
+                    path,
+                    format_args!("field `{}`", a.name),
+                    format_args!("field `{}`", b.name),
+                );
+            }
+            self.format(&format!("{path}.{}", a.name), &a.value, &b.value);
+        }
+        if expected.len() != found.len() {
+            self.push(
+                path,
+                format_args!("{} fields", expected.len()),
+                format_args!("{} fields", found.len()),
+            );
+        }
+    }
+
+    fn container(&mut self, path: &str, expected: &'a Container, found: &'a Container) {
+        match (expected, found) {
+            (Container::UnitStruct, Container::UnitStruct) => {}
+            (Container::NewtypeStruct(a), Container::NewtypeStruct(b)) => {
+                self.format(&format!("{path}.0"), a, b)
+            }
+            (Container::TupleStruct(a), Container::TupleStruct(b)) => self.formats(path, a, b),
+            (Container::Struct(a), Container::Struct(b)) => self.fields(path, a, b),
+            (Container::Enum(a), Container::Enum(b)) => {
+                for (va, vb) in a.iter().zip(b) {
+                    if va.name != vb.name {
+                        self.push(
+                            path,
+                            format_args!("variant `{}`", va.name),
+                            format_args!("variant `{}`", vb.name),
+                        );
+                    }
+                    let path = format!("{path}::{}", va.name);
+                    match (&va.value, &vb.value) {
+                        (VariantFormat::Unit, VariantFormat::Unit) => {}
+                        (VariantFormat::Newtype(a), VariantFormat::Newtype(b)) => {
+                            self.format(&format!("{path}.0"), a, b)
+                        }
+                        (VariantFormat::Tuple(a), VariantFormat::Tuple(b)) => {
+                            self.formats(&path, a, b)
+                        }
+                        (VariantFormat::Struct(a), VariantFormat::Struct(b)) => {
+                            self.fields(&path, a, b)
+                        }
+                        (a, b) => self.push(&path, a.kind(), b.kind()),
+                    }
+                }
+                if a.len() != b.len() {
+                    self.push(
+                        path,
+                        format_args!("{} variants", a.len()),
+                        format_args!("{} variants", b.len()),
+                    );
+                }
+            }
+            (a, b) => self.push(path, a.kind(), b.kind()),
+        }
+    }
+}
+
+/// 64-bit FNV-1a hasher.
+///
+/// Unlike [`std::collections::hash_map::DefaultHasher`], this algorithm is stable across Rust
+/// releases. Integers are written in little-endian byte order and strings are prefixed with their
+/// length, so the hashed byte stream does not depend on the platform.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.write_u64(s.len() as u64);
+        self.write(s.as_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+type Types = BTreeMap<String, Container>;
+
+fn hash_format<'a>(
+    hasher: &mut Fnv1a,
+    format: &'a Format,
+    types: &'a Types,
+    stack: &mut Vec<&'a str>,
+) {
+    match format {
+        Format::Option(inner) | Format::Seq(inner) => {
+            hasher.write_str(format.kind());
+            hash_format(hasher, inner, types, stack);
+        }
+        Format::Map { key, value } => {
+            hasher.write_str(format.kind());
+            hash_format(hasher, key, types, stack);
+            hash_format(hasher, value, types, stack);
+        }
+        Format::Tuple(elems) => {
+            hasher.write_str(format.kind());
+            hash_formats(hasher, elems, types, stack);
+        }
+        Format::TypeName(name) => {
+            // Recursive references are hashed as the distance to the referenced container, so that
+            // the result does not depend on type names.
+            if let Some(pos) = stack.iter().rposition(|n| n == name) {
+                hasher.write_str("recursive");
+                hasher.write_u64((stack.len() - pos) as u64);
+                return;
+            }
+            let Some(container) = types.get(name) else {
+                hasher.write_str("unknown");
+                return;
+            };
+
+            stack.push(name);
+            hash_container(hasher, container, types, stack);
+            stack.pop();
+        }
+        _ => hasher.write_str(format.kind()),
+    }
+}
+
+fn hash_formats<'a>(
+    hasher: &mut Fnv1a,
+    formats: &'a [Format],
+    types: &'a Types,
+    stack: &mut Vec<&'a str>,
+) {
+    hasher.write_u64(formats.len() as u64);
+    for format in formats {
+        hash_format(hasher, format, types, stack);
+    }
+}
+
+fn hash_fields<'a>(
+    hasher: &mut Fnv1a,
+    fields: &'a [Named<Format>],
+    types: &'a Types,
+    stack: &mut Vec<&'a str>,
+) {
+    hasher.write_u64(fields.len() as u64);
+    for field in fields {
+        hasher.write_str(&field.name);
+        hash_format(hasher, &field.value, types, stack);
+    }
+}
+
+fn hash_container<'a>(
+    hasher: &mut Fnv1a,
+    container: &'a Container,
+    types: &'a Types,
+    stack: &mut Vec<&'a str>,
+) {
+    match container {
+        Container::UnitStruct => hasher.write_str("unit_struct"),
+        Container::NewtypeStruct(inner) => {
+            hasher.write_str("newtype_struct");
+            hash_format(hasher, inner, types, stack);
+        }
+        Container::TupleStruct(elems) => {
+            hasher.write_str("tuple_struct");
+            hash_formats(hasher, elems, types, stack);
+        }
+        Container::Struct(fields) => {
+            hasher.write_str("struct");
+            hash_fields(hasher, fields, types, stack);
+        }
+        Container::Enum(variants) => {
+            hasher.write_str("enum");
+            hasher.write_u64(variants.len() as u64);
+            for variant in variants {
+                hasher.write_str(&variant.name);
+                match &variant.value {
+                    VariantFormat::Unit => hasher.write_str("unit_variant"),
+                    VariantFormat::Newtype(inner) => {
+                        hasher.write_str("newtype_variant");
+                        hash_format(hasher, inner, types, stack);
+                    }
+                    VariantFormat::Tuple(elems) => {
+                        hasher.write_str("tuple_variant");
+                        hash_formats(hasher, elems, types, stack);
+                    }
+                    VariantFormat::Struct(fields) => {
+                        hasher.write_str("struct_variant");
+                        hash_fields(hasher, fields, types, stack);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A named type encountered while tracing.
+enum Traced {
+    Container(Container),
+    Enum {
+        variants: &'static [&'static str],
+        /// The variants that have been traced so far, by index.
+        traced: BTreeMap<u32, VariantFormat>,
+    },
+}
+
+type Registry = BTreeMap<&'static str, Traced>;
+
+/// Returns whether `name` and every container reachable from it has been fully traced.
+fn is_complete<'a>(registry: &'a Registry, name: &'a str, visited: &mut Vec<&'a str>) -> bool {
+    if visited.contains(&name) {
+        return true;
+    }
+    visited.push(name);
+
+    let mut complete = true;
+    let mut check = |format: &'a Format| {
+        format.for_each_type_name(&mut |name| {
+            complete = complete && is_complete(registry, name, visited);
+        })
+    };
+    match registry.get(name) {
+        None => return false,
+        Some(Traced::Container(container)) => container.for_each_format(&mut check),
+        Some(Traced::Enum { variants, traced }) => {
+            if traced.len() != variants.len() {
+                return false;
+            }
+            traced
+                .values()
+                .for_each(|variant| variant.for_each_format(&mut check));
+        }
+    }
+    complete
+}
+
+/// Traces the structure of `S` by repeatedly deserializing it from a [`Deser`] until every enum
+/// variant has been visited.
+fn trace<'de, S: Deserialize<'de>>() -> Result<Schema, Error> {
+    let mut tracer = Tracer::default();
+    loop {
+        tracer.stack = vec![Frame::new("", None)];
+        tracer.recursion = None;
+        tracer.retry = false;
+        let progress = tracer.progress();
+
+        let mut root = Format::Unknown;
+        match S::deserialize(Deser {
+            tracer: &mut tracer,
+            format: &mut root,
+        }) {
+            Ok(_) => {}
+            // A recursive type was encountered, and the site that breaks the cycle will be elided
+            // on the next pass.
+            Err(_) if tracer.retry => continue,
+            Err(e) => return Err(e),
+        }
+
+        let mut complete = true;
+        root.for_each_type_name(&mut |name| {
+            complete = complete && is_complete(&tracer.registry, name, &mut Vec::new());
+        });
+        if complete {
+            let types = tracer
+                .registry
+                .into_iter()
+                .filter_map(|(name, traced)| {
+                    let container = match traced {
+                        Traced::Container(container) => container,
+                        Traced::Enum {
+                            variants,
+                            mut traced,
+                        } => Container::Enum(
+                            variants
+                                .iter()
+                                .enumerate()
+                                .map(|(i, name)| {
+                                    Some(Named::new(name, traced.remove(&(i as u32))?))
+                                })
+                                .collect::<Option<_>>()?,
+                        ),
+                    };
+                    Some((name.to_string(), container))
+                })
+                .collect();
+
+            // The registry may contain types that are not reachable from the root (only if a type
+            // name is shared by multiple types), so remove those.
+            let mut schema = Schema { root, types };
+            let reachable = schema
+                .type_names()
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>();
+            schema.types.retain(|name, _| reachable.contains(name));
+            return Ok(schema);
+        }
+        if tracer.progress() == progress {
+            return Err(Error::custom("failed to trace all enum variants"));
+        }
+    }
+}
+
+/// Identifies an [`Option`], sequence or map within the container that contains it.
+///
+/// Fields: container name, enum variant index, number of preceding sites in the container.
+type Site = (&'static str, Option<u32>, u32);
+
+struct Frame {
+    name: &'static str,
+    variant: Option<u32>,
+    sites: u32,
+}
+
+impl Frame {
+    fn new(name: &'static str, variant: Option<u32>) -> Self {
+        Self {
+            name,
+            variant,
+            sites: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Tracer {
+    registry: Registry,
+    /// The named containers that are currently being traced (recursion guard).
+    stack: Vec<Frame>,
+    /// Sites that have to be traced as empty to break a cycle, with the format of their contents.
+    elided: BTreeMap<Site, Format>,
+    /// Set to the name of a container that was entered recursively.
+    recursion: Option<&'static str>,
+    /// Set when a new site was elided and the current pass should be restarted.
+    retry: bool,
+}
+
+impl Tracer {
+    /// Total number of traced containers and enum variants, used to detect lack of progress.
+    fn progress(&self) -> usize {
+        self.registry
+            .values()
+            .map(|traced| match traced {
+                Traced::Container(_) => 1,
+                Traced::Enum { traced, .. } => traced.len(),
+            })
+            .sum()
+    }
+
+    fn next_site(&mut self) -> Site {
+        let frame = self.stack.last_mut().unwrap();
+        let site = (frame.name, frame.variant, frame.sites);
+        frame.sites += 1;
+        site
+    }
+
+    /// Called when tracing the contents of `site` failed.
+    ///
+    /// If the failure was caused by re-entering a container that encloses `site`, the site will be
+    /// traced as empty on subsequent passes.
+    fn elide(&mut self, site: Site, contents: &Format) {
+        let Some(name) = self.recursion else {
+            return;
+        };
+        if self.stack.iter().any(|frame| frame.name == name) {
+            self.elided.insert(site, contents.clone());
+            self.recursion = None;
+            self.retry = true;
+        }
+    }
+
+    fn enter_struct(&mut self, name: &'static str) -> Result<(), Error> {
+        if self.stack.iter().any(|frame| frame.name == name) {
+            self.recursion = Some(name);
+            return Err(Error::custom(format_args!("recursive type `{name}`")));
+        }
+        self.stack.push(Frame::new(name, None));
+        Ok(())
+    }
+
+    fn record_struct(&mut self, name: &'static str, container: Container) {
+        self.registry.insert(name, Traced::Container(container));
+    }
+
+    /// Selects the enum variant to trace next and enters it.
+    ///
+    /// Prefers variants that haven't been traced yet, then variants that lead to containers that
+    /// haven't been fully traced yet. Variants that are currently being traced are never selected.
+    fn enter_enum(
+        &mut self,
+        name: &'static str,
+        variants: &'static [&'static str],
+    ) -> Result<u32, Error> {
+        self.registry.entry(name).or_insert(Traced::Enum {
+            variants,
+            traced: BTreeMap::new(),
+        });
+        let Traced::Enum { traced, .. } = &self.registry[name] else {
+            return Err(Error::custom(format_args!(
+                "type name `{name}` used by both a struct and an enum"
+            )));
+        };
+
+        let candidates = (0..variants.len() as u32)
+            .filter(|&index| {
+                !self
+                    .stack
+                    .iter()
+                    .any(|frame| frame.name == name && frame.variant == Some(index))
+            })
+            .collect::<Vec<_>>();
+        let untraced = candidates
+            .iter()
+            .copied()
+            .find(|index| !traced.contains_key(index));
+        let incomplete = || {
+            candidates.iter().copied().find(|index| {
+                let mut complete = true;
+                traced[index].for_each_format(&mut |format| {
+                    format.for_each_type_name(&mut |n| {
+                        complete = complete && is_complete(&self.registry, n, &mut vec![name]);
+                    })
+                });
+                !complete
+            })
+        };
+
+        let Some(index) = untraced.or_else(incomplete).or(candidates.first().copied()) else {
+            self.recursion = Some(name);
+            return Err(Error::custom(format_args!("recursive type `{name}`")));
+        };
+        self.stack.push(Frame::new(name, Some(index)));
+        Ok(index)
+    }
+
+    fn record_variant(&mut self, name: &'static str, index: u32, format: VariantFormat) {
+        if let Some(Traced::Enum { traced, .. }) = self.registry.get_mut(name) {
+            traced.insert(index, format);
+        }
+    }
 }
 
 struct Seq<'a> {
-    hasher: &'a mut DefaultHasher,
-    len: usize,
+    tracer: &'a mut Tracer,
+    formats: slice::IterMut<'a, Format>,
 }
 
 impl<'a, 'de> SeqAccess<'de> for Seq<'a> {
@@ -31,24 +924,68 @@ impl<'a, 'de> SeqAccess<'de> for Seq<'a> {
 
     fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
     where
-        T: serde::de::DeserializeSeed<'de>,
+        T: DeserializeSeed<'de>,
     {
-        if self.len == 0 {
+        let Some(format) = self.formats.next() else {
             return Ok(None);
-        }
+        };
 
-        self.len -= 1;
         seed.deserialize(Deser {
-            hasher: self.hasher,
+            tracer: self.tracer,
+            format,
         })
         .map(Some)
     }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.formats.len())
+    }
+}
+
+struct Map<'a> {
+    tracer: &'a mut Tracer,
+    entry: Option<(&'a mut Format, &'a mut Format)>,
+    value: Option<&'a mut Format>,
+}
+
+impl<'a, 'de> MapAccess<'de> for Map<'a> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        let Some((key, value)) = self.entry.take() else {
+            return Ok(None);
+        };
+
+        self.value = Some(value);
+        seed.deserialize(Deser {
+            tracer: self.tracer,
+            format: key,
+        })
+        .map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let format = self
+            .value
+            .take()
+            .ok_or_else(|| Error::custom("`next_value_seed` called before `next_key_seed`"))?;
+        seed.deserialize(Deser {
+            tracer: self.tracer,
+            format,
+        })
+    }
 }
 
-#[allow(dead_code)]
 struct Enum<'a> {
-    hasher: &'a mut DefaultHasher,
-    len: usize,
+    tracer: &'a mut Tracer,
+    index: u32,
+    format: &'a mut Option<VariantFormat>,
 }
 
 impl<'a, 'de> EnumAccess<'de> for Enum<'a> {
@@ -56,46 +993,58 @@ impl<'a, 'de> EnumAccess<'de> for Enum<'a> {
 
     type Variant = Variant<'a>;
 
-    fn variant_seed<V>(self, _seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
     where
-        V: serde::de::DeserializeSeed<'de>,
+        V: DeserializeSeed<'de>,
     {
-        Err(Error::custom("enum fingerprinting is not yet supported"))
+        let value = seed.deserialize(self.index.into_deserializer())?;
+        Ok((
+            value,
+            Variant {
+                tracer: self.tracer,
+                format: self.format,
+            },
+        ))
     }
 }
 
 struct Variant<'a> {
-    hasher: &'a mut DefaultHasher,
+    tracer: &'a mut Tracer,
+    format: &'a mut Option<VariantFormat>,
 }
 
 impl<'a, 'de> VariantAccess<'de> for Variant<'a> {
     type Error = Error;
 
     fn unit_variant(self) -> Result<(), Self::Error> {
-        self.hasher.write(b"unit_variant");
+        *self.format = Some(VariantFormat::Unit);
         Ok(())
     }
 
     fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
     where
-        T: serde::de::DeserializeSeed<'de>,
+        T: DeserializeSeed<'de>,
     {
-        self.hasher.write(b"newtype_variant_seed");
-        seed.deserialize(Deser {
-            hasher: self.hasher,
-        })
+        let mut inner = Format::Unknown;
+        let value = seed.deserialize(Deser {
+            tracer: self.tracer,
+            format: &mut inner,
+        })?;
+        *self.format = Some(VariantFormat::Newtype(inner));
+        Ok(value)
     }
 
     fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"tuple_variant");
-        self.hasher.write_usize(len);
-        visitor.visit_seq(Seq {
-            hasher: self.hasher,
-            len,
-        })
+        let mut elems = vec![Format::Unknown; len];
+        let value = visitor.visit_seq(Seq {
+            tracer: self.tracer,
+            formats: elems.iter_mut(),
+        })?;
+        *self.format = Some(VariantFormat::Tuple(elems));
+        Ok(value)
     }
 
     fn struct_variant<V>(
@@ -106,17 +1055,62 @@ impl<'a, 'de> VariantAccess<'de> for Variant<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"struct_variant");
-        fields.hash(self.hasher);
-        visitor.visit_seq(Seq {
-            hasher: self.hasher,
-            len: fields.len(),
-        })
+        let mut formats = vec![Format::Unknown; fields.len()];
+        let value = visitor.visit_seq(Seq {
+            tracer: self.tracer,
+            formats: formats.iter_mut(),
+        })?;
+        *self.format = Some(VariantFormat::Struct(
+            fields
+                .iter()
+                .zip(formats)
+                .map(|(name, format)| Named::new(name, format))
+                .collect(),
+        ));
+        Ok(value)
     }
 }
 
 struct Deser<'a> {
-    hasher: &'a mut DefaultHasher,
+    tracer: &'a mut Tracer,
+    format: &'a mut Format,
+}
+
+impl<'a> Deser<'a> {
+    /// Traces the contents of an [`Option`], sequence or map.
+    ///
+    /// `visit` is invoked with `true` if the contents should be visited, and with `false` if they
+    /// have to be skipped to break a cycle in a recursive type.
+    fn trace_site<T>(
+        self,
+        visit: impl FnOnce(&mut Tracer, &mut Format, bool) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let site = self.tracer.next_site();
+        if let Some(contents) = self.tracer.elided.get(&site) {
+            *self.format = contents.clone();
+            return visit(self.tracer, &mut Format::Unknown, false);
+        }
+
+        let res = visit(self.tracer, self.format, true);
+        if res.is_err() {
+            self.tracer.elide(site, self.format);
+        }
+        res
+    }
+
+    fn trace_struct<T>(
+        self,
+        name: &'static str,
+        visit: impl FnOnce(&mut Tracer) -> Result<(T, Container), Error>,
+    ) -> Result<T, Error> {
+        *self.format = Format::TypeName(name.to_string());
+        self.tracer.enter_struct(name)?;
+        let res = visit(self.tracer);
+        self.tracer.stack.pop();
+        let (value, container) = res?;
+        self.tracer.record_struct(name, container);
+        Ok(value)
+    }
 }
 
 impl<'a, 'de> Deserializer<'de> for Deser<'a> {
@@ -133,7 +1127,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"bool");
+        *self.format = Format::Bool;
         visitor.visit_bool(false)
     }
 
@@ -141,7 +1135,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"i8");
+        *self.format = Format::I8;
         visitor.visit_i8(0)
     }
 
@@ -149,7 +1143,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"i16");
+        *self.format = Format::I16;
         visitor.visit_i16(0)
     }
 
@@ -157,7 +1151,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"i32");
+        *self.format = Format::I32;
         visitor.visit_i32(0)
     }
 
@@ -165,7 +1159,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"i64");
+        *self.format = Format::I64;
         visitor.visit_i64(0)
     }
 
@@ -173,7 +1167,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"u8");
+        *self.format = Format::U8;
         visitor.visit_u8(0)
     }
 
@@ -181,7 +1175,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"u16");
+        *self.format = Format::U16;
         visitor.visit_u16(0)
     }
 
@@ -189,7 +1183,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"u32");
+        *self.format = Format::U32;
         visitor.visit_u32(0)
     }
 
@@ -197,7 +1191,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"u64");
+        *self.format = Format::U64;
         visitor.visit_u64(0)
     }
 
@@ -205,7 +1199,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"f32");
+        *self.format = Format::F32;
         visitor.visit_f32(0.0)
     }
 
@@ -213,7 +1207,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"f64");
+        *self.format = Format::F64;
         visitor.visit_f64(0.0)
     }
 
@@ -221,7 +1215,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"char");
+        *self.format = Format::Char;
         visitor.visit_char('c')
     }
 
@@ -229,7 +1223,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"str");
+        *self.format = Format::Str;
         visitor.visit_borrowed_str("borrowed_str")
     }
 
@@ -237,7 +1231,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"str");
+        *self.format = Format::Str;
         visitor.visit_str("string")
     }
 
@@ -245,7 +1239,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"bytes");
+        *self.format = Format::Bytes;
         visitor.visit_bytes(&[])
     }
 
@@ -253,7 +1247,7 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"bytes");
+        *self.format = Format::Bytes;
         visitor.visit_bytes(&[])
     }
 
@@ -261,50 +1255,75 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"option");
-        visitor.visit_none()
+        self.trace_site(|tracer, format, visit| {
+            let mut inner = Format::Unknown;
+            let res = if visit {
+                visitor.visit_some(Deser {
+                    tracer,
+                    format: &mut inner,
+                })
+            } else {
+                visitor.visit_none()
+            };
+            if visit {
+                *format = Format::Option(Box::new(inner));
+            }
+            res
+        })
     }
 
     fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"unit");
+        *self.format = Format::Unit;
         visitor.visit_unit()
     }
 
     fn deserialize_unit_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"unit_struct");
-        visitor.visit_unit()
+        self.trace_struct(name, |_| Ok((visitor.visit_unit()?, Container::UnitStruct)))
     }
 
     fn deserialize_newtype_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"newtype_struct");
-        visitor.visit_newtype_struct(self)
+        self.trace_struct(name, |tracer| {
+            let mut inner = Format::Unknown;
+            let value = visitor.visit_newtype_struct(Deser {
+                tracer,
+                format: &mut inner,
+            })?;
+            Ok((value, Container::NewtypeStruct(inner)))
+        })
     }
 
     fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"seq");
-        visitor.visit_seq(Seq {
-            hasher: self.hasher,
-            len: 0,
+        self.trace_site(|tracer, format, visit| {
+            let mut elem = Format::Unknown;
+            let len = if visit { 1 } else { 0 };
+            let res = visitor.visit_seq(Seq {
+                tracer,
+                formats: slice::from_mut(&mut elem)[..len].iter_mut(),
+            });
+            if visit {
+                *format = Format::Seq(Box::new(elem));
+            }
+            res
         })
     }
 
@@ -312,28 +1331,31 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"tuple");
-        self.hasher.write_usize(len);
-        visitor.visit_seq(Seq {
-            hasher: self.hasher,
-            len,
-        })
+        let mut elems = vec![Format::Unknown; len];
+        let res = visitor.visit_seq(Seq {
+            tracer: self.tracer,
+            formats: elems.iter_mut(),
+        });
+        *self.format = Format::Tuple(elems);
+        res
     }
 
     fn deserialize_tuple_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         len: usize,
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"tuple_struct");
-        self.hasher.write_usize(len);
-        visitor.visit_seq(Seq {
-            hasher: self.hasher,
-            len,
+        self.trace_struct(name, |tracer| {
+            let mut elems = vec![Format::Unknown; len];
+            let value = visitor.visit_seq(Seq {
+                tracer,
+                formats: elems.iter_mut(),
+            })?;
+            Ok((value, Container::TupleStruct(elems)))
         })
     }
 
@@ -341,53 +1363,82 @@ impl<'a, 'de> Deserializer<'de> for Deser<'a> {
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"map");
-        visitor.visit_seq(Seq {
-            hasher: self.hasher,
-            len: 0,
+        self.trace_site(|tracer, format, visit| {
+            let (mut key, mut value) = (Format::Unknown, Format::Unknown);
+            let res = visitor.visit_map(Map {
+                tracer,
+                entry: if visit {
+                    Some((&mut key, &mut value))
+                } else {
+                    None
+                },
+                value: None,
+            });
+            if visit {
+                *format = Format::Map {
+                    key: Box::new(key),
+                    value: Box::new(value),
+                };
+            }
+            res
         })
     }
 
     fn deserialize_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"struct");
-        fields.hash(self.hasher);
-        visitor.visit_seq(Seq {
-            hasher: self.hasher,
-            len: fields.len(),
+        self.trace_struct(name, |tracer| {
+            let mut formats = vec![Format::Unknown; fields.len()];
+            let value = visitor.visit_seq(Seq {
+                tracer,
+                formats: formats.iter_mut(),
+            })?;
+            let fields = fields
+                .iter()
+                .zip(formats)
+                .map(|(name, format)| Named::new(name, format))
+                .collect();
+            Ok((value, Container::Struct(fields)))
         })
     }
 
     fn deserialize_enum<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         variants: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"enum");
-        variants.hash(self.hasher);
-        visitor.visit_enum(Enum {
-            hasher: self.hasher,
-            len: variants.len(),
-        })
+        *self.format = Format::TypeName(name.to_string());
+        let index = self.tracer.enter_enum(name, variants)?;
+
+        let mut format = None;
+        let res = visitor.visit_enum(Enum {
+            tracer: self.tracer,
+            index,
+            format: &mut format,
+        });
+        self.tracer.stack.pop();
+
+        let value = res?;
+        let format = format.ok_or_else(|| Error::custom("enum variant was not visited"))?;
+        self.tracer.record_variant(name, index, format);
+        Ok(value)
     }
 
-    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.hasher.write(b"identifier");
-        visitor.visit_u32(0) // FIXME unsure what this is for??
+        Err(Error::custom("`deserialize_identifier` is not supported"))
     }
 
     fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
@@ -487,4 +1538,367 @@ mod tests {
         different::<S<u8>, S<i8>>();
         same::<S<u8>, S<u8>>();
     }
+
+    #[test]
+    fn nested_type_change() {
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct Inner<T> {
+            a: T,
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct Outer<T> {
+            seq: Vec<T>,
+        }
+
+        different::<Vec<u8>, Vec<u16>>();
+        different::<Option<u8>, Option<u16>>();
+        different::<Vec<Option<u8>>, Vec<Option<i8>>>();
+        different::<Outer<Inner<u8>>, Outer<Inner<f32>>>();
+        different::<Option<Inner<u8>>, Option<Inner<u32>>>();
+        different::<Vec<(u8, u8)>, Vec<(u8, u8, u8)>>();
+        same::<Outer<Inner<u8>>, Outer<Inner<u8>>>();
+    }
+
+    #[test]
+    fn map_types() {
+        use std::collections::{BTreeMap, HashMap};
+
+        different::<BTreeMap<u8, u8>, BTreeMap<u8, u16>>();
+        different::<BTreeMap<u8, u8>, BTreeMap<u16, u8>>();
+        same::<BTreeMap<String, u8>, HashMap<String, u8>>();
+    }
+
+    #[test]
+    fn enum_variants() {
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        enum E1 {
+            A,
+            B(u8),
+            C { x: f32 },
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        enum E2 {
+            A,
+            B(u8),
+            C { x: f64 },
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        enum E3 {
+            A,
+            B(u8),
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        enum E4 {
+            A,
+            B(u16),
+            C { x: f32 },
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        enum E5 {
+            B(u8),
+            A,
+            C { x: f32 },
+        }
+
+        different::<E1, E2>();
+        different::<E1, E3>();
+        different::<E1, E4>();
+        different::<E1, E5>();
+        same::<E1, E1>();
+    }
+
+    #[test]
+    fn nested_enums() {
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        enum Outer<T> {
+            A(Vec<u8>),
+            B(T),
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        enum Inner<T> {
+            X,
+            Y(Option<T>),
+        }
+
+        different::<Outer<Inner<u8>>, Outer<Inner<i8>>>();
+        different::<Vec<Outer<Inner<u8>>>, Vec<Outer<Inner<i8>>>>();
+    }
+
+    #[test]
+    fn recursive_types() {
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct Tree<T> {
+            value: T,
+            children: Vec<Tree<T>>,
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct Linked<T> {
+            value: T,
+            next: Option<Box<Linked<T>>>,
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        enum List<T> {
+            Cons(T, Box<List<T>>),
+            Nil,
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct A {
+            b: Vec<B>,
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct B {
+            a: Option<A>,
+            x: u8,
+        }
+
+        different::<Tree<u8>, Tree<u16>>();
+        different::<Linked<u8>, Linked<u16>>();
+        different::<List<u8>, List<u16>>();
+        different::<Tree<u8>, Linked<u8>>();
+        same::<Tree<u8>, Tree<u8>>();
+        serde_fingerprint::<A>();
+        serde_fingerprint::<B>();
+    }
+
+    #[test]
+    fn type_names_are_ignored() {
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct S1 {
+            a: u8,
+            e: E1,
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct S2 {
+            a: u8,
+            e: E2,
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        enum E1 {
+            A,
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        enum E2 {
+            A,
+        }
+
+        same::<S1, S2>();
+    }
+
+    #[test]
+    fn tracking_message_covers_nested_types() {
+        use crate::data::TrackingMessage;
+
+        let schema = Schema::of::<TrackingMessage>();
+        assert_eq!(schema.root, Format::TypeName("TrackingMessage".into()));
+        for name in [
+            "TrackingMessage",
+            "FaceData",
+            "PersistentId",
+            "Eye",
+            "Image",
+            "Mesh",
+            "Vertex",
+        ] {
+            assert!(schema.types.contains_key(name), "`{name}` was not traced");
+        }
+        let Container::Enum(variants) = &schema.types["PersistentId"] else {
+            panic!("`PersistentId` should be an enum");
+        };
+        assert_eq!(variants.len(), 4);
+        assert_eq!(
+            variants[3],
+            Named::new("Available", VariantFormat::Newtype(Format::Str))
+        );
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Point<T> {
+        x: T,
+        y: T,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    enum Shape<T> {
+        Empty,
+        Circle(Point<T>, f32),
+        Polygon { points: Vec<Point<T>> },
+    }
+
+    #[test]
+    fn display() {
+        let schema = Schema::of::<Option<Shape<u8>>>();
+        assert_eq!(
+            schema.to_string(),
+            "Option<Shape>\n\n\
+             enum Shape {\n    \
+                 Empty,\n    \
+                 Circle(Point, f32),\n    \
+                 Polygon { points: Vec<Point> },\n\
+             }\n\n\
+             struct Point {\n    \
+                 x: u8,\n    \
+                 y: u8,\n\
+             }"
+        );
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let schema = Schema::of::<Shape<u8>>();
+        let json = schema.to_json();
+        assert!(json.contains("\"Polygon\""));
+        let parsed = Schema::from_json(&json).unwrap();
+        assert_eq!(parsed, schema);
+        assert_eq!(parsed.fingerprint(), schema.fingerprint());
+    }
+
+    #[test]
+    fn schema_of_schema() {
+        // `Format` is recursive, so this exercises the recursion guards.
+        let schema = Schema::of::<Format>();
+        let Container::Enum(variants) = &schema.types["Format"] else {
+            panic!("`Format` should be an enum");
+        };
+        assert_eq!(
+            variants[16],
+            Named::new(
+                "option",
+                VariantFormat::Newtype(Format::TypeName("Format".into()))
+            )
+        );
+
+        // `Named<Format>` and `Named<VariantFormat>` have the same name and get conflated, but
+        // that should still produce a usable schema.
+        let schema = Schema::of::<Schema>();
+        assert!(schema.types.contains_key("Container"));
+        schema.fingerprint();
+    }
+
+    #[test]
+    fn diff() {
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct Outer<T> {
+            a: u8,
+            inner: Vec<Option<T>>,
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct Inner1 {
+            b: u32,
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct Inner2 {
+            b: u16,
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct Inner3 {
+            c: u32,
+        }
+
+        let one = Schema::of::<Outer<Inner1>>();
+        assert_eq!(one.diff(&one), []);
+
+        let diff = one.diff(&Schema::of::<Outer<Inner2>>());
+        assert_eq!(diff.len(), 1, "{diff:?}");
+        assert_eq!(diff[0].path, "Outer.inner[]?.b");
+        assert_eq!(
+            diff[0].to_string(),
+            "`Outer.inner[]?.b`: expected `u32`, found `u16`"
+        );
+
+        let diff = one.diff(&Schema::of::<Outer<Inner3>>());
+        assert_eq!(diff.len(), 1, "{diff:?}");
+        assert_eq!(diff[0].path, "Outer.inner[]?");
+        assert_eq!(diff[0].expected, "field `b`");
+        assert_eq!(diff[0].found, "field `c`");
+
+        let diff = Schema::of::<Shape<u8>>().diff(&Schema::of::<Shape<i8>>());
+        let paths = diff.iter().map(|d| &*d.path).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "Shape::Circle.0.x",
+                "Shape::Circle.0.y",
+                "Shape::Polygon.points[].x",
+                "Shape::Polygon.points[].y",
+            ]
+        );
+    }
+
+    #[test]
+    fn fnv1a_reference_values() {
+        fn fnv1a(bytes: &[u8]) -> u64 {
+            let mut hasher = Fnv1a::new();
+            hasher.write(bytes);
+            hasher.finish()
+        }
+
+        // Test vectors from the FNV reference implementation.
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn golden_primitives() {
+        // These are independent of the Rust version and platform.
+        assert_eq!(serde_fingerprint::<u8>(), 0x9c88c3d328cbe24a);
+        assert_eq!(
+            serde_fingerprint::<Vec<Option<String>>>(),
+            0x7d7a8f828071cb20
+        );
+    }
+
+    /// Pins the fingerprint of the protocol's [`TrackingMessage`].
+    ///
+    /// If this test fails, the wire format of the protocol has changed, and deployed trackers and
+    /// clients will no longer be able to talk to new ones. If that is intentional, update the
+    /// expected value.
+    ///
+    /// [`TrackingMessage`]: crate::data::TrackingMessage
+    #[test]
+    fn golden_tracking_message() {
+        use crate::data::TrackingMessage;
+
+        assert_eq!(serde_fingerprint::<TrackingMessage>(), 0x15d93de22086d6c6);
+    }
 }
@@ -0,0 +1,454 @@
+//! The connection handshake between trackers and clients.
+//!
+//! After connecting, the client sends a [`Hello`] to the tracker. The tracker checks that both
+//! sides speak the same protocol and replies with a [`Response`]: either [`Response::Accept`]
+//! carrying the tracker's own [`Hello`], or [`Response::Reject`] explaining why the connection was
+//! refused. If the connection was accepted, the tracker then streams length-prefixed
+//! [`TrackingMessage`] frames (see [`Codec::write`]).
+//!
+//! Optional features are negotiated via [`Hello::capabilities`]: the client lists the
+//! capabilities it would like to use in order of preference, the tracker lists everything it
+//! supports, and both sides pick the first of the client's capabilities the tracker supports. This
+//! is how the [`Codec`] of the frames and the [`ImageEncoding`] of eye textures are chosen. Without
+//! a match, [`Codec::Bincode`] and [`ImageEncoding::Rgba`] are used.
+//!
+//! [`Codec`]: crate::data::Codec
+//! [`Codec::write`]: crate::data::Codec::write
+//! [`Codec::Bincode`]: crate::data::Codec::Bincode
+//! [`ImageEncoding`]: crate::data::ImageEncoding
+//! [`ImageEncoding::Rgba`]: crate::data::ImageEncoding::Rgba
+//!
+//! Every handshake frame has the following layout:
+//!
+//! | Offset | Size | Contents                                  |
+//! |--------|------|-------------------------------------------|
+//! | 0      | 4    | [`MAGIC`]                                 |
+//! | 4      | 4    | [`PROTOCOL_VERSION`] (little-endian)      |
+//! | 8      | 4    | payload length in bytes (little-endian)   |
+//! | 12     | *n*  | payload ([`Hello`] or [`Response`], bincode-encoded) |
+//!
+//! The protocol version comes before the payload, so that peers speaking different versions can
+//! reject each other without having to decode a payload whose layout they don't know. The layout
+//! of [`Response::Reject`] is kept the same across all protocol versions for the same reason.
+
+use std::{fmt, io, time::Duration};
+
+use async_io::Timer;
+use futures_lite::{
+    future,
+    io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    data::{convert_error, TrackingMessage},
+    fingerprint::Schema,
+};
+
+/// Magic bytes at the start of every handshake frame.
+pub const MAGIC: [u8; 4] = *b"PRVD";
+
+/// The version of the connection protocol implemented by this library.
+///
+/// This is incremented whenever the handshake or the framing of messages changes. Changes to the
+/// structure of [`TrackingMessage`] are detected via [`TrackingMessage::fingerprint`] instead.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Maximum size of a handshake payload.
+const MAX_PAYLOAD_SIZE: u32 = 1024 * 1024;
+
+/// How long to wait for the peer's handshake frame before giving up.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Information a peer sends about itself when establishing a connection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    /// The protocol version spoken by the peer.
+    ///
+    /// This is transmitted in the frame header rather than in the payload.
+    #[serde(skip)]
+    pub version: u32,
+    /// The peer's [`TrackingMessage::fingerprint`].
+    pub fingerprint: u64,
+    /// A human-readable name identifying the peer (for trackers, the advertised instance name).
+    pub name: String,
+    /// Optional protocol features supported by the peer.
+    pub capabilities: Vec<String>,
+}
+
+impl Hello {
+    /// Creates a [`Hello`] for the protocol implemented by this library.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            fingerprint: TrackingMessage::fingerprint(),
+            name: name.into(),
+            capabilities: Vec::new(),
+        }
+    }
+
+    /// Returns a [`bool`] indicating whether the peer announced support for `capability`.
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Checks whether a connection to the peer that sent this [`Hello`] can be established.
+    pub fn check(&self) -> Result<(), Rejection> {
+        if self.version != PROTOCOL_VERSION {
+            return Err(Rejection {
+                reason: format!(
+                    "unsupported protocol version {} (expected version {})",
+                    self.version, PROTOCOL_VERSION,
+                ),
+                schema: None,
+            });
+        }
+        if self.fingerprint != TrackingMessage::fingerprint() {
+            return Err(Rejection {
+                reason: format!(
+                    "message fingerprint mismatch (expected {:016x}, got {:016x})",
+                    TrackingMessage::fingerprint(),
+                    self.fingerprint,
+                ),
+                schema: Some(TrackingMessage::schema().to_json()),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The tracker's reply to a client's [`Hello`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Response {
+    /// The connection was refused, and will be closed by the tracker.
+    ///
+    /// This variant and its payload are encoded the same way in every protocol version.
+    Reject(Rejection),
+    /// The connection was accepted. [`TrackingMessage`]s will follow.
+    Accept(Hello),
+}
+
+/// The reason a connection was refused.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rejection {
+    /// Human-readable reason for the rejection.
+    pub reason: String,
+    /// The rejecting peer's [`TrackingMessage::schema`] as JSON, if the rejection was caused by a
+    /// fingerprint mismatch.
+    pub schema: Option<String>,
+}
+
+impl Rejection {
+    /// Returns the places where the rejecting peer's schema differs from ours, if available.
+    pub fn differences(&self) -> Option<Vec<crate::fingerprint::Difference>> {
+        let schema = Schema::from_json(self.schema.as_ref()?).ok()?;
+        Some(TrackingMessage::schema().diff(&schema))
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.reason)?;
+        if let Some(differences) = self.differences() {
+            for difference in differences {
+                write!(f, "\n- {difference}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<Rejection> for io::Error {
+    fn from(rejection: Rejection) -> Self {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("connection rejected: {rejection}"),
+        )
+    }
+}
+
+/// Performs the client side of the handshake.
+///
+/// Returns the tracker's [`Hello`] if the connection was accepted.
+pub(crate) async fn client<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    hello: &Hello,
+) -> io::Result<Hello> {
+    write_frame(&mut *stream, hello).await?;
+    let (version, payload) = with_timeout(read_frame(&mut *stream)).await?;
+    match bincode::deserialize::<Response>(&payload) {
+        Ok(Response::Reject(rejection)) => Err(rejection.into()),
+        Ok(Response::Accept(_)) | Err(_) if version != PROTOCOL_VERSION => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "tracker speaks unsupported protocol version {version} (expected version {PROTOCOL_VERSION})"
+            ),
+        )),
+        Ok(Response::Accept(mut tracker)) => {
+            tracker.version = version;
+            // The tracker should have rejected us in this case, but let's not rely on that.
+            tracker.check()?;
+            Ok(tracker)
+        }
+        Err(e) => Err(convert_error(e)),
+    }
+}
+
+/// Performs the tracker side of the handshake.
+///
+/// Returns the client's [`Hello`] if the connection was accepted. If it was rejected, the
+/// [`Rejection`] is sent to the client and returned as an error.
+pub(crate) async fn server<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    hello: &Hello,
+) -> io::Result<Hello> {
+    let (version, payload) = with_timeout(read_frame(&mut *stream)).await?;
+    let client = if version == PROTOCOL_VERSION {
+        decode::<Hello>(&payload)
+            .map(|client| Hello { version, ..client })
+            .map_err(|e| Rejection {
+                reason: format!("malformed hello: {e}"),
+                schema: None,
+            })
+    } else {
+        Ok(Hello {
+            version,
+            fingerprint: 0,
+            name: String::new(),
+            capabilities: Vec::new(),
+        })
+    };
+
+    match client.and_then(|client| client.check().map(|()| client)) {
+        Ok(client) => {
+            write_frame(&mut *stream, &Response::Accept(hello.clone())).await?;
+            Ok(client)
+        }
+        Err(rejection) => {
+            write_frame(&mut *stream, &Response::Reject(rejection.clone())).await?;
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("rejected client: {}", rejection.reason),
+            ))
+        }
+    }
+}
+
+async fn with_timeout<T>(fut: impl future::Future<Output = io::Result<T>>) -> io::Result<T> {
+    future::or(fut, async {
+        Timer::after(TIMEOUT).await;
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "timed out waiting for handshake",
+        ))
+    })
+    .await
+}
+
+fn decode<T: DeserializeOwned>(payload: &[u8]) -> io::Result<T> {
+    bincode::deserialize(payload).map_err(convert_error)
+}
+
+async fn write_frame<W: AsyncWrite + Unpin, T: Serialize>(
+    mut writer: W,
+    payload: &T,
+) -> io::Result<()> {
+    let payload = bincode::serialize(payload).map_err(convert_error)?;
+    let mut buf = Vec::with_capacity(12 + payload.len());
+    buf.extend_from_slice(&MAGIC);
+    buf.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    buf.extend_from_slice(&u32::try_from(payload.len()).unwrap().to_le_bytes());
+    buf.extend_from_slice(&payload);
+    writer.write_all(&buf).await?;
+    writer.flush().await
+}
+
+/// Reads a handshake frame, returning the peer's protocol version and the raw payload.
+async fn read_frame<R: AsyncRead + Unpin>(mut reader: R) -> io::Result<(u32, Vec<u8>)> {
+    let mut header = [0; 12];
+    reader.read_exact(&mut header).await?;
+    if header[..4] != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "peer did not send a handshake (is it using an outdated protocol?)",
+        ));
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    let size = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    if size > MAX_PAYLOAD_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("handshake payload too large ({size} bytes)"),
+        ));
+    }
+
+    let mut payload = vec![0; size as usize];
+    reader.read_exact(&mut payload).await?;
+    Ok((version, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::{future::block_on, io::Cursor};
+
+    use super::*;
+
+    /// An in-memory stream that reads from `input` and writes to `output`.
+    struct Pipe {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Pipe {
+        fn new(input: Vec<u8>) -> Self {
+            Self {
+                input: Cursor::new(input),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl AsyncRead for Pipe {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut [u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            std::pin::Pin::new(&mut self.input).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Pipe {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            std::pin::Pin::new(&mut self.output).poll_write(cx, buf)
+        }
+
+        fn poll_flush(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::pin::Pin::new(&mut self.output).poll_flush(cx)
+        }
+
+        fn poll_close(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::pin::Pin::new(&mut self.output).poll_close(cx)
+        }
+    }
+
+    fn frame<T: Serialize>(version: u32, payload: &T) -> Vec<u8> {
+        let payload = bincode::serialize(payload).unwrap();
+        let mut buf = MAGIC.to_vec();
+        buf.extend_from_slice(&version.to_le_bytes());
+        buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&payload);
+        buf
+    }
+
+    /// Runs the server side of the handshake on `input` and returns the result and the response.
+    fn serve(input: Vec<u8>) -> (io::Result<Hello>, Response) {
+        let mut pipe = Pipe::new(input);
+        let res = block_on(server(&mut pipe, &Hello::new("tracker")));
+        let mut output = Cursor::new(pipe.output);
+        let (version, payload) = block_on(read_frame(&mut output)).unwrap();
+        assert_eq!(version, PROTOCOL_VERSION);
+        (res, decode(&payload).unwrap())
+    }
+
+    #[test]
+    fn accept() {
+        let mut hello = Hello::new("client");
+        hello.capabilities.push("test".into());
+        let (res, response) = serve(frame(PROTOCOL_VERSION, &hello));
+        let client = res.unwrap();
+        assert_eq!(client, hello);
+        assert!(client.has_capability("test"));
+        let Response::Accept(tracker) = response else {
+            panic!("expected acceptance, got {response:?}");
+        };
+        assert_eq!(tracker.name, "tracker");
+        assert_eq!(tracker.fingerprint, TrackingMessage::fingerprint());
+    }
+
+    #[test]
+    fn reject_fingerprint() {
+        let mut hello = Hello::new("client");
+        hello.fingerprint ^= 1;
+        let (res, response) = serve(frame(PROTOCOL_VERSION, &hello));
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let Response::Reject(rejection) = response else {
+            panic!("expected rejection, got {response:?}");
+        };
+        assert!(rejection.reason.contains("fingerprint mismatch"));
+        // The schema is the same, so there are no differences to report.
+        assert_eq!(rejection.differences(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn reject_version() {
+        // Payloads of other versions might be undecodable, so they must not be parsed.
+        let (res, response) = serve(frame(PROTOCOL_VERSION + 1, &"garbage"));
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let Response::Reject(rejection) = response else {
+            panic!("expected rejection, got {response:?}");
+        };
+        assert!(rejection.reason.contains("unsupported protocol version"));
+    }
+
+    #[test]
+    fn reject_malformed() {
+        let (res, response) = serve(frame(PROTOCOL_VERSION, &0u8));
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let Response::Reject(rejection) = response else {
+            panic!("expected rejection, got {response:?}");
+        };
+        assert!(
+            rejection.reason.starts_with("malformed hello"),
+            "{rejection}"
+        );
+    }
+
+    #[test]
+    fn client_rejected() {
+        let rejection = Rejection {
+            reason: "go away".into(),
+            schema: None,
+        };
+        // Rejections must be understood regardless of the tracker's protocol version.
+        for version in [PROTOCOL_VERSION, PROTOCOL_VERSION + 1] {
+            let mut pipe = Pipe::new(frame(version, &Response::Reject(rejection.clone())));
+            let err = block_on(client(&mut pipe, &Hello::new("client"))).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains("go away"), "{err}");
+        }
+    }
+
+    #[test]
+    fn client_accepted() {
+        let mut pipe = Pipe::new(frame(
+            PROTOCOL_VERSION,
+            &Response::Accept(Hello::new("tracker")),
+        ));
+        let tracker = block_on(client(&mut pipe, &Hello::new("client"))).unwrap();
+        assert_eq!(tracker.name, "tracker");
+
+        let mut output = Cursor::new(pipe.output);
+        let (version, payload) = block_on(read_frame(&mut output)).unwrap();
+        assert_eq!(version, PROTOCOL_VERSION);
+        assert_eq!(decode::<Hello>(&payload).unwrap().name, "client");
+    }
+
+    #[test]
+    fn not_a_handshake() {
+        let mut pipe = Pipe::new(vec![0; 64]);
+        let err = block_on(server(&mut pipe, &Hello::new("tracker"))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(pipe.output.is_empty());
+    }
+}
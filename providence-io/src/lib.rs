@@ -1,6 +1,14 @@
+pub mod convert;
 pub mod data;
+pub mod discovery;
+pub mod edit;
+pub mod fingerprint;
+pub mod handshake;
 pub mod net;
+pub mod recording;
+pub mod replay;
 pub mod task;
 
 mod drop;
-mod fingerprint;
+mod mdns;
+mod texture;
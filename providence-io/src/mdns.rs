@@ -0,0 +1,291 @@
+//! mDNS advertising of trackers, over IPv4 and IPv6.
+//!
+//! `uwuhi`'s advertiser only listens on IPv4, so both families are served by our own loop around
+//! its I/O-less [`Advertiser`]. The advertised records are the same on both: clients receive the
+//! A and AAAA records of the tracker no matter which family they ask over.
+//!
+//! Discovery lives in [`crate::discovery`].
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+};
+
+use async_io::Async;
+use futures_lite::future;
+use socket2::{Domain, Protocol, Socket, Type};
+use tracing::debug;
+use uwuhi_async::{
+    name::Label,
+    packet::records::TXT,
+    service::{
+        advertising::Advertiser, InstanceDetails, ServiceInstance, ServiceTransport, TxtRecords,
+    },
+    MDNS_BUFFER_SIZE,
+};
+
+use crate::{net::CameraInfo, task::Task};
+
+pub(crate) const SERVICE: &str = "_providence";
+
+/// An IP address family mDNS is used over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    pub(crate) const ALL: [Self; 2] = [Self::V4, Self::V6];
+
+    /// Returns the mDNS multicast group of this family.
+    pub(crate) fn group(self) -> SocketAddr {
+        match self {
+            Self::V4 => (Ipv4Addr::new(224, 0, 0, 251), 5353).into(),
+            Self::V6 => (Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb), 5353).into(),
+        }
+    }
+}
+
+/// Returns whether `ip` is an IPv6 link-local address.
+///
+/// These are only meaningful together with the interface they belong to, which DNS records can't
+/// convey, so they are neither advertised nor connected to.
+pub(crate) fn is_link_local_v6(ip: &IpAddr) -> bool {
+    matches!(ip, IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80)
+}
+
+/// Metadata a tracker advertises about itself in its TXT record.
+///
+/// This lets clients pick a tracker before connecting to it. Every field is optional, since older
+/// trackers don't advertise any metadata.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Metadata {
+    pub(crate) version: Option<u32>,
+    pub(crate) fingerprint: Option<u64>,
+    pub(crate) friendly_name: Option<String>,
+    pub(crate) camera: Option<CameraInfo>,
+    pub(crate) capabilities: Vec<String>,
+    pub(crate) eye_textures: bool,
+}
+
+impl Metadata {
+    /// Version of the TXT record format.
+    const TXT_VERSION: &'static str = "1";
+
+    /// Encodes the metadata as TXT record entries.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if an entry exceeds the 255 bytes DNS allows.
+    pub(crate) fn to_txt(&self) -> io::Result<Vec<String>> {
+        let mut entries = vec![format!("txtvers={}", Self::TXT_VERSION)];
+        if let Some(version) = self.version {
+            entries.push(format!("proto={version}"));
+        }
+        if let Some(fingerprint) = self.fingerprint {
+            entries.push(format!("fp={fingerprint:016x}"));
+        }
+        if let Some(name) = &self.friendly_name {
+            entries.push(format!("name={name}"));
+        }
+        if let Some(camera) = &self.camera {
+            entries.push(format!("cam={}x{}", camera.width, camera.height));
+            entries.push(format!("fps={}", camera.fps));
+        }
+        if !self.capabilities.is_empty() {
+            entries.push(format!("caps={}", self.capabilities.join(",")));
+        }
+        if self.eye_textures {
+            entries.push(String::from("textures"));
+        }
+
+        if let Some(entry) = entries.iter().find(|entry| entry.len() > 255) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("advertised metadata is too long: `{entry}` (max. 255 bytes)"),
+            ));
+        }
+        Ok(entries)
+    }
+
+    /// Decodes the metadata from TXT record entries.
+    ///
+    /// Malformed and unknown entries are ignored.
+    pub(crate) fn from_txt<'a>(entries: impl Iterator<Item = &'a [u8]>) -> Self {
+        let mut metadata = Self::default();
+        let (mut resolution, mut fps) = (None, None);
+        for entry in entries {
+            let entry = String::from_utf8_lossy(entry);
+            let (key, value) = entry.split_once('=').unwrap_or((&entry, ""));
+            match &*key.to_ascii_lowercase() {
+                "proto" => metadata.version = value.parse().ok(),
+                "fp" => metadata.fingerprint = u64::from_str_radix(value, 16).ok(),
+                "name" => metadata.friendly_name = Some(value.to_owned()),
+                "cam" => {
+                    resolution = value
+                        .split_once('x')
+                        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+                }
+                "fps" => fps = value.parse().ok(),
+                "caps" => {
+                    metadata.capabilities = value
+                        .split(',')
+                        .filter(|cap| !cap.is_empty())
+                        .map(String::from)
+                        .collect();
+                }
+                "textures" => metadata.eye_textures = true,
+                _ => {}
+            }
+        }
+        if let (Some((width, height)), Some(fps)) = (resolution, fps) {
+            metadata.camera = Some(CameraInfo { width, height, fps });
+        }
+        metadata
+    }
+}
+
+/// Advertises a tracker called `name`, reachable on `addrs` and `port`.
+///
+/// The tracker is advertised on every family that is available. Fails if it can't be advertised on
+/// any.
+pub(crate) fn advertise(
+    name: &Label,
+    addrs: &[IpAddr],
+    port: u16,
+    metadata: &Metadata,
+) -> io::Result<Task<io::Result<()>>> {
+    let txt = metadata.to_txt()?;
+
+    let mut sockets = Vec::new();
+    let mut last_error = None;
+    for family in Family::ALL {
+        match socket(family) {
+            Ok(socket) => sockets.push(Async::new(socket)?),
+            Err(e) => {
+                debug!("cannot advertise via mDNS over {family:?}: {e}");
+                last_error = Some(e);
+            }
+        }
+    }
+    if sockets.is_empty() {
+        return Err(last_error.unwrap());
+    }
+
+    let mut advertisers = Vec::new();
+    for socket in sockets {
+        let (first, rest) = addrs.split_first().expect("no addresses to advertise");
+        let mut advertiser = Advertiser::new(name.clone(), *first)?;
+        for &addr in rest {
+            advertiser.add_name(name.clone(), addr);
+        }
+        let mut details = InstanceDetails::new(format!("{name}.local").parse().unwrap(), port);
+        *details.txt_records_mut() =
+            TxtRecords::from_txt(&TXT::new(txt.iter().map(|entry| entry.as_bytes())));
+        advertiser.add_instance(
+            ServiceInstance::new(name.clone(), Label::new(SERVICE), ServiceTransport::TCP),
+            details,
+        );
+        advertisers.push(serve(advertiser, socket));
+    }
+
+    Ok(Task::spawn(async move {
+        let mut advertisers = advertisers.into_iter();
+        let first = advertisers.next().unwrap();
+        match advertisers.next() {
+            Some(second) => future::or(first, second).await,
+            None => first.await,
+        }
+    }))
+}
+
+/// Creates a socket listening for mDNS queries of the given family.
+fn socket(family: Family) -> io::Result<UdpSocket> {
+    let socket = match family {
+        Family::V4 => Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?,
+        Family::V6 => Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?,
+    };
+    // Other mDNS responders on the system listen on the same port.
+    socket.set_reuse_address(true)?;
+    match family.group() {
+        SocketAddr::V4(group) => {
+            socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 5353)).into())?;
+            socket.join_multicast_v4(group.ip(), &Ipv4Addr::UNSPECIFIED)?;
+        }
+        SocketAddr::V6(group) => {
+            socket.set_only_v6(true)?;
+            socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, 5353)).into())?;
+            // IPv6 multicast groups have to be joined on every interface separately.
+            let mut indices = if_addrs::get_if_addrs()?
+                .into_iter()
+                .filter(|interface| interface.ip().is_ipv6())
+                .filter_map(|interface| interface.index)
+                .collect::<Vec<_>>();
+            indices.dedup();
+            let joined = indices
+                .into_iter()
+                .filter(|&index| socket.join_multicast_v6(group.ip(), index).is_ok())
+                .count();
+            if joined == 0 {
+                socket.join_multicast_v6(group.ip(), 0)?;
+            }
+        }
+    }
+    Ok(socket.into())
+}
+
+/// Answers the mDNS queries received on `socket`.
+async fn serve(mut advertiser: Advertiser, socket: Async<UdpSocket>) -> io::Result<()> {
+    let mut buf = [0; MDNS_BUFFER_SIZE];
+    loop {
+        let (len, addr) = socket.recv_from(&mut buf).await?;
+        match advertiser.handle_packet(&buf[..len]) {
+            Ok(Some(response)) => {
+                socket.send_to(response, addr).await?;
+            }
+            Ok(None) => {}
+            Err(e) => debug!("failed to handle mDNS packet from {addr}: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        let metadata = Metadata {
+            version: Some(1),
+            fingerprint: Some(0x0123456789abcdef),
+            friendly_name: Some(String::from("Désirée's tracker = best")),
+            camera: Some(CameraInfo {
+                width: 640,
+                height: 480,
+                fps: 29.97,
+            }),
+            capabilities: vec![String::from("codec/bincode"), String::from("image/qoi")],
+            eye_textures: true,
+        };
+        let txt = metadata.to_txt().unwrap();
+        assert_eq!(
+            Metadata::from_txt(txt.iter().map(|e| e.as_bytes())),
+            metadata
+        );
+
+        // Old trackers send an empty TXT record.
+        assert_eq!(
+            Metadata::from_txt([&b""[..]].into_iter()),
+            Metadata::default()
+        );
+        // Incomplete and unknown entries are ignored.
+        let entries: [&[u8]; 4] = [b"cam=640x", b"fps=30", b"fp=xyz", b"future=1"];
+        assert_eq!(Metadata::from_txt(entries.into_iter()), Metadata::default());
+
+        let metadata = Metadata {
+            friendly_name: Some("x".repeat(251)),
+            ..Metadata::default()
+        };
+        let err = metadata.to_txt().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,82 +1,119 @@
 use std::{
-    io,
-    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream},
-    ops::ControlFlow,
-    sync::Arc,
+    collections::VecDeque,
+    convert::Infallible,
+    env, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex, OnceLock},
     time::Duration,
 };
 
-use async_io::Async;
+use async_io::{Async, Timer};
+use futures_lite::{future, AsyncWriteExt as _};
 use pawawwewism::reactive::{Disconnected, Reader, Value};
-use tracing::{debug, info};
-use uwuhi_async::{
-    name::Label,
-    resolver::{AsyncResolver, SyncResolver},
-    service::{
-        advertising::AsyncAdvertiser,
-        discovery::{AsyncDiscoverer, SyncDiscoverer},
-        InstanceDetails, Service, ServiceInstance, ServiceTransport,
-    },
+use socket2::{Domain, Socket, Type};
+use tracing::{debug, info, warn};
+use uwuhi_async::name::Label;
+
+use crate::{
+    data::{Codec, DecodeLimits, ImageEncoding, TrackingMessage},
+    discovery::{self, DiscoveredTracker},
+    drop::defer,
+    handshake::{self, Hello},
+    mdns,
+    task::Task,
 };
 
-use crate::{data::TrackingMessage, drop::defer, task::Task};
+/// How long [`Subscriber::autoconnect_blocking`] waits for a tracker to be discovered.
+const AUTOCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
 
-const SERVICE: &str = "_providence";
+/// How long a reconnecting [`Subscriber`] waits for its tracker to be discovered again.
+const REDISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
 
+/// Publishes [`TrackingMessage`]s to the clients connected to it.
+///
+/// Use [`Publisher::spawn`] for the defaults, or a [`PublisherBuilder`] to configure where it
+/// accepts connections and how it advertises itself.
 pub struct Publisher {
     port: u16,
-    message: Value<Option<Arc<TrackingMessage>>>,
+    message: Value<Option<Arc<Outgoing>>>,
     connections_reader: Reader<usize>,
-    _advertiser: Task<io::Result<()>>,
+    eye_textures: bool,
+    _advertiser: Option<Task<io::Result<()>>>,
     _listener: Task<io::Result<()>>,
 }
 
 impl Publisher {
+    /// Spawns a [`Publisher`] with the default [`PublisherBuilder`] settings.
     pub fn spawn() -> io::Result<Self> {
-        let local_addrs = if_addrs::get_if_addrs()?
-            .into_iter()
-            .filter_map(|interface| match interface.ip() {
-                IpAddr::V4(ip) if ip.is_private() => Some(ip),
-                _ => None,
-            })
-            .collect::<Vec<_>>();
-
-        info!("local private network addresses: {:?}", local_addrs);
-        let (&first_addr, more_addrs) = match &*local_addrs {
-            [first, rest @ ..] => (first, rest),
-            _ => {
+        PublisherBuilder::new().spawn()
+    }
+
+    /// Spawns a [`Publisher`] that accepts connections on the given TCP `port`.
+    ///
+    /// A `port` of 0 lets the operating system pick a free port, like [`Publisher::spawn`] does.
+    pub fn spawn_on_port(port: u16) -> io::Result<Self> {
+        PublisherBuilder::new().with_port(port).spawn()
+    }
+
+    /// Returns a [`PublisherBuilder`] for configuring a [`Publisher`].
+    pub fn builder() -> PublisherBuilder {
+        PublisherBuilder::new()
+    }
+
+    fn spawn_with(builder: PublisherBuilder) -> io::Result<Self> {
+        let tcp_listener = builder.listen()?;
+        let local_addr = tcp_listener.local_addr()?;
+        let port = local_addr.port();
+
+        let advertised = if builder.mdns {
+            let interfaces = if_addrs::get_if_addrs()?
+                .into_iter()
+                .map(|interface| (interface.name.clone(), interface.ip()));
+            let addrs = builder.advertised_addrs(local_addr.ip(), interfaces);
+            if addrs.is_empty() {
                 return Err(io::Error::new(
                     io::ErrorKind::AddrNotAvailable,
-                    "no local network interface with private IPv4 address found",
+                    "no IP address to advertise the tracker on (check the selected interfaces)",
                 ));
             }
+            addrs
+        } else {
+            Vec::new()
         };
+        info!("advertised network addresses: {:?}", advertised);
 
-        // Bind to 0.0.0.0 so that we're available from all IPs the system has.
-        let tcp_listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))?;
-        let port = match tcp_listener.local_addr()? {
-            SocketAddr::V4(addr) => addr.port(),
-            SocketAddr::V6(_) => unreachable!(), // we listened on a V4 address
+        let name = match (&builder.name, advertised.first()) {
+            (Some(name), _) => name.clone(),
+            (None, Some(addr)) => format!("providence-{addr}").replace(['.', ':'], "-"),
+            (None, None) => String::from("providence"),
         };
+        let label = instance_label(&name)?;
 
-        let name: Label = format!("providence-{first_addr}")
-            .replace('.', "-")
-            .parse()
-            .unwrap();
-        let mut advertiser = AsyncAdvertiser::new(name.clone(), first_addr.into())?;
-        for &addr in more_addrs {
-            advertiser.add_name(name.clone(), addr.into());
-        }
-        advertiser.add_instance(
-            ServiceInstance::new(name.clone(), Label::new(SERVICE), ServiceTransport::TCP),
-            InstanceDetails::new(format!("{name}.local").parse().unwrap(), port),
-        );
+        let hello = Arc::new(Hello {
+            capabilities: capabilities(&Codec::ALL, &ImageEncoding::ALL),
+            ..Hello::new(name)
+        });
+        let metadata = mdns::Metadata {
+            version: Some(hello.version),
+            fingerprint: Some(hello.fingerprint),
+            friendly_name: builder.friendly_name.clone(),
+            camera: builder.camera,
+            capabilities: hello.capabilities.clone(),
+            eye_textures: builder.eye_textures,
+        };
+        // Also validates the metadata when mDNS is disabled.
+        metadata.to_txt()?;
+
+        let advertiser = if advertised.is_empty() {
+            None
+        } else {
+            Some(mdns::advertise(&label, &advertised, port, &metadata)?)
+        };
 
-        let message: Value<Option<Arc<TrackingMessage>>> = Value::new(None);
+        let message: Value<Option<Arc<Outgoing>>> = Value::new(None);
         let message_reader = message.reader();
         let connections = Value::new(0);
         let connections_reader = connections.reader();
-        let advertiser = Task::spawn(async move { advertiser.listen().await });
         let listener = Task::spawn(async move {
             // (contains `Task`s so that they make progress without us polling them)
             let mut streams = Vec::<Task<_>>::new();
@@ -84,21 +121,38 @@ impl Publisher {
 
             loop {
                 let (mut stream, sockaddr) = listener.accept().await?;
-                info!("client connected: {}", sockaddr);
+                debug!("incoming connection from {}", sockaddr);
 
                 // Clean up periodically to avoid unbounded memory growth.
                 streams.retain(|task| !task.is_finished());
 
                 let mut message_reader = message_reader.clone();
                 let mut connections = connections.clone();
+                let hello = hello.clone();
                 streams.push(Task::spawn(async move {
+                    let client = match handshake::server(&mut stream, &hello).await {
+                        Ok(client) => client,
+                        Err(e) => {
+                            warn!("handshake with {} failed: {}", sockaddr, e);
+                            return Ok(());
+                        }
+                    };
+                    let codec = negotiate(&client, &hello, Codec::from_capability)
+                        .unwrap_or(Codec::Bincode);
+                    let encoding = negotiate(&client, &hello, ImageEncoding::from_capability)
+                        .unwrap_or(ImageEncoding::Rgba);
+                    info!(
+                        "client `{}` connected: {} (codec: {:?}, image encoding: {:?})",
+                        client.name, sockaddr, codec, encoding,
+                    );
+
                     connections.modify(|mut c| *c += 1);
                     let _fin = defer(|| connections.modify(|mut c| *c -= 1));
 
                     // If there's an existing message available, send it to the client immediately.
                     if let Ok(Some(msg)) = message_reader.get() {
                         debug!("sending existing message to client");
-                        msg.async_write(&mut stream).await?;
+                        stream.write_all(&msg.frame(codec, encoding)?).await?;
                     }
 
                     loop {
@@ -107,7 +161,7 @@ impl Publisher {
                             Ok(None) => continue,
                             Err(_) => break,
                         };
-                        msg.async_write(&mut stream).await?;
+                        stream.write_all(&msg.frame(codec, encoding)?).await?;
                     }
                     Ok::<(), io::Error>(())
                 }));
@@ -118,14 +172,24 @@ impl Publisher {
             port,
             message,
             connections_reader,
+            eye_textures: builder.eye_textures,
             _advertiser: advertiser,
             _listener: listener,
         })
     }
 
     /// Updates the [`TrackingMessage`] that is sent to connected clients.
-    pub fn publish(&mut self, message: TrackingMessage) {
-        self.message.set(Some(Arc::new(message)));
+    ///
+    /// If the [`Publisher`] was configured to not send eye textures, they are removed from the
+    /// message.
+    pub fn publish(&mut self, mut message: TrackingMessage) {
+        if !self.eye_textures {
+            message.strip_textures();
+        }
+        self.message.set(Some(Arc::new(Outgoing {
+            message,
+            frames: Mutex::new(Vec::new()),
+        })));
     }
 
     /// Clears the stored tracking message.
@@ -159,108 +223,664 @@ impl Publisher {
     }
 }
 
+/// Configures and spawns a [`Publisher`].
+///
+/// By default, the [`Publisher`] accepts connections on all IPv4 and IPv6 addresses, on a port
+/// picked by the operating system, and advertises itself via mDNS on the private addresses of the
+/// local network interfaces (IPv4 private and IPv6 unique local addresses). If there are none, it
+/// falls back to the public addresses, and then to the loopback addresses.
+#[derive(Debug, Clone)]
+pub struct PublisherBuilder {
+    bind: Option<IpAddr>,
+    port: u16,
+    interfaces: Vec<String>,
+    excluded_interfaces: Vec<String>,
+    name: Option<String>,
+    friendly_name: Option<String>,
+    camera: Option<CameraInfo>,
+    eye_textures: bool,
+    mdns: bool,
+}
+
+impl PublisherBuilder {
+    /// Creates a [`PublisherBuilder`] with the default settings.
+    pub fn new() -> Self {
+        Self {
+            bind: None,
+            port: 0,
+            interfaces: Vec::new(),
+            excluded_interfaces: Vec::new(),
+            name: None,
+            friendly_name: None,
+            camera: None,
+            eye_textures: true,
+            mdns: true,
+        }
+    }
+
+    /// Sets the IP address to accept connections on.
+    ///
+    /// If this is not an unspecified address, it is also the only address that is advertised.
+    /// Binding to `0.0.0.0` accepts IPv4 connections only, while `::` accepts only IPv6
+    /// connections. The default accepts both.
+    pub fn with_bind_address(mut self, ip: IpAddr) -> Self {
+        self.bind = Some(ip);
+        self
+    }
+
+    /// Sets the TCP port to accept connections on.
+    ///
+    /// A `port` of 0 lets the operating system pick a free port, which is the default.
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Advertises the [`Publisher`] only on the network interfaces with the given names (like
+    /// `eth0` or `tun0`).
+    ///
+    /// All addresses of these interfaces are advertised, whether they are private or not (except
+    /// for IPv6 link-local addresses, which are never advertised).
+    pub fn with_interfaces<S: AsRef<str>>(mut self, names: &[S]) -> Self {
+        self.interfaces = names.iter().map(|name| name.as_ref().to_owned()).collect();
+        self
+    }
+
+    /// Never advertises the [`Publisher`] on the network interfaces with the given names.
+    pub fn without_interfaces<S: AsRef<str>>(mut self, names: &[S]) -> Self {
+        self.excluded_interfaces = names.iter().map(|name| name.as_ref().to_owned()).collect();
+        self
+    }
+
+    /// Sets the instance name the [`Publisher`] advertises, and announces to clients in its
+    /// [`Hello`].
+    ///
+    /// The name must be a valid DNS label: between 1 and 63 bytes long, without any dots. By
+    /// default, it is derived from the first advertised address (for example,
+    /// `providence-192-168-0-2`).
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets a human-readable name for the tracker, which is advertised via mDNS.
+    ///
+    /// Unlike the [instance name](Self::with_name), this can be any text (like the name of the
+    /// person being tracked), up to 250 bytes long.
+    pub fn with_friendly_name(mut self, name: impl Into<String>) -> Self {
+        self.friendly_name = Some(name.into());
+        self
+    }
+
+    /// Sets the camera the tracker captures from, which is advertised via mDNS.
+    pub fn with_camera(mut self, camera: CameraInfo) -> Self {
+        self.camera = Some(camera);
+        self
+    }
+
+    /// Sets whether eye textures are sent to clients, which is the default.
+    ///
+    /// Without them, the [`Eye::texture`](crate::data::Eye::texture) of every published message
+    /// is an empty image, which saves a lot of bandwidth for clients that don't need them. This is
+    /// advertised via mDNS.
+    pub fn with_eye_textures(mut self, eye_textures: bool) -> Self {
+        self.eye_textures = eye_textures;
+        self
+    }
+
+    /// Sets whether the [`Publisher`] advertises itself via mDNS, which is the default.
+    ///
+    /// Without mDNS, clients can't discover the tracker, and have to [connect](Subscriber::connect)
+    /// to its address directly. This also means that no network interface is needed.
+    pub fn with_mdns(mut self, mdns: bool) -> Self {
+        self.mdns = mdns;
+        self
+    }
+
+    /// Spawns the [`Publisher`].
+    ///
+    /// # Errors
+    ///
+    /// - [`io::ErrorKind::InvalidInput`] if the instance name is invalid, or the friendly name is
+    ///   too long.
+    /// - [`io::ErrorKind::AddrNotAvailable`] if mDNS is enabled, but there is no address to
+    ///   advertise.
+    /// - Any error that occurs while binding the TCP socket, like
+    ///   [`io::ErrorKind::AddrInUse`] if the port is taken.
+    pub fn spawn(self) -> io::Result<Publisher> {
+        Publisher::spawn_with(self)
+    }
+
+    /// Binds the TCP listener.
+    ///
+    /// Without a bind address, this creates a dual-stack IPv6 socket, and falls back to IPv4 on
+    /// systems without IPv6 support.
+    fn listen(&self) -> io::Result<TcpListener> {
+        if let Some(ip) = self.bind {
+            return TcpListener::bind((ip, self.port));
+        }
+
+        let dual_stack = || {
+            let socket = Socket::new(Domain::IPV6, Type::STREAM, None)?;
+            socket.set_only_v6(false)?;
+            // Matches what `TcpListener::bind` does.
+            #[cfg(unix)]
+            socket.set_reuse_address(true)?;
+            socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, self.port)).into())?;
+            socket.listen(128)?;
+            io::Result::Ok(TcpListener::from(socket))
+        };
+        match dual_stack() {
+            Ok(listener) => Ok(listener),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => Err(e),
+            Err(e) => {
+                debug!("failed to create dual-stack socket, falling back to IPv4: {e}");
+                TcpListener::bind((Ipv4Addr::UNSPECIFIED, self.port))
+            }
+        }
+    }
+
+    /// Picks the addresses to advertise from the addresses of the local network interfaces.
+    ///
+    /// `local` is the address the listener is bound to.
+    fn advertised_addrs(
+        &self,
+        local: IpAddr,
+        interfaces: impl IntoIterator<Item = (String, IpAddr)>,
+    ) -> Vec<IpAddr> {
+        if !local.is_unspecified() {
+            return vec![local];
+        }
+
+        // An IPv6 listener is dual-stack, unless it was explicitly bound to `::`.
+        let families_match = |ip: &IpAddr| match (local, self.bind) {
+            (IpAddr::V4(_), _) => ip.is_ipv4(),
+            (IpAddr::V6(_), Some(_)) => ip.is_ipv6(),
+            (IpAddr::V6(_), None) => true,
+        };
+        let candidates = interfaces
+            .into_iter()
+            .filter(|(name, _)| self.interfaces.is_empty() || self.interfaces.contains(name))
+            .filter(|(name, _)| !self.excluded_interfaces.contains(name))
+            .map(|(_, ip)| ip)
+            .filter(|ip| families_match(ip) && !mdns::is_link_local_v6(ip));
+        let mut candidates = if !self.interfaces.is_empty() {
+            candidates.collect::<Vec<_>>()
+        } else {
+            // Prefer private addresses, then public ones, then loopback. Each family is ranked on
+            // its own, but loopback is only advertised if there's nothing else.
+            let rank = |ip: &IpAddr| match ip {
+                IpAddr::V4(ip) if ip.is_private() => 0,
+                IpAddr::V6(ip) if ip.segments()[0] & 0xfe00 == 0xfc00 => 0,
+                ip if ip.is_loopback() => 2,
+                _ => 1,
+            };
+            let candidates = candidates.collect::<Vec<_>>();
+            let best = |v6| {
+                let ranks = candidates.iter().filter(|ip| ip.is_ipv6() == v6).map(rank);
+                ranks.min()
+            };
+            let best = [best(false), best(true)];
+            let loopback_only = best.iter().flatten().all(|&rank| rank == 2);
+            candidates
+                .iter()
+                .copied()
+                .filter(|ip| Some(rank(ip)) == best[usize::from(ip.is_ipv6())])
+                .filter(|ip| loopback_only || !ip.is_loopback())
+                .collect()
+        };
+        // Clients that don't support IPv6 only look at the first address.
+        candidates.sort_by_key(|ip| ip.is_ipv6());
+        candidates
+    }
+}
+
+/// The camera a tracker captures from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraInfo {
+    /// Width of the camera frames, in pixels.
+    pub width: u32,
+    /// Height of the camera frames, in pixels.
+    pub height: u32,
+    /// Rate the camera delivers frames at, per second.
+    pub fps: f32,
+}
+
+impl Default for PublisherBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parses the instance name of a [`Publisher`].
+fn instance_label(name: &str) -> io::Result<Label> {
+    match name.parse::<Label>() {
+        Ok(label) if !name.is_empty() && !name.contains('.') => Ok(label),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid instance name `{name}` (must be 1-63 bytes long, without dots)"),
+        )),
+    }
+}
+
+/// A published [`TrackingMessage`], along with the frames encoded from it so far.
+struct Outgoing {
+    message: TrackingMessage,
+    /// One frame for every combination of [`Codec`] and [`ImageEncoding`] requested by a client.
+    frames: Mutex<Vec<Frame>>,
+}
+
+/// A [`TrackingMessage`] frame, encoded for clients that requested `codec` and `encoding`.
+struct Frame {
+    codec: Codec,
+    encoding: ImageEncoding,
+    bytes: Arc<[u8]>,
+}
+
+impl Outgoing {
+    /// Returns the frame to send to clients that requested `codec` and `encoding`.
+    ///
+    /// Every frame is only encoded once, no matter how many clients requested it.
+    fn frame(&self, codec: Codec, encoding: ImageEncoding) -> io::Result<Arc<[u8]>> {
+        let mut frames = self.frames.lock().unwrap();
+        if let Some(frame) = frames
+            .iter()
+            .find(|f| f.codec == codec && f.encoding == encoding)
+        {
+            return Ok(frame.bytes.clone());
+        }
+
+        let mut message = self.message.clone();
+        message.encode_images(encoding)?;
+        let mut frame = Vec::new();
+        codec.write(&message, &mut frame)?;
+        let bytes: Arc<[u8]> = frame.into();
+        frames.push(Frame {
+            codec,
+            encoding,
+            bytes: bytes.clone(),
+        });
+        Ok(bytes)
+    }
+}
+
+/// Returns the [`Hello::capabilities`] announcing support for the given codecs and encodings.
+///
+/// Anything not supported by this build is left out.
+fn capabilities(codecs: &[Codec], encodings: &[ImageEncoding]) -> Vec<String> {
+    let codecs = codecs
+        .iter()
+        .filter(|codec| codec.is_supported())
+        .map(|codec| codec.capability());
+    let encodings = encodings
+        .iter()
+        .filter(|encoding| encoding.is_supported())
+        .map(|encoding| encoding.capability());
+    codecs.chain(encodings).map(String::from).collect()
+}
+
+/// Picks the first of the `client`'s capabilities that the `tracker` announced as well.
+///
+/// Both sides compute this after the handshake, so they agree on the result without having to
+/// exchange another message.
+fn negotiate<T>(client: &Hello, tracker: &Hello, parse: fn(&str) -> Option<T>) -> Option<T> {
+    client
+        .capabilities
+        .iter()
+        .filter(|capability| tracker.has_capability(capability))
+        .find_map(|capability| parse(capability))
+}
+
+/// Options for connecting a [`Subscriber`] to a tracker.
+#[derive(Debug, Clone)]
+pub struct SubscriberOptions {
+    limits: DecodeLimits,
+    codecs: Vec<Codec>,
+    image_encodings: Vec<ImageEncoding>,
+    reconnect: Option<ReconnectPolicy>,
+}
+
+impl SubscriberOptions {
+    /// Creates the default options.
+    ///
+    /// By default, the [`DecodeLimits`] are the defaults, messages are received in
+    /// [`Codec::Bincode`], and all lossless image encodings supported by this build are requested.
+    pub fn new() -> Self {
+        Self {
+            limits: DecodeLimits::default(),
+            codecs: Vec::new(),
+            image_encodings: ImageEncoding::ALL
+                .into_iter()
+                .filter(|encoding| *encoding != ImageEncoding::Rgba && !encoding.is_lossy())
+                .collect(),
+            reconnect: None,
+        }
+    }
+
+    /// Sets the [`DecodeLimits`] enforced on every received message.
+    ///
+    /// If the tracker sends a message that exceeds the limits, the connection is closed and the
+    /// error is returned by the next call to [`Subscriber::get`], [`Subscriber::next`] or
+    /// [`Subscriber::block`].
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the codecs the tracker may use for messages, in order of preference.
+    ///
+    /// Codecs that this build does not support are ignored. If the tracker supports none of the
+    /// requested codecs, it falls back to [`Codec::Bincode`].
+    pub fn with_codecs(mut self, codecs: &[Codec]) -> Self {
+        self.codecs = codecs.to_vec();
+        self
+    }
+
+    /// Reconnects to the tracker according to `policy` whenever the connection is lost.
+    ///
+    /// By default, a lost connection is final: the error is returned by the next call to
+    /// [`Subscriber::get`], [`Subscriber::next`] or [`Subscriber::block`], and a new [`Subscriber`]
+    /// has to be created. With a [`ReconnectPolicy`], those calls keep returning the last message
+    /// until the connection is re-established. Only once the policy gives up is the error
+    /// returned.
+    ///
+    /// Errors that would just happen again (like the tracker rejecting the handshake, or sending
+    /// a message that exceeds the [`DecodeLimits`]) are never retried.
+    ///
+    /// If the [`Subscriber`] was connected to a tracker discovered via mDNS, the tracker is
+    /// looked up again before every attempt, in case it restarted on another address or port.
+    pub fn with_reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    /// Sets the image encodings the tracker may use for eye textures, in order of preference.
+    ///
+    /// Received images are always decoded to [`ImageEncoding::Rgba`] before they are handed out,
+    /// so this only affects bandwidth and CPU usage (and image quality, for lossy encodings).
+    /// Encodings that this build does not support are ignored. If the tracker supports none of
+    /// the requested encodings, it falls back to [`ImageEncoding::Rgba`].
+    pub fn with_image_encodings(mut self, encodings: &[ImageEncoding]) -> Self {
+        self.image_encodings = encodings.to_vec();
+        self
+    }
+}
+
+impl Default for SubscriberOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Controls how a [`Subscriber`] reconnects to the tracker after losing its connection.
+///
+/// The delay before each attempt starts at the initial delay and doubles with every failed
+/// attempt, up to the maximum delay. Once a connection is re-established, the delay is reset.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    max_attempts: Option<u32>,
+}
+
+impl ReconnectPolicy {
+    /// Creates the default policy.
+    ///
+    /// By default, the first attempt is made after 250 ms, the delay grows up to 5 seconds, and
+    /// the [`Subscriber`] never gives up.
+    pub fn new() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(5),
+            max_attempts: None,
+        }
+    }
+
+    /// Sets the delay before the first attempt to reconnect.
+    pub fn with_initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// Sets the maximum delay between two attempts to reconnect.
+    pub fn with_max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Gives up after `attempts` failed attempts in a row.
+    pub fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    /// Returns the delay before attempt number `attempt` (starting at 1), or [`None`] if the
+    /// [`Subscriber`] should give up instead.
+    fn delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt > max) {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        Some(
+            self.initial_delay
+                .saturating_mul(factor)
+                .min(self.max_delay.max(self.initial_delay)),
+        )
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A change in the state of a [`Subscriber`]'s connection to the tracker.
+///
+/// Retrieved via [`Subscriber::events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The connection to the tracker at `addr` was established (or re-established), and the
+    /// handshake has completed.
+    Connected { addr: SocketAddr },
+    /// The connection was lost, or could not be established, because of `reason`. Attempt number
+    /// `attempt` to reconnect will be made after `delay`.
+    ///
+    /// Only emitted when the [`Subscriber`] was configured with a [`ReconnectPolicy`].
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+        reason: String,
+    },
+    /// The connection was lost for good. The error is returned by the next call to
+    /// [`Subscriber::get`], [`Subscriber::next`] or [`Subscriber::block`].
+    Lost,
+}
+
+/// The [`ConnectionEvent`]s that haven't been retrieved via [`Subscriber::events`] yet.
+#[derive(Default)]
+struct EventQueue(Mutex<VecDeque<ConnectionEvent>>);
+
+impl EventQueue {
+    /// The maximum number of events kept. When it is reached, the oldest event is dropped.
+    const CAPACITY: usize = 64;
+
+    fn push(&self, event: ConnectionEvent) {
+        let mut events = self.0.lock().unwrap();
+        if events.len() == Self::CAPACITY {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    fn take(&self) -> Vec<ConnectionEvent> {
+        self.0.lock().unwrap().drain(..).collect()
+    }
+}
+
 pub struct Subscriber {
     task: Option<Task<io::Result<()>>>, // FIXME: ! instead of ()
     reader: Reader<Option<Arc<TrackingMessage>>>,
+    tracker: Arc<OnceLock<Hello>>,
+    events: Arc<EventQueue>,
+    limits: DecodeLimits,
 }
 
 impl Subscriber {
+    /// Discovers a tracker on the local network via mDNS, and connects to it.
+    ///
+    /// Blocks until a tracker is found, or discovery times out. If there may be several trackers,
+    /// use the [`discovery`] module to pick the right one instead.
     pub fn autoconnect_blocking() -> io::Result<Self> {
-        let service = Service::new(Label::new(SERVICE), ServiceTransport::TCP);
-        let mut discoverer = SyncDiscoverer::new_multicast_v4()?;
-
-        let mut instance = None;
-        discoverer.discover_instances(&service, |new| {
-            instance = Some(new.clone());
-            ControlFlow::Break(())
-        })?;
-        let details = match instance {
-            Some(instance) => discoverer.load_instance_details(&instance)?,
-            None => {
-                return Err(io::Error::new(
-                    io::ErrorKind::TimedOut,
-                    format!("timed out while discovering `{}` network service", SERVICE),
-                ));
-            }
-        };
-        info!(
-            "discovered providence on {}:{}",
-            details.host(),
-            details.port(),
-        );
-
-        let mut res = SyncResolver::new_multicast_v4()?;
-        let mut ips = res
-            .resolve_domain(details.host())?
-            .filter_map(|ip| match ip {
-                IpAddr::V4(ip) => Some(ip),
-                IpAddr::V6(_) => None,
-            });
-        let ip = ips.next().ok_or(io::ErrorKind::TimedOut)?;
-        info!("resolved server IP: {}", ip);
+        Self::autoconnect_blocking_with_options(SubscriberOptions::default())
+    }
 
-        Self::connect(SocketAddrV4::new(ip, details.port()))
+    /// Discovers a tracker on the local network via mDNS, and connects to it using the given
+    /// [`SubscriberOptions`].
+    pub fn autoconnect_blocking_with_options(options: SubscriberOptions) -> io::Result<Self> {
+        let tracker = future::block_on(discovery::first(Some(AUTOCONNECT_TIMEOUT)))?;
+        Ok(Self::connect_to_tracker(&tracker, options))
     }
 
+    /// Discovers a tracker on the local network via mDNS, and connects to it.
+    ///
+    /// Waits until a tracker is found. If there may be several trackers, use the [`discovery`]
+    /// module to pick the right one instead.
     pub async fn autoconnect_async() -> io::Result<Self> {
-        let service = Service::new(Label::new(SERVICE), ServiceTransport::TCP);
-        let mut discoverer = AsyncDiscoverer::new_multicast_v4().await?;
-
-        let mut instance = None;
-        discoverer.set_discovery_timeout(Duration::MAX)?;
-        discoverer
-            .discover_instances(&service, |new| {
-                instance = Some(new.clone());
-                ControlFlow::Break(())
-            })
-            .await?;
-        let details = match instance {
-            Some(instance) => discoverer.load_instance_details(&instance).await?,
-            None => {
-                // The timeout is ~infinite, good luck hitting this
-                return Err(io::Error::new(
-                    io::ErrorKind::TimedOut,
-                    format!("timed out while discovering `{}` network service", SERVICE),
-                ));
-            }
-        };
-        info!(
-            "discovered providence on {}:{}",
-            details.host(),
-            details.port(),
-        );
+        Self::autoconnect_async_with_options(SubscriberOptions::default()).await
+    }
+
+    /// Discovers a tracker on the local network via mDNS, and connects to it using the given
+    /// [`SubscriberOptions`].
+    pub async fn autoconnect_async_with_options(options: SubscriberOptions) -> io::Result<Self> {
+        let tracker = discovery::first(None).await?;
+        Ok(Self::connect_to_tracker(&tracker, options))
+    }
+
+    /// Connects to a tracker at `addr`.
+    ///
+    /// `addr` can be any IPv4 or IPv6 socket address, or a `host:port` string. If it resolves to
+    /// several addresses, they are tried in order until a connection succeeds.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::connect_with_options(addr, SubscriberOptions::default())
+    }
 
-        let mut res = AsyncResolver::new_multicast_v4().await?;
-        let mut ips = res
-            .resolve_domain(details.host())
-            .await?
-            .filter_map(|ip| match ip {
-                IpAddr::V4(ip) => Some(ip),
-                IpAddr::V6(_) => None,
-            });
-        let ip = ips.next().ok_or(io::ErrorKind::TimedOut)?;
-        info!("resolved server IP: {}", ip);
+    /// Connects to a tracker using the given [`SubscriberOptions`].
+    pub fn connect_with_options<A: ToSocketAddrs>(
+        addr: A,
+        options: SubscriberOptions,
+    ) -> io::Result<Self> {
+        let addrs = addr.to_socket_addrs()?.collect::<Vec<_>>();
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            ));
+        }
+        Ok(Self::spawn(Target::Addrs(addrs), options))
+    }
 
-        Self::connect(SocketAddrV4::new(ip, details.port()))
+    /// Connects to a tracker found via the [`discovery`] module, using the given
+    /// [`SubscriberOptions`].
+    ///
+    /// Unlike connecting to the tracker's [addresses](DiscoveredTracker::addrs), this allows the
+    /// [`Subscriber`] to look the tracker up again when it reconnects.
+    pub fn connect_to_tracker(tracker: &DiscoveredTracker, options: SubscriberOptions) -> Self {
+        Self::spawn(
+            Target::Tracker {
+                name: tracker.name().to_owned(),
+                addrs: tracker.addrs().to_vec(),
+            },
+            options,
+        )
     }
 
-    pub fn connect(addr: SocketAddrV4) -> io::Result<Self> {
+    fn spawn(mut target: Target, options: SubscriberOptions) -> Self {
+        let limits = options.limits;
+        let reconnect = options.reconnect;
+        let hello = Hello {
+            capabilities: capabilities(&options.codecs, &options.image_encodings),
+            ..Hello::new(client_name())
+        };
         let mut message = Value::new(None);
         let reader = message.reader();
+        let tracker = Arc::new(OnceLock::new());
+        let events = Arc::new(EventQueue::default());
 
+        let tracker2 = tracker.clone();
+        let events2 = events.clone();
         let task = Task::spawn(async move {
-            let mut stream = Async::<TcpStream>::connect(addr).await?;
-            info!("connected to server at {addr}");
+            let mut failures = 0;
             loop {
-                let msg = Arc::new(TrackingMessage::async_read(&mut stream).await?);
-                message.set(Some(msg));
+                if failures > 0 {
+                    target.rediscover().await;
+                }
+                let on_connected = |addr| {
+                    failures = 0;
+                    events2.push(ConnectionEvent::Connected { addr });
+                };
+                let e = receive(
+                    target.addrs(),
+                    &hello,
+                    &limits,
+                    &mut message,
+                    &tracker2,
+                    on_connected,
+                )
+                .await;
+
+                failures += 1;
+                let permanent = matches!(
+                    e.kind(),
+                    io::ErrorKind::InvalidData | io::ErrorKind::Unsupported
+                );
+                let delay = match &reconnect {
+                    Some(policy) if !permanent => policy.delay(failures),
+                    _ => None,
+                };
+                let Some(delay) = delay else {
+                    events2.push(ConnectionEvent::Lost);
+                    return Err(e);
+                };
+                warn!("lost connection to tracker ({e}), reconnecting in {delay:?}");
+                events2.push(ConnectionEvent::Reconnecting {
+                    attempt: failures,
+                    delay,
+                    reason: e.to_string(),
+                });
+                Timer::after(delay).await;
             }
         });
 
-        Ok(Self {
+        Self {
             task: Some(task),
             reader,
-        })
+            tracker,
+            events,
+            limits,
+        }
+    }
+
+    /// Returns the [`ConnectionEvent`]s that happened since the last call, oldest first.
+    ///
+    /// Only the 64 most recent events are kept, so applications that don't call this regularly
+    /// will miss older ones.
+    pub fn events(&self) -> Vec<ConnectionEvent> {
+        self.events.take()
+    }
+
+    /// Returns the [`Hello`] the tracker sent during the connection handshake.
+    ///
+    /// Returns [`None`] if the handshake has not completed yet. After reconnecting, this is still
+    /// the [`Hello`] of the first connection.
+    pub fn tracker(&self) -> Option<&Hello> {
+        self.tracker.get()
+    }
+
+    /// Returns the [`DecodeLimits`] applied to messages received from the tracker.
+    #[inline]
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
     }
 
     /// Retrieves the most recent message received.
@@ -278,6 +898,7 @@ impl Subscriber {
     /// If no message was received since the last time one was retrieved from this [`Subscriber`],
     /// this function returns [`None`]. If you want to access the last message regardless, call
     /// [`Subscriber::get`] instead.
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> io::Result<Option<Arc<TrackingMessage>>> {
         if self.reader.has_changed() {
             match self.reader.get() {
@@ -308,8 +929,100 @@ impl Subscriber {
     }
 }
 
+/// What a [`Subscriber`] connects to.
+enum Target {
+    /// Fixed addresses.
+    Addrs(Vec<SocketAddr>),
+    /// A tracker discovered via mDNS, which is looked up again when reconnecting.
+    Tracker {
+        name: String,
+        addrs: Vec<SocketAddr>,
+    },
+}
+
+impl Target {
+    fn addrs(&self) -> &[SocketAddr] {
+        match self {
+            Target::Addrs(addrs) | Target::Tracker { addrs, .. } => addrs,
+        }
+    }
+
+    /// Looks up the addresses of the tracker again.
+    ///
+    /// If it can't be found, the last known addresses are kept.
+    async fn rediscover(&mut self) {
+        let Target::Tracker { name, addrs } = self else {
+            return;
+        };
+        match discovery::find_async(name, Some(REDISCOVERY_TIMEOUT)).await {
+            Ok(tracker) => *addrs = tracker.addrs().to_vec(),
+            Err(e) => debug!("failed to rediscover tracker `{name}`: {e}"),
+        }
+    }
+}
+
+/// Connects to the tracker, and receives messages until the connection fails.
+///
+/// `on_connected` is invoked once the handshake has completed. Returns the error that ended the
+/// connection.
+async fn receive(
+    addrs: &[SocketAddr],
+    hello: &Hello,
+    limits: &DecodeLimits,
+    message: &mut Value<Option<Arc<TrackingMessage>>>,
+    tracker_hello: &OnceLock<Hello>,
+    on_connected: impl FnOnce(SocketAddr),
+) -> io::Error {
+    let res: io::Result<Infallible> = async {
+        let (mut stream, addr) = connect_any(addrs).await?;
+        let tracker = handshake::client(&mut stream, hello).await?;
+        let codec = negotiate(hello, &tracker, Codec::from_capability).unwrap_or(Codec::Bincode);
+        info!(
+            "connected to tracker `{}` at {addr} (codec: {codec:?})",
+            tracker.name,
+        );
+        tracker_hello.set(tracker).ok();
+        on_connected(addr);
+        loop {
+            let mut msg = codec.async_read(&mut stream, limits).await?;
+            msg.decode_images_with_limits(limits)?;
+            message.set(Some(Arc::new(msg)));
+        }
+    }
+    .await;
+    match res {
+        Ok(never) => match never {},
+        Err(e) => e,
+    }
+}
+
+/// Connects to the first of `addrs` that accepts the connection.
+async fn connect_any(addrs: &[SocketAddr]) -> io::Result<(Async<TcpStream>, SocketAddr)> {
+    let mut last_error = None;
+    for &addr in addrs {
+        match Async::<TcpStream>::connect(addr).await {
+            Ok(stream) => return Ok((stream, addr)),
+            Err(e) => {
+                debug!("failed to connect to {addr}: {e}");
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap())
+}
+
+/// Name a [`Subscriber`] reports to the tracker: the name of the running executable.
+fn client_name() -> String {
+    env::current_exe()
+        .ok()
+        .and_then(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| String::from("providence-client"))
+}
+
 #[cfg(test)]
 mod tests {
+    use std::net::SocketAddrV4;
+
     use crate::data::{Eye, FaceData, Image, Mesh, PersistentId, Vertex};
 
     use super::*;
@@ -334,6 +1047,375 @@ mod tests {
         let mut s = Subscriber::connect(SocketAddrV4::new(Ipv4Addr::LOCALHOST, p.port())).unwrap();
         s.block().unwrap();
         let _msg = s.get().unwrap();
+
+        for codec in Codec::ALL.into_iter().filter(|c| c.is_supported()) {
+            let options = SubscriberOptions::new().with_codecs(&[codec]);
+            let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, p.port());
+            let mut s = Subscriber::connect_with_options(addr, options).unwrap();
+            let msg = s.block().unwrap();
+            assert_eq!(msg.timestamp, mk_test_msg().timestamp, "{codec:?}");
+        }
+    }
+
+    #[test]
+    fn builder() {
+        let mut p = Publisher::builder()
+            .with_bind_address(Ipv4Addr::LOCALHOST.into())
+            .with_name("test-tracker")
+            .with_mdns(false)
+            .spawn()
+            .unwrap();
+        p.publish(mk_test_msg());
+        let mut s = Subscriber::connect(SocketAddrV4::new(Ipv4Addr::LOCALHOST, p.port())).unwrap();
+        s.block().unwrap();
+        assert_eq!(s.tracker().unwrap().name, "test-tracker");
+
+        // The port is taken now.
+        let err = Publisher::builder()
+            .with_port(p.port())
+            .with_mdns(false)
+            .spawn()
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        for name in ["", "a.b", &"x".repeat(64)] {
+            let err = Publisher::builder()
+                .with_name(name)
+                .with_mdns(false)
+                .spawn()
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{name}");
+        }
+        let err = Publisher::builder()
+            .with_friendly_name("x".repeat(251))
+            .with_mdns(false)
+            .spawn()
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn without_eye_textures() {
+        let mut p = Publisher::builder()
+            .with_bind_address(Ipv4Addr::LOCALHOST.into())
+            .with_eye_textures(false)
+            .with_mdns(false)
+            .spawn()
+            .unwrap();
+        p.publish(mk_test_msg());
+        let mut s = Subscriber::connect((Ipv4Addr::LOCALHOST, p.port())).unwrap();
+        let msg = s.block().unwrap();
+        let eye = msg.faces[0].left_eye.as_ref().unwrap();
+        assert_eq!((eye.texture.width, eye.texture.height), (0, 0));
+        assert_eq!(eye.iris_radius, 0.25);
+    }
+
+    #[test]
+    fn ipv6() {
+        let mut p = Publisher::builder().with_mdns(false).spawn().unwrap();
+        p.publish(mk_test_msg());
+        // The default listener is dual-stack.
+        for addr in [
+            SocketAddr::from((Ipv4Addr::LOCALHOST, p.port())),
+            SocketAddr::from((Ipv6Addr::LOCALHOST, p.port())),
+        ] {
+            let mut s = Subscriber::connect(addr).unwrap();
+            s.block().unwrap();
+        }
+        let mut s = Subscriber::connect(format!("localhost:{}", p.port())).unwrap();
+        s.block().unwrap();
+
+        let mut p = Publisher::builder()
+            .with_bind_address(Ipv6Addr::LOCALHOST.into())
+            .with_mdns(false)
+            .spawn()
+            .unwrap();
+        p.publish(mk_test_msg());
+        let mut s = Subscriber::connect(format!("[::1]:{}", p.port())).unwrap();
+        s.block().unwrap();
+
+        let err = Subscriber::connect(&[][..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn reconnect_policy() {
+        let policy = ReconnectPolicy::new()
+            .with_initial_delay(Duration::from_millis(100))
+            .with_max_delay(Duration::from_millis(500))
+            .with_max_attempts(5);
+        let delays = (1..=6).map(|n| policy.delay(n)).collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 500, 500]
+                .map(|ms| Some(Duration::from_millis(ms)))
+                .into_iter()
+                .chain([None])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ReconnectPolicy::new().delay(1000),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn reconnect() {
+        let spawn = |port| {
+            Publisher::builder()
+                .with_bind_address(Ipv4Addr::LOCALHOST.into())
+                .with_port(port)
+                .with_mdns(false)
+                .spawn()
+        };
+        let mut p = spawn(0).unwrap();
+        let port = p.port();
+        p.publish(mk_test_msg());
+
+        // Without a policy, losing the connection is final.
+        let mut s = Subscriber::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        s.block().unwrap();
+
+        let policy = ReconnectPolicy::new().with_initial_delay(Duration::from_millis(20));
+        let options = SubscriberOptions::new().with_reconnect(policy);
+        let mut r = Subscriber::connect_with_options((Ipv4Addr::LOCALHOST, port), options).unwrap();
+        r.block().unwrap();
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        assert_eq!(r.events(), [ConnectionEvent::Connected { addr }]);
+
+        drop(p);
+        s.block().unwrap_err();
+        assert_eq!(
+            s.events(),
+            [ConnectionEvent::Connected { addr }, ConnectionEvent::Lost]
+        );
+
+        // The port is freed once the publisher's task has been dropped.
+        let mut p = loop {
+            match spawn(port) {
+                Ok(p) => break p,
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                Err(e) => panic!("{e}"),
+            }
+        };
+        let mut msg = mk_test_msg();
+        msg.timestamp = 42;
+        p.publish(msg);
+        while r.block().unwrap().timestamp != 42 {}
+        let events = r.events();
+        assert!(
+            matches!(events[0], ConnectionEvent::Reconnecting { attempt: 1, .. }),
+            "{events:?}"
+        );
+        assert_eq!(events.last(), Some(&ConnectionEvent::Connected { addr }));
+        assert!(!events.contains(&ConnectionEvent::Lost), "{events:?}");
+        drop(p);
+
+        // Give up after the configured number of attempts.
+        let policy = ReconnectPolicy::new()
+            .with_initial_delay(Duration::from_millis(1))
+            .with_max_attempts(2);
+        let options = SubscriberOptions::new().with_reconnect(policy);
+        let mut s = Subscriber::connect_with_options(addr, options).unwrap();
+        let err = s.block().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        let events = s.events();
+        assert_eq!(events.len(), 3, "{events:?}");
+        assert!(matches!(
+            events[1],
+            ConnectionEvent::Reconnecting { attempt: 2, .. }
+        ));
+        assert_eq!(events[2], ConnectionEvent::Lost);
+
+        // Only the most recent events are kept.
+        let attempts = EventQueue::CAPACITY as u32 + 10;
+        let policy = ReconnectPolicy::new()
+            .with_initial_delay(Duration::from_millis(1))
+            .with_max_delay(Duration::from_millis(1))
+            .with_max_attempts(attempts);
+        let options = SubscriberOptions::new().with_reconnect(policy);
+        let mut s = Subscriber::connect_with_options(addr, options).unwrap();
+        s.block().unwrap_err();
+        let events = s.events();
+        assert_eq!(events.len(), EventQueue::CAPACITY);
+        assert_eq!(events.last(), Some(&ConnectionEvent::Lost));
+        assert!(matches!(
+            events[0],
+            ConnectionEvent::Reconnecting { attempt: 12, .. }
+        ));
+    }
+
+    #[test]
+    fn reconnect_rediscover() {
+        let spawn = || {
+            Publisher::builder()
+                .with_name("reconnect-test")
+                .spawn()
+                .unwrap()
+        };
+        let mut p = spawn();
+        p.publish(mk_test_msg());
+        let tracker =
+            discovery::find_blocking("reconnect-test", Some(Duration::from_secs(10))).unwrap();
+        let policy = ReconnectPolicy::new().with_initial_delay(Duration::from_millis(20));
+        let options = SubscriberOptions::new().with_reconnect(policy);
+        let mut s = Subscriber::connect_to_tracker(&tracker, options);
+        s.block().unwrap();
+
+        // The new publisher listens on a different port.
+        drop(p);
+        let mut p = spawn();
+        assert_ne!(p.port(), tracker.port());
+        let mut msg = mk_test_msg();
+        msg.timestamp = 42;
+        p.publish(msg);
+        while s.block().unwrap().timestamp != 42 {}
+        let Some(ConnectionEvent::Connected { addr }) = s.events().pop() else {
+            panic!("not reconnected");
+        };
+        assert_eq!(addr.port(), p.port());
+    }
+
+    #[test]
+    fn advertised_addrs() {
+        let interfaces = || {
+            [
+                ("lo", "127.0.0.1"),
+                ("lo", "::1"),
+                ("eth0", "203.0.113.5"),
+                ("eth0", "fe80::1"),
+                ("eth0", "2001:db8::5"),
+                ("eth0", "192.168.0.2"),
+                ("wlan0", "10.0.0.7"),
+                ("wlan0", "fd00::7"),
+                ("tun0", "100.64.0.1"),
+            ]
+            .map(|(name, ip)| (name.to_string(), ip.parse().unwrap()))
+        };
+        let ips = |ips: &[&str]| {
+            ips.iter()
+                .map(|ip| ip.parse().unwrap())
+                .collect::<Vec<IpAddr>>()
+        };
+        let dual_stack = IpAddr::from(Ipv6Addr::UNSPECIFIED);
+        let v4 = IpAddr::from(Ipv4Addr::UNSPECIFIED);
+
+        let builder = PublisherBuilder::new();
+        assert_eq!(
+            builder.advertised_addrs(dual_stack, interfaces()),
+            ips(&["192.168.0.2", "10.0.0.7", "fd00::7"])
+        );
+        assert_eq!(
+            builder.advertised_addrs(v4, interfaces()),
+            ips(&["192.168.0.2", "10.0.0.7"])
+        );
+        let builder = builder.without_interfaces(&["wlan0"]);
+        assert_eq!(
+            builder.advertised_addrs(dual_stack, interfaces()),
+            ips(&["192.168.0.2", "2001:db8::5"])
+        );
+        let builder = builder.without_interfaces(&["eth0", "wlan0"]);
+        assert_eq!(
+            builder.advertised_addrs(dual_stack, interfaces()),
+            ips(&["100.64.0.1"])
+        );
+        let builder = builder.without_interfaces(&["eth0", "wlan0", "tun0"]);
+        assert_eq!(
+            builder.advertised_addrs(dual_stack, interfaces()),
+            ips(&["127.0.0.1", "::1"])
+        );
+        // Excluded interfaces stay excluded, even if they are selected.
+        let builder = builder.with_interfaces(&["lo", "tun0"]);
+        assert_eq!(
+            builder.advertised_addrs(v4, interfaces()),
+            ips(&["127.0.0.1"])
+        );
+        let builder = PublisherBuilder::new().with_interfaces(&["eth0"]);
+        assert_eq!(
+            builder.advertised_addrs(dual_stack, interfaces()),
+            ips(&["203.0.113.5", "192.168.0.2", "2001:db8::5"])
+        );
+        let builder = builder.with_bind_address(Ipv6Addr::UNSPECIFIED.into());
+        assert_eq!(
+            builder.advertised_addrs(dual_stack, interfaces()),
+            ips(&["2001:db8::5"])
+        );
+        assert_eq!(
+            builder.advertised_addrs("10.0.0.7".parse().unwrap(), interfaces()),
+            ips(&["10.0.0.7"])
+        );
+        assert!(PublisherBuilder::new()
+            .with_interfaces(&["eth1"])
+            .advertised_addrs(dual_stack, interfaces())
+            .is_empty());
+    }
+
+    #[test]
+    fn negotiation() {
+        let tracker = Hello {
+            capabilities: capabilities(&Codec::ALL, &ImageEncoding::ALL),
+            ..Hello::new("tracker")
+        };
+        let mut client = Hello::new("client");
+        assert_eq!(negotiate(&client, &tracker, Codec::from_capability), None);
+        assert_eq!(
+            negotiate(&client, &tracker, ImageEncoding::from_capability),
+            None
+        );
+
+        client.capabilities = vec![
+            "unknown".into(),
+            "image/gray".into(),
+            "codec/carrier-pigeon".into(),
+            "image/qoi".into(),
+            "codec/bincode".into(),
+        ];
+        assert_eq!(
+            negotiate(&client, &tracker, ImageEncoding::from_capability),
+            Some(ImageEncoding::Gray),
+        );
+        assert_eq!(
+            negotiate(&client, &tracker, Codec::from_capability),
+            Some(Codec::Bincode),
+        );
+
+        // Old trackers don't announce anything.
+        let tracker = Hello::new("tracker");
+        assert_eq!(
+            negotiate(&client, &tracker, ImageEncoding::from_capability),
+            None
+        );
+
+        let options = SubscriberOptions::new();
+        assert!(!options.image_encodings.iter().any(|e| e.is_lossy()));
+        assert_eq!(options.image_encodings[0], ImageEncoding::Qoi);
+    }
+
+    #[test]
+    fn outgoing_frames() {
+        let outgoing = Outgoing {
+            message: mk_test_msg(),
+            frames: Mutex::new(Vec::new()),
+        };
+        let rgba = outgoing.frame(Codec::Bincode, ImageEncoding::Rgba).unwrap();
+        let qoi = outgoing.frame(Codec::Bincode, ImageEncoding::Qoi).unwrap();
+        assert!(Arc::ptr_eq(
+            &qoi,
+            &outgoing.frame(Codec::Bincode, ImageEncoding::Qoi).unwrap()
+        ));
+
+        let mut msg = TrackingMessage::read(&*qoi).unwrap();
+        let eye = msg.faces[0].left_eye.as_ref().unwrap();
+        assert_eq!(eye.texture.encoding, ImageEncoding::Qoi);
+        msg.decode_images().unwrap();
+        let mut buf = Vec::new();
+        msg.write(&mut buf).unwrap();
+        assert_eq!(&*buf, &*rgba);
     }
 
     fn mk_test_msg() -> TrackingMessage {
@@ -349,11 +1431,7 @@ mod tests {
                         3
                     ],
                 },
-                texture: Image {
-                    data: vec![0, 1, 2, 3],
-                    height: 1,
-                    width: 1,
-                },
+                texture: Image::from_rgba(1, 1, vec![0, 1, 2, 3]),
                 iris_center: [0.0; 3],
                 iris_radius: 0.25,
             }
@@ -361,6 +1439,7 @@ mod tests {
 
         TrackingMessage {
             timestamp: 123456,
+            timing: None,
             faces: vec![FaceData {
                 ephemeral_id: 123,
                 persistent_id: PersistentId::Unknown,
@@ -0,0 +1,1068 @@
+//! A file format for recordings of [`TrackingMessage`] streams.
+//!
+//! A recording starts with a versioned [`Header`] that identifies the file and describes what it
+//! contains, followed by the recorded messages and an index of their positions, which allows
+//! seeking without reading the whole file.
+//!
+//! The header has the following layout:
+//!
+//! | Offset | Size | Contents                                 |
+//! |--------|------|------------------------------------------|
+//! | 0      | 4    | [`MAGIC`]                                |
+//! | 4      | 4    | [`FORMAT_VERSION`] (little-endian)       |
+//! | 8      | 4    | payload length in bytes (little-endian)  |
+//! | 12     | *n*  | payload ([`Header`], bincode-encoded)    |
+//!
+//! It is followed by one record per message. Every record has the following layout (all integers
+//! are little-endian):
+//!
+//! | Offset | Size | Contents                                                    |
+//! |--------|------|-------------------------------------------------------------|
+//! | 0      | 4    | [`SYNC`]                                                    |
+//! | 4      | 1    | record type (`0` for messages)                              |
+//! | 5      | 8    | time since the start of the recording in microseconds       |
+//! | 13     | 4    | payload length in bytes                                     |
+//! | 17     | 4    | CRC-32 of bytes 4 to 16 and the payload                     |
+//! | 21     | *n*  | payload ([`TrackingMessage`], encoded with [`Codec::Bincode`]) |
+//!
+//! When the recording is finished, an index record (record type `1`) is appended, followed by a
+//! fixed-size trailer pointing at it. The payload of the index record holds the number of messages
+//! *N* as a `u64`, followed by *N* pairs of `u64`s: the time of each message and the file offset of
+//! its record. The trailer consists of the file offset of the index record (as a `u64`), followed by
+//! [`TRAILER_MAGIC`].
+//!
+//! # Damaged recordings
+//!
+//! Recordings that were never [finished](RecordingWriter::finish) (for example, because the
+//! recording process was killed) lack the index, and often end in the middle of a record. Files
+//! can also be damaged in other ways after they were written. [`RecordingReader`] recovers from
+//! both: if the index is missing or damaged, it rebuilds it by scanning the file. Records whose
+//! checksum doesn't match are skipped by searching for the next [`SYNC`] marker that starts an
+//! intact record. All skipped data is reported as [`Damage`].
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::{convert_error, Codec, DecodeLimits, TrackingMessage},
+    fingerprint::{Difference, Schema},
+};
+
+/// Magic bytes at the start of every recording.
+pub const MAGIC: [u8; 4] = *b"PRVR";
+
+/// Magic bytes at the start of every record.
+pub const SYNC: [u8; 4] = *b"PRVS";
+
+/// Magic bytes at the end of every finished recording.
+pub const TRAILER_MAGIC: [u8; 4] = *b"PRVI";
+
+/// The version of the recording format implemented by this library.
+///
+/// This is incremented whenever the layout of the file or of the [`Header`] changes. Changes to
+/// the structure of [`TrackingMessage`] are detected via [`Header::fingerprint`] instead.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Maximum size of a header payload.
+const MAX_HEADER_SIZE: u32 = 1024 * 1024;
+
+const TAG_MESSAGE: u8 = 0;
+const TAG_INDEX: u8 = 1;
+
+/// Size of a record, excluding the payload.
+const RECORD_OVERHEAD: u64 = 4 + 1 + 8 + 4 + 4;
+
+/// Size of the trailer at the end of a finished recording.
+const TRAILER_SIZE: u64 = 8 + 4;
+
+/// Information about a recording, stored at the start of the file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    /// The [`TrackingMessage::fingerprint`] of the recorded messages.
+    pub fingerprint: u64,
+    /// The [`TrackingMessage::schema`] of the recorded messages, as JSON.
+    ///
+    /// This describes how the messages are structured even if this library no longer understands
+    /// them.
+    pub schema: String,
+    /// The tracker that produced the messages.
+    pub tracker: TrackerInfo,
+    /// The wall-clock time the recording was started at.
+    pub start_time: SystemTime,
+}
+
+/// Identifies the tracker that produced a recording.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackerInfo {
+    /// Human-readable name of the tracker.
+    pub name: String,
+    /// Version of the tracker, or an empty string if unknown.
+    pub version: String,
+}
+
+impl Header {
+    /// Creates a [`Header`] for a recording of messages produced by `tracker`, starting now.
+    pub fn new(tracker: TrackerInfo) -> Self {
+        Self {
+            fingerprint: TrackingMessage::fingerprint(),
+            schema: TrackingMessage::schema().to_json(),
+            tracker,
+            start_time: SystemTime::now(),
+        }
+    }
+
+    /// Reads the [`Header`] at the start of a recording.
+    ///
+    /// This only checks that the file is a recording in a format version this library supports,
+    /// not whether this library can decode the recorded messages (see [`Header::check`]). This
+    /// allows inspecting incompatible recordings.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 12];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a recording (is it using the old recording format?)",
+            ));
+        }
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported recording format version {version} (expected version {FORMAT_VERSION})"
+                ),
+            ));
+        }
+        let size = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if size > MAX_HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("recording header too large ({size} bytes)"),
+            ));
+        }
+
+        let mut payload = Vec::new();
+        reader.take(size.into()).read_to_end(&mut payload)?;
+        if payload.len() != size as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        bincode::deserialize(&payload).map_err(convert_error)
+    }
+
+    /// Checks whether this library can decode the messages in the recording.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the recording was made with a different
+    /// [`TrackingMessage::fingerprint`]. The error lists the differences between the schemas.
+    pub fn check(&self) -> io::Result<()> {
+        if self.fingerprint == TrackingMessage::fingerprint() {
+            return Ok(());
+        }
+
+        let mut msg = format!(
+            "recording was made with an incompatible message fingerprint (expected {:016x}, got {:016x})",
+            TrackingMessage::fingerprint(),
+            self.fingerprint,
+        );
+        for difference in self.differences().unwrap_or_default() {
+            msg.push_str(&format!("\n- {difference}"));
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+    }
+
+    /// Returns the places where the schema of the recorded messages differs from ours.
+    ///
+    /// Returns [`None`] if [`Header::schema`] is not a valid schema.
+    pub fn differences(&self) -> Option<Vec<Difference>> {
+        let schema = Schema::from_json(&self.schema).ok()?;
+        Some(TrackingMessage::schema().diff(&schema))
+    }
+
+    fn encode(&self) -> io::Result<Vec<u8>> {
+        let payload = bincode::serialize(self).map_err(convert_error)?;
+        let mut buf = Vec::with_capacity(12 + payload.len());
+        buf.extend_from_slice(&MAGIC);
+        buf.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        buf.extend_from_slice(&u32::try_from(payload.len()).unwrap().to_le_bytes());
+        buf.extend_from_slice(&payload);
+        Ok(buf)
+    }
+}
+
+/// A message read from a recording.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// Time since the start of the recording.
+    pub time: Duration,
+    pub message: TrackingMessage,
+}
+
+/// A damaged region of a recording, which [`RecordingReader`] had to skip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Damage {
+    /// File offset of the first skipped byte.
+    pub offset: u64,
+    /// Number of bytes skipped.
+    pub len: u64,
+    /// Time of the last intact message before the damaged region, if any.
+    ///
+    /// Messages recorded after this point and before the next intact message were lost.
+    pub after: Option<Duration>,
+    /// Why the region was skipped.
+    pub reason: DamageReason,
+}
+
+/// The reason a [`Damage`]d region of a recording was skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DamageReason {
+    /// The file ends in the middle of a record.
+    ///
+    /// This is expected for recordings that were never [finished](RecordingWriter::finish).
+    Truncated,
+    /// A record does not start with the [`SYNC`] marker.
+    MissingSync,
+    /// A record's payload length exceeds the [`DecodeLimits`].
+    TooLarge(u32),
+    /// A record's checksum does not match its contents.
+    ChecksumMismatch,
+    /// A record is intact, but its contents are invalid.
+    Invalid(String),
+    /// The index of a finished recording is damaged, so it had to be rebuilt by scanning the file.
+    ///
+    /// [`Damage::len`] covers the index and the trailer.
+    Index,
+}
+
+impl fmt::Display for Damage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes at offset {}", self.len, self.offset)?;
+        if let Some(after) = self.after {
+            write!(f, " (after {after:.3?})")?;
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+impl fmt::Display for DamageReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => f.write_str("recording ends in the middle of a record"),
+            Self::MissingSync => f.write_str("missing sync marker"),
+            Self::TooLarge(size) => write!(f, "record of {size} bytes exceeds the size limit"),
+            Self::ChecksumMismatch => f.write_str("checksum mismatch"),
+            Self::Invalid(reason) => write!(f, "invalid record: {reason}"),
+            Self::Index => f.write_str("index is damaged"),
+        }
+    }
+}
+
+/// Position of a message in a recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IndexEntry {
+    /// Time since the start of the recording, in microseconds.
+    time: u64,
+    /// File offset of the message's record.
+    offset: u64,
+}
+
+/// Encodes a record with the given type, time and payload.
+fn encode_record(tag: u8, time: u64, payload: &[u8]) -> io::Result<Vec<u8>> {
+    let size = u32::try_from(payload.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("record of {} bytes is too large", payload.len()),
+        )
+    })?;
+    let mut buf = Vec::with_capacity(RECORD_OVERHEAD as usize + payload.len());
+    buf.extend_from_slice(&SYNC);
+    buf.push(tag);
+    buf.extend_from_slice(&time.to_le_bytes());
+    buf.extend_from_slice(&size.to_le_bytes());
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(&buf[4..]);
+    crc.update(payload);
+    buf.extend_from_slice(&crc.finalize().to_le_bytes());
+    buf.extend_from_slice(payload);
+    Ok(buf)
+}
+
+/// Writes a recording.
+#[derive(Debug)]
+pub struct RecordingWriter<W: Write> {
+    writer: W,
+    /// Number of bytes written so far.
+    position: u64,
+    index: Vec<IndexEntry>,
+}
+
+impl RecordingWriter<BufWriter<File>> {
+    /// Creates a recording at `path`, replacing any existing file.
+    pub fn create<P: AsRef<Path>>(path: P, header: &Header) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), header)
+    }
+}
+
+impl<W: Write> RecordingWriter<W> {
+    /// Starts a recording by writing `header` to `writer`.
+    pub fn new(mut writer: W, header: &Header) -> io::Result<Self> {
+        let header = header.encode()?;
+        writer.write_all(&header)?;
+        Ok(Self {
+            writer,
+            position: header.len() as u64,
+            index: Vec::new(),
+        })
+    }
+
+    /// Returns the number of messages written so far.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Appends a message to the recording.
+    ///
+    /// `time` is the time since the start of the recording. Fails with
+    /// [`io::ErrorKind::InvalidInput`] if it is earlier than that of the previous message.
+    pub fn write(&mut self, time: Duration, message: &TrackingMessage) -> io::Result<()> {
+        let time = u64::try_from(time.as_micros()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "message time out of range")
+        })?;
+        if let Some(last) = self.index.last() {
+            if time < last.time {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "message time {time}µs is earlier than that of the previous message ({}µs)",
+                        last.time,
+                    ),
+                ));
+            }
+        }
+
+        let record = encode_record(TAG_MESSAGE, time, &Codec::Bincode.encode(message)?)?;
+        self.writer.write_all(&record)?;
+
+        self.index.push(IndexEntry {
+            time,
+            offset: self.position,
+        });
+        self.position += record.len() as u64;
+        Ok(())
+    }
+
+    /// Flushes all written messages to the underlying writer.
+    ///
+    /// If the recording is never [finished](RecordingWriter::finish), the flushed messages can
+    /// still be read.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Finishes the recording by writing the index, and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut payload = Vec::with_capacity(8 + 16 * self.index.len());
+        payload.extend_from_slice(&(self.index.len() as u64).to_le_bytes());
+        for entry in &self.index {
+            payload.extend_from_slice(&entry.time.to_le_bytes());
+            payload.extend_from_slice(&entry.offset.to_le_bytes());
+        }
+        let duration = self.index.last().map_or(0, |entry| entry.time);
+        let mut buf = encode_record(TAG_INDEX, duration, &payload)?;
+        buf.extend_from_slice(&self.position.to_le_bytes());
+        buf.extend_from_slice(&TRAILER_MAGIC);
+        self.writer.write_all(&buf)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads a recording.
+///
+/// Damaged parts of the recording are skipped (see the [module documentation](self)). They can be
+/// inspected via [`RecordingReader::damage`].
+#[derive(Debug)]
+pub struct RecordingReader<R> {
+    records: Records<R>,
+    header: Header,
+    index: Vec<IndexEntry>,
+    /// Index of the message [`RecordingReader::next_message`] returns.
+    next: usize,
+    /// File offset of the index record, or the file size if there is none.
+    end: u64,
+    finished: bool,
+    damage: Vec<Damage>,
+}
+
+impl RecordingReader<File> {
+    /// Opens the recording at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> RecordingReader<R> {
+    /// Opens a recording, and loads its index.
+    ///
+    /// If the recording wasn't finished or its index is damaged, the whole file is scanned to
+    /// rebuild the index.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if `reader` does not contain a recording, or if
+    /// the recorded messages can't be decoded by this library (see [`Header::check`]).
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        reader.rewind()?;
+        let mut reader = BufReader::new(reader);
+        let header = Header::read(&mut reader)?;
+        header.check()?;
+
+        let data_start = reader.stream_position()?;
+        let len = reader.seek(SeekFrom::End(0))?;
+        let mut this = Self {
+            records: Records {
+                reader,
+                position: len,
+                len,
+                limits: DecodeLimits::default(),
+            },
+            header,
+            index: Vec::new(),
+            next: 0,
+            end: len,
+            finished: false,
+            damage: Vec::new(),
+        };
+        match this.read_index(data_start)? {
+            Some((index, offset)) => {
+                this.index = index;
+                this.end = offset;
+                this.finished = true;
+            }
+            None => this.scan(data_start)?,
+        }
+        Ok(this)
+    }
+
+    /// Returns the [`Header`] of the recording.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Returns the number of messages in the recording.
+    ///
+    /// If the recording is damaged, this may include messages that turn out to be unreadable.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Returns the time of the last message in the recording.
+    pub fn duration(&self) -> Duration {
+        self.index
+            .last()
+            .map_or(Duration::ZERO, |entry| Duration::from_micros(entry.time))
+    }
+
+    /// Returns whether the recording was [finished](RecordingWriter::finish).
+    ///
+    /// If it wasn't, the index was rebuilt by scanning the file.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Returns the damaged regions of the recording found so far, in the order they were found.
+    ///
+    /// When the index has to be rebuilt, all damage is found while opening the recording.
+    /// Otherwise, damaged messages are only found as they are read.
+    pub fn damage(&self) -> &[Damage] {
+        &self.damage
+    }
+
+    /// Returns the number of the message the next call to [`RecordingReader::next_message`] reads.
+    pub fn position(&self) -> usize {
+        self.next
+    }
+
+    /// Reads the next message.
+    ///
+    /// Damaged messages are skipped and recorded in [`RecordingReader::damage`]. Returns [`None`]
+    /// once the end of the recording is reached.
+    pub fn next_message(&mut self) -> io::Result<Option<Record>> {
+        while let Some(&entry) = self.index.get(self.next) {
+            self.next += 1;
+            let reason = match self.records.read(entry.offset)? {
+                Ok(record) if record.tag != TAG_MESSAGE => DamageReason::Invalid(format!(
+                    "expected a message, found record type {}",
+                    record.tag,
+                )),
+                Ok(record) => match Codec::Bincode.decode(&record.payload) {
+                    Ok(message) => {
+                        return Ok(Some(Record {
+                            time: Duration::from_micros(entry.time),
+                            message,
+                        }))
+                    }
+                    Err(e) => DamageReason::Invalid(e.to_string()),
+                },
+                Err(reason) => reason,
+            };
+
+            let end = self
+                .index
+                .get(self.next)
+                .map_or(self.end, |next| next.offset);
+            let after = self.next.checked_sub(2).map(|i| self.index[i].time);
+            self.report(Damage {
+                offset: entry.offset,
+                len: end - entry.offset,
+                after: after.map(Duration::from_micros),
+                reason,
+            });
+        }
+        Ok(None)
+    }
+
+    /// Moves to the first message at or after `time`.
+    pub fn seek(&mut self, time: Duration) -> io::Result<()> {
+        let time = u64::try_from(time.as_micros()).unwrap_or(u64::MAX);
+        let n = self.index.partition_point(|entry| entry.time < time);
+        self.seek_to_message(n)
+    }
+
+    /// Moves to the message with number `n`.
+    ///
+    /// If `n` is past the end of the recording, [`RecordingReader::next_message`] will return
+    /// [`None`].
+    pub fn seek_to_message(&mut self, n: usize) -> io::Result<()> {
+        self.next = n.min(self.index.len());
+        Ok(())
+    }
+
+    fn report(&mut self, damage: Damage) {
+        if self.damage.contains(&damage) {
+            // Found before, when the message was read prior to seeking back.
+            return;
+        }
+        tracing::warn!("skipping damaged part of recording: {damage}");
+        self.damage.push(damage);
+    }
+
+    /// Reads the index of a finished recording, and returns it along with its file offset.
+    ///
+    /// Returns [`None`] if the recording wasn't finished, or if its index is damaged.
+    fn read_index(&mut self, data_start: u64) -> io::Result<Option<(Vec<IndexEntry>, u64)>> {
+        let len = self.records.len;
+        if len < data_start + TRAILER_SIZE {
+            return Ok(None);
+        }
+        let index_end = len - TRAILER_SIZE;
+        let mut trailer = [0; TRAILER_SIZE as usize];
+        self.records.read_exact_at(index_end, &mut trailer)?;
+        if trailer[8..] != TRAILER_MAGIC {
+            return Ok(None);
+        }
+
+        let offset = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+        let index = if (data_start..index_end).contains(&offset) {
+            self.records
+                .read(offset)?
+                .ok()
+                .filter(|record| record.tag == TAG_INDEX && offset + record.len() == index_end)
+                .and_then(|record| parse_index(&record.payload, data_start, offset))
+        } else {
+            None
+        };
+        if index.is_none() {
+            let offset = offset.clamp(data_start, index_end);
+            self.report(Damage {
+                offset,
+                len: len - offset,
+                after: None,
+                reason: DamageReason::Index,
+            });
+        }
+        Ok(index.map(|index| (index, offset)))
+    }
+
+    /// Rebuilds the index by reading every record, skipping damaged ones.
+    ///
+    /// Stops at the first intact index record.
+    fn scan(&mut self, data_start: u64) -> io::Result<()> {
+        let mut offset = data_start;
+        while offset < self.records.len {
+            let reason = match self.records.read(offset)? {
+                Ok(record) if record.tag == TAG_INDEX => {
+                    self.end = offset;
+                    break;
+                }
+                Ok(record) if record.tag != TAG_MESSAGE => {
+                    DamageReason::Invalid(format!("unknown record type {}", record.tag))
+                }
+                Ok(record)
+                    if self
+                        .index
+                        .last()
+                        .is_some_and(|last| record.time < last.time) =>
+                {
+                    DamageReason::Invalid("message is out of order".into())
+                }
+                Ok(record) => {
+                    self.index.push(IndexEntry {
+                        time: record.time,
+                        offset,
+                    });
+                    offset += record.len();
+                    continue;
+                }
+                Err(reason) => reason,
+            };
+
+            let end = self
+                .records
+                .find_sync(offset + 1)?
+                .unwrap_or(self.records.len);
+            match self.damage.last_mut() {
+                // Merge with the previous region if no intact record was found in between.
+                Some(last) if last.offset + last.len == offset => last.len = end - last.offset,
+                // Already reported as a damaged index.
+                Some(last) if (last.offset..last.offset + last.len).contains(&offset) => {}
+                _ => {
+                    let after = self.index.last().map(|entry| entry.time);
+                    self.report(Damage {
+                        offset,
+                        len: end - offset,
+                        after: after.map(Duration::from_micros),
+                        reason,
+                    })
+                }
+            }
+            offset = end;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the payload of the index record at file offset `end`.
+fn parse_index(payload: &[u8], data_start: u64, end: u64) -> Option<Vec<IndexEntry>> {
+    let (count, entries) = payload.split_first_chunk::<8>()?;
+    if Some(entries.len() as u64) != u64::from_le_bytes(*count).checked_mul(16) {
+        return None;
+    }
+    let index = entries
+        .chunks_exact(16)
+        .map(|chunk| IndexEntry {
+            time: u64::from_le_bytes(chunk[..8].try_into().unwrap()),
+            offset: u64::from_le_bytes(chunk[8..].try_into().unwrap()),
+        })
+        .collect::<Vec<_>>();
+    let ordered = index.windows(2).all(|pair| {
+        pair[0].time <= pair[1].time && pair[0].offset + RECORD_OVERHEAD <= pair[1].offset
+    });
+    let in_bounds = index
+        .iter()
+        .all(|entry| entry.offset >= data_start && entry.offset + RECORD_OVERHEAD <= end);
+    (ordered && in_bounds).then_some(index)
+}
+
+/// A record read from a recording, before its payload is decoded.
+struct RawRecord {
+    tag: u8,
+    time: u64,
+    payload: Vec<u8>,
+}
+
+impl RawRecord {
+    /// Returns the size of the record in the file.
+    fn len(&self) -> u64 {
+        RECORD_OVERHEAD + self.payload.len() as u64
+    }
+}
+
+/// Random access to the records of a recording.
+#[derive(Debug)]
+struct Records<R> {
+    reader: BufReader<R>,
+    /// Current file offset of `reader`.
+    position: u64,
+    /// Size of the file.
+    len: u64,
+    limits: DecodeLimits,
+}
+
+impl<R: Read + Seek> Records<R> {
+    fn seek(&mut self, offset: u64) -> io::Result<()> {
+        if offset != self.position {
+            // Reading the records in order doesn't need to seek, which keeps the buffer intact.
+            self.reader.seek(SeekFrom::Start(offset))?;
+            self.position = offset;
+        }
+        Ok(())
+    }
+
+    fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.seek(offset)?;
+        self.reader.read_exact(buf)?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Reads and verifies the record at `offset`.
+    ///
+    /// Returns the reason if there is no intact record at `offset`. I/O errors are returned as-is.
+    fn read(&mut self, offset: u64) -> io::Result<Result<RawRecord, DamageReason>> {
+        if offset + RECORD_OVERHEAD > self.len {
+            return Ok(Err(DamageReason::Truncated));
+        }
+        let mut head = [0; RECORD_OVERHEAD as usize];
+        self.read_exact_at(offset, &mut head)?;
+        if head[..4] != SYNC {
+            return Ok(Err(DamageReason::MissingSync));
+        }
+        let tag = head[4];
+        let time = u64::from_le_bytes(head[5..13].try_into().unwrap());
+        let size = u32::from_le_bytes(head[13..17].try_into().unwrap());
+        let crc = u32::from_le_bytes(head[17..].try_into().unwrap());
+        // Index records aren't subject to the limits, since their size depends on the length of
+        // the recording.
+        if tag != TAG_INDEX && self.limits.check_frame_size(size).is_err() {
+            return Ok(Err(DamageReason::TooLarge(size)));
+        }
+        if offset + RECORD_OVERHEAD + u64::from(size) > self.len {
+            return Ok(Err(DamageReason::Truncated));
+        }
+
+        let mut payload = vec![0; size as usize];
+        self.read_exact_at(offset + RECORD_OVERHEAD, &mut payload)?;
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&head[4..17]);
+        hasher.update(&payload);
+        if hasher.finalize() != crc {
+            return Ok(Err(DamageReason::ChecksumMismatch));
+        }
+        Ok(Ok(RawRecord { tag, time, payload }))
+    }
+
+    /// Returns the offset of the first [`SYNC`] marker at or after `offset`.
+    fn find_sync(&mut self, offset: u64) -> io::Result<Option<u64>> {
+        self.seek(offset)?;
+        // Holds the data read so far, minus anything that can't be part of a marker anymore.
+        let mut window = Vec::new();
+        let mut window_start = offset;
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            window.extend_from_slice(buf);
+            let n = buf.len();
+            self.reader.consume(n);
+            self.position += n as u64;
+
+            if let Some(i) = window.windows(SYNC.len()).position(|w| w == SYNC) {
+                return Ok(Some(window_start + i as u64));
+            }
+            let discard = window.len().saturating_sub(SYNC.len() - 1);
+            window.drain(..discard);
+            window_start += discard as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+
+    fn msg(timestamp: u32) -> TrackingMessage {
+        TrackingMessage {
+            timestamp,
+            timing: None,
+            faces: Vec::new(),
+        }
+    }
+
+    /// Returns a [`Header`] for test recordings.
+    pub(crate) fn header() -> Header {
+        Header::new(TrackerInfo {
+            name: "test".into(),
+            version: "1.0".into(),
+        })
+    }
+
+    /// Records messages at 0, 10, 20, ... ms, with their timestamp set to their number.
+    fn record(count: u32) -> RecordingWriter<Vec<u8>> {
+        let mut writer = RecordingWriter::new(Vec::new(), &header()).unwrap();
+        for i in 0..count {
+            writer
+                .write(Duration::from_millis(i.into()) * 10, &msg(i))
+                .unwrap();
+        }
+        writer
+    }
+
+    /// Returns the file offsets of the message records in an intact recording.
+    fn offsets(buf: &[u8]) -> Vec<usize> {
+        let reader = RecordingReader::new(Cursor::new(buf)).unwrap();
+        reader
+            .index
+            .iter()
+            .map(|entry| entry.offset as usize)
+            .collect()
+    }
+
+    fn open(buf: Vec<u8>) -> RecordingReader<Cursor<Vec<u8>>> {
+        RecordingReader::new(Cursor::new(buf)).unwrap()
+    }
+
+    fn read_all<R: Read + Seek>(reader: &mut RecordingReader<R>) -> Vec<(Duration, u32)> {
+        let mut messages = Vec::new();
+        while let Some(record) = reader.next_message().unwrap() {
+            messages.push((record.time, record.message.timestamp));
+        }
+        messages
+    }
+
+    fn timestamps<R: Read + Seek>(reader: &mut RecordingReader<R>) -> Vec<u32> {
+        read_all(reader).into_iter().map(|(_, ts)| ts).collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut reader = open(record(5).finish().unwrap());
+        assert!(reader.is_finished());
+        assert_eq!(reader.header().tracker, header().tracker);
+        assert_eq!(reader.len(), 5);
+        assert_eq!(reader.duration(), Duration::from_millis(40));
+
+        let messages = read_all(&mut reader);
+        assert_eq!(messages.len(), 5);
+        assert_eq!(messages[3], (Duration::from_millis(30), 3));
+        assert!(reader.next_message().unwrap().is_none());
+        assert!(reader.damage().is_empty());
+    }
+
+    #[test]
+    fn seek() {
+        let mut reader = open(record(5).finish().unwrap());
+        reader.seek(Duration::from_millis(15)).unwrap();
+        assert_eq!(reader.position(), 2);
+        assert_eq!(reader.next_message().unwrap().unwrap().message.timestamp, 2);
+        reader.seek(Duration::ZERO).unwrap();
+        assert_eq!(read_all(&mut reader).len(), 5);
+        reader.seek_to_message(4).unwrap();
+        assert_eq!(read_all(&mut reader), [(Duration::from_millis(40), 4)]);
+        reader.seek(Duration::from_secs(1)).unwrap();
+        assert!(reader.next_message().unwrap().is_none());
+    }
+
+    #[test]
+    fn unfinished() {
+        let mut writer = record(3);
+        writer.flush().unwrap();
+        let buf = writer.writer;
+
+        let mut reader = open(buf.clone());
+        assert!(!reader.is_finished());
+        assert_eq!(timestamps(&mut reader), [0, 1, 2]);
+        assert!(reader.damage().is_empty());
+
+        // Every complete message before the truncation is recovered.
+        let last = offsets(&buf)[2];
+        for len in [buf.len() - 1, last + 5, last + 1] {
+            let mut reader = open(buf[..len].to_vec());
+            assert_eq!(timestamps(&mut reader), [0, 1]);
+            assert_eq!(
+                reader.damage(),
+                [Damage {
+                    offset: last as u64,
+                    len: (len - last) as u64,
+                    after: Some(Duration::from_millis(10)),
+                    reason: DamageReason::Truncated,
+                }]
+            );
+        }
+
+        // Killed while writing the index.
+        let finished = record(3).finish().unwrap();
+        let mut reader = open(finished[..finished.len() - 1].to_vec());
+        assert!(!reader.is_finished());
+        assert_eq!(timestamps(&mut reader), [0, 1, 2]);
+        assert!(reader.damage().is_empty());
+    }
+
+    #[test]
+    fn empty() {
+        let mut reader = open(record(0).finish().unwrap());
+        assert!(reader.is_finished());
+        assert!(reader.is_empty());
+        assert_eq!(reader.duration(), Duration::ZERO);
+        assert!(reader.next_message().unwrap().is_none());
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut writer = record(2);
+        let err = writer.write(Duration::ZERO, &msg(0)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(writer.len(), 2);
+    }
+
+    #[test]
+    fn invalid_header() {
+        let mut buf = record(1).finish().unwrap();
+        let err = RecordingReader::new(Cursor::new(&buf[4..])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        buf[4] += 1;
+        let err = RecordingReader::new(Cursor::new(buf)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("version"), "{err}");
+    }
+
+    #[test]
+    fn incompatible_fingerprint() {
+        let mut header = header();
+        header.fingerprint ^= 1;
+        let buf = RecordingWriter::new(Vec::new(), &header)
+            .unwrap()
+            .finish()
+            .unwrap();
+
+        // The header of incompatible recordings can still be inspected.
+        assert_eq!(Header::read(&*buf).unwrap().fingerprint, header.fingerprint);
+        let err = RecordingReader::new(Cursor::new(buf)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("fingerprint"), "{err}");
+    }
+
+    #[test]
+    fn corrupted_message() {
+        let mut buf = record(4).finish().unwrap();
+        let offsets = offsets(&buf);
+        buf[offsets[1] + RECORD_OVERHEAD as usize] ^= 0xff;
+
+        // With an intact index, the message is skipped when it's read.
+        let mut reader = open(buf.clone());
+        assert!(reader.is_finished());
+        assert!(reader.damage().is_empty());
+        assert_eq!(timestamps(&mut reader), [0, 2, 3]);
+        let damage = Damage {
+            offset: offsets[1] as u64,
+            len: (offsets[2] - offsets[1]) as u64,
+            after: Some(Duration::ZERO),
+            reason: DamageReason::ChecksumMismatch,
+        };
+        assert_eq!(reader.damage(), std::slice::from_ref(&damage));
+
+        // Reading it again doesn't report it twice.
+        reader.seek(Duration::ZERO).unwrap();
+        assert_eq!(timestamps(&mut reader), [0, 2, 3]);
+        assert_eq!(reader.damage(), std::slice::from_ref(&damage));
+
+        // Without the index, the reader resyncs at the next message.
+        buf.truncate(buf.len() - 1);
+        let mut reader = open(buf);
+        assert_eq!(reader.damage(), [damage]);
+        assert_eq!(timestamps(&mut reader), [0, 2, 3]);
+    }
+
+    #[test]
+    fn resync() {
+        let mut writer = record(3);
+        writer.flush().unwrap();
+        let buf = writer.writer;
+        let offsets = offsets(&buf);
+
+        // Garbage inserted between records, including something that looks like a record.
+        let mut garbage = b"garbage".to_vec();
+        garbage.extend_from_slice(&SYNC);
+        garbage.extend_from_slice(&[0; 30]);
+        let mut damaged = buf[..offsets[1]].to_vec();
+        damaged.extend_from_slice(&garbage);
+        damaged.extend_from_slice(&buf[offsets[1]..]);
+
+        let mut reader = open(damaged);
+        assert_eq!(timestamps(&mut reader), [0, 1, 2]);
+        assert_eq!(
+            reader.damage(),
+            [Damage {
+                offset: offsets[1] as u64,
+                len: garbage.len() as u64,
+                after: Some(Duration::ZERO),
+                reason: DamageReason::MissingSync,
+            }]
+        );
+
+        // A damaged sync marker loses only that message.
+        let mut damaged = buf.clone();
+        damaged[offsets[0]] = 0;
+        let mut reader = open(damaged);
+        assert_eq!(timestamps(&mut reader), [1, 2]);
+        assert_eq!(reader.damage().len(), 1);
+        assert_eq!(reader.damage()[0].after, None);
+        assert_eq!(reader.damage()[0].len, (offsets[1] - offsets[0]) as u64);
+    }
+
+    #[test]
+    fn corrupted_index() {
+        let mut buf = record(3).finish().unwrap();
+        let len = buf.len();
+        // Damage the index itself.
+        buf[len - TRAILER_SIZE as usize - 1] ^= 1;
+        let mut reader = open(buf.clone());
+        assert!(!reader.is_finished());
+        assert_eq!(reader.damage().len(), 1);
+        assert_eq!(reader.damage()[0].reason, DamageReason::Index);
+        assert_eq!(timestamps(&mut reader), [0, 1, 2]);
+
+        // Point the trailer at the wrong offset.
+        buf[len - TRAILER_SIZE as usize] ^= 1;
+        let mut reader = open(buf);
+        assert_eq!(reader.damage()[0].reason, DamageReason::Index);
+        assert_eq!(timestamps(&mut reader), [0, 1, 2]);
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_damage(
+            flips in vec((any::<prop::sample::Index>(), 1..=u8::MAX), 0..4),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let buf = record(8).finish().unwrap();
+            let offsets = offsets(&buf);
+            let record_len = offsets[1] - offsets[0];
+
+            let mut damaged = buf.clone();
+            for (i, x) in &flips {
+                damaged[offsets[0] + i.index(buf.len() - offsets[0])] ^= x;
+            }
+            damaged.truncate(offsets[0] + cut.index(buf.len() - offsets[0] + 1));
+
+            // Exactly the messages whose records are untouched are recovered.
+            let intact = (0..8u32)
+                .filter(|&i| {
+                    let start = offsets[i as usize];
+                    let end = start + record_len;
+                    end <= damaged.len() && damaged[start..end] == buf[start..end]
+                })
+                .collect::<Vec<_>>();
+            let mut reader = open(damaged);
+            prop_assert_eq!(timestamps(&mut reader), intact);
+        }
+    }
+}
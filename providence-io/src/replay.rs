@@ -0,0 +1,701 @@
+//! Interactive replay of recordings.
+//!
+//! A [`Replayer`] publishes the messages of a recording at the pace they were recorded at, while
+//! allowing the playback to be controlled via [`Command`]s: changing the speed, pausing, stepping
+//! through single messages, seeking, and looping a part of the recording.
+//!
+//! Commands are sent via [`Controls`], either directly or as text (see [`Command`]'s [`FromStr`]
+//! implementation for the syntax). [`Controls::serve`] reads text commands from a stream like
+//! stdin, and [`Controls::listen`] accepts them on a TCP socket. Every command is answered with a
+//! line containing either `ok` or `error: <reason>`.
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Read, Seek, Write},
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    str::FromStr,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_io::Async;
+use futures_lite::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+    StreamExt as _,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    data::{Timing, TrackingMessage},
+    net::Publisher,
+    recording::{Record, RecordingReader},
+    task::Task,
+};
+
+/// The minimum time between the end of the loop range and its start when playback loops.
+const MIN_LOOP_GAP: Duration = Duration::from_millis(1);
+
+/// A command controlling a [`Replayer`].
+///
+/// Commands can be parsed from text, with one command per line:
+///
+/// | Command                | Effect                                                           |
+/// |------------------------|------------------------------------------------------------------|
+/// | `play`                 | [`Command::Play`]                                                |
+/// | `pause`                | [`Command::Pause`]                                               |
+/// | `toggle`               | [`Command::Toggle`]                                              |
+/// | `speed <factor>`       | [`Command::Speed`] (for example, `speed 0.25` or `speed 2x`)     |
+/// | `seek <time>`          | [`Command::Seek`]                                                |
+/// | `step`                 | [`Command::Step`]                                                |
+/// | `back`                 | [`Command::StepBack`]                                            |
+/// | `loop <start> <end>`   | [`Command::Loop`] with a range                                   |
+/// | `loop`                 | [`Command::Loop`] without a range                                |
+/// | `quit`                 | [`Command::Quit`]                                                |
+///
+/// Times are given in seconds since the start of the recording (`12.5`), or as `m:ss` or
+/// `h:mm:ss`, optionally with fractional seconds (`1:02.5`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// Resumes playback.
+    Play,
+    /// Pauses playback, keeping the last message published.
+    Pause,
+    /// Pauses playback if it is running, resumes it otherwise.
+    Toggle,
+    /// Sets the playback speed, as a factor of the recorded speed.
+    ///
+    /// The factor must be positive and finite.
+    Speed(f64),
+    /// Moves to the first message at or after the given time.
+    ///
+    /// If playback is paused, that message is published right away. Seeking outside of the loop
+    /// range resets it to the whole recording.
+    Seek(Duration),
+    /// Pauses playback, and publishes the next message.
+    Step,
+    /// Pauses playback, and publishes the message before the last one published.
+    StepBack,
+    /// Loops playback between the given start and end times, starting over at the start time.
+    ///
+    /// [`None`] loops the whole recording, which is the default.
+    Loop(Option<(Duration, Duration)>),
+    /// Stops the [`Replayer`].
+    Quit,
+}
+
+/// Error returned when parsing an invalid [`Command`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCommandError(String);
+
+impl fmt::Display for ParseCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for ParseCommandError {}
+
+impl FromStr for Command {
+    type Err = ParseCommandError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let Some(command) = words.next() else {
+            return Err(ParseCommandError("empty command".into()));
+        };
+        let args = words.collect::<Vec<_>>();
+        let command = match (command, &*args) {
+            ("play", []) => Self::Play,
+            ("pause", []) => Self::Pause,
+            ("toggle", []) => Self::Toggle,
+            ("speed", [factor]) => {
+                let factor = factor.strip_suffix('x').unwrap_or(factor);
+                match factor.parse::<f64>() {
+                    Ok(factor) if factor > 0.0 && factor.is_finite() => Self::Speed(factor),
+                    _ => {
+                        return Err(ParseCommandError(format!(
+                            "invalid speed `{factor}` (expected a positive number)"
+                        )))
+                    }
+                }
+            }
+            ("seek", [time]) => Self::Seek(parse_time(time)?),
+            ("step", []) => Self::Step,
+            ("back", []) => Self::StepBack,
+            ("loop", []) => Self::Loop(None),
+            ("loop", [start, end]) => {
+                let (start, end) = (parse_time(start)?, parse_time(end)?);
+                if end <= start {
+                    return Err(ParseCommandError(format!(
+                        "loop end `{}` is not after its start `{}`",
+                        args[1], args[0],
+                    )));
+                }
+                Self::Loop(Some((start, end)))
+            }
+            ("quit", []) => Self::Quit,
+            (
+                "play" | "pause" | "toggle" | "speed" | "seek" | "step" | "back" | "loop" | "quit",
+                _,
+            ) => {
+                return Err(ParseCommandError(format!(
+                    "wrong number of arguments for `{command}`"
+                )))
+            }
+            _ => return Err(ParseCommandError(format!("unknown command `{command}`"))),
+        };
+        Ok(command)
+    }
+}
+
+/// Parses a time given as seconds, `m:ss` or `h:mm:ss`.
+fn parse_time(s: &str) -> Result<Duration, ParseCommandError> {
+    let err = || {
+        ParseCommandError(format!(
+            "invalid time `{s}` (expected seconds or `h:mm:ss`)"
+        ))
+    };
+    let mut parts = s.rsplit(':');
+    let seconds = parts.next().unwrap().parse::<f64>().map_err(|_| err())?;
+    let mut total = seconds;
+    for (i, part) in parts.enumerate() {
+        let value = part.parse::<u32>().map_err(|_| err())?;
+        match i {
+            0 => total += f64::from(value) * 60.0,
+            1 => total += f64::from(value) * 3600.0,
+            _ => return Err(err()),
+        }
+    }
+    Duration::try_from_secs_f64(total).map_err(|_| err())
+}
+
+/// Sends [`Command`]s to a [`Replayer`].
+#[derive(Debug, Clone)]
+pub struct Controls {
+    sender: Sender<Command>,
+}
+
+impl Controls {
+    /// Sends a command to the [`Replayer`].
+    ///
+    /// Commands sent after the [`Replayer`] has stopped are ignored.
+    pub fn send(&self, command: Command) {
+        self.sender.send(command).ok();
+    }
+
+    /// Parses `line` as a [`Command`] and sends it to the [`Replayer`].
+    pub fn execute(&self, line: &str) -> Result<(), ParseCommandError> {
+        self.send(line.parse()?);
+        Ok(())
+    }
+
+    /// Executes the commands read from `reader`, one per line, until it reaches the end.
+    ///
+    /// Each command is answered by writing a line to `writer`. Empty lines are ignored.
+    pub fn serve<R: BufRead, W: Write>(&self, reader: R, mut writer: W) -> io::Result<()> {
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            writer.write_all(response(self.execute(&line)).as_bytes())?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Accepts connections on `addr`, and executes the commands sent over them.
+    ///
+    /// Commands are read and answered like they are by [`Controls::serve`]. Connections are
+    /// accepted until the returned [`ControlSocket`] is dropped.
+    pub fn listen<A: ToSocketAddrs>(&self, addr: A) -> io::Result<ControlSocket> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let controls = self.clone();
+        let task = Task::spawn(async move {
+            // (contains `Task`s so that they make progress without us polling them)
+            let mut connections = Vec::<Task<_>>::new();
+            let listener = Async::new(listener)?;
+            loop {
+                let (stream, sockaddr) = listener.accept().await?;
+                debug!("control connection from {}", sockaddr);
+                connections.retain(|task| !task.is_finished());
+
+                let controls = controls.clone();
+                connections.push(Task::spawn(async move {
+                    let mut lines = BufReader::new(&stream).lines();
+                    let mut writer = &stream;
+                    while let Some(line) = lines.next().await {
+                        let line = line?;
+                        if line.trim().is_empty() {
+                            continue;
+                        }
+                        let response = response(controls.execute(&line));
+                        writer.write_all(response.as_bytes()).await?;
+                    }
+                    Ok::<(), io::Error>(())
+                }));
+            }
+        });
+        Ok(ControlSocket {
+            addr,
+            _listener: task,
+        })
+    }
+}
+
+fn response(result: Result<(), ParseCommandError>) -> String {
+    match result {
+        Ok(()) => "ok\n".into(),
+        Err(e) => format!("error: {e}\n"),
+    }
+}
+
+/// A TCP socket accepting commands for a [`Replayer`], created by [`Controls::listen`].
+pub struct ControlSocket {
+    addr: SocketAddr,
+    _listener: Task<io::Result<()>>,
+}
+
+impl ControlSocket {
+    /// Returns the address the socket is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+/// Replays a recording, controlled by [`Command`]s.
+///
+/// Playback starts right away when [`Replayer::run`] is called, and loops the whole recording
+/// until the loop range is changed with [`Command::Loop`].
+///
+/// Every message is due at a fixed point in time, computed from its time in the recording
+/// relative to the point where playback was last (re)started, rather than from the time the
+/// previous message was published. This keeps playback from drifting, no matter how long it runs.
+///
+/// The timestamps of published messages are replaced with the time they were published at, so that
+/// clients see a continuous stream of messages even when playback is looped, seeked or sped up.
+pub struct Replayer<R> {
+    reader: RecordingReader<R>,
+    commands: Receiver<Command>,
+    sender: Sender<Command>,
+    speed: f64,
+    paused: bool,
+    /// The loop range.
+    range: (Duration, Duration),
+    /// The point in time at which the given recording time is (or was) due.
+    ///
+    /// While paused, the recording time is the current playback position.
+    anchor: (Instant, Duration),
+    /// The next message to publish, and its number.
+    pending: Option<(usize, Record)>,
+    /// The number of the last message published.
+    current: Option<usize>,
+    /// Used to timestamp published messages.
+    start: Instant,
+    epoch_offset: i64,
+}
+
+impl<R: Read + Seek> Replayer<R> {
+    /// Creates a [`Replayer`] for a recording.
+    pub fn new(reader: RecordingReader<R>) -> Self {
+        let (sender, commands) = mpsc::channel();
+        let range = (Duration::ZERO, reader.duration());
+        let now = Instant::now();
+        Self {
+            reader,
+            commands,
+            sender,
+            speed: 1.0,
+            paused: false,
+            range,
+            anchor: (now, Duration::ZERO),
+            pending: None,
+            current: None,
+            start: now,
+            epoch_offset: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_micros() as i64),
+        }
+    }
+
+    /// Returns [`Controls`] for sending [`Command`]s to this [`Replayer`].
+    pub fn controls(&self) -> Controls {
+        Controls {
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// Returns the recording being replayed.
+    pub fn recording(&self) -> &RecordingReader<R> {
+        &self.reader
+    }
+
+    /// Replays the recording through `publisher` until [`Command::Quit`] is received.
+    pub fn run(&mut self, publisher: &mut Publisher) -> io::Result<()> {
+        self.run_with(|_, message| publisher.publish(message))
+    }
+
+    /// Replays the recording until [`Command::Quit`] is received, passing every message that is
+    /// due to `output`, along with its time in the recording.
+    pub fn run_with(
+        &mut self,
+        mut output: impl FnMut(Duration, TrackingMessage),
+    ) -> io::Result<()> {
+        self.anchor = (Instant::now(), self.range.0);
+        self.reader.seek(self.range.0)?;
+        self.load()?;
+
+        loop {
+            let command = match &self.pending {
+                Some((_, record)) if !self.paused => {
+                    let timeout = self
+                        .deadline(record.time)
+                        .saturating_duration_since(Instant::now());
+                    match self.commands.recv_timeout(timeout) {
+                        Ok(command) => Some(command),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => unreachable!(),
+                    }
+                }
+                // Nothing to do until a command arrives.
+                _ => Some(self.commands.recv().unwrap()),
+            };
+
+            match command {
+                Some(Command::Quit) => return Ok(()),
+                Some(command) => self.apply(command, &mut output)?,
+                None => self.advance(&mut output)?,
+            }
+        }
+    }
+
+    fn apply(
+        &mut self,
+        command: Command,
+        output: &mut impl FnMut(Duration, TrackingMessage),
+    ) -> io::Result<()> {
+        debug!("replay command: {command:?}");
+        match command {
+            Command::Play => self.resume(),
+            Command::Pause => self.pause(),
+            Command::Toggle if self.paused => self.resume(),
+            Command::Toggle => self.pause(),
+            Command::Speed(speed) if speed > 0.0 && speed.is_finite() => {
+                self.anchor = (Instant::now(), self.position());
+                self.speed = speed;
+            }
+            Command::Speed(speed) => warn!("ignoring invalid replay speed {speed}"),
+            Command::Seek(time) => {
+                if time < self.range.0 || time > self.range.1 {
+                    self.range = (Duration::ZERO, self.reader.duration());
+                }
+                self.seek(time, output)?;
+            }
+            Command::Step => {
+                self.pause();
+                self.advance(output)?;
+            }
+            Command::StepBack => {
+                self.pause();
+                if let Some(current) = self.current.filter(|&n| n > 0) {
+                    self.reader.seek_to_message(current - 1)?;
+                    self.load()?;
+                    self.advance(output)?;
+                }
+            }
+            Command::Loop(range) => {
+                let duration = self.reader.duration();
+                match range {
+                    Some((start, end)) => {
+                        self.range = (start.min(duration), end.min(duration));
+                        self.seek(self.range.0, output)?;
+                    }
+                    None => self.range = (Duration::ZERO, duration),
+                }
+            }
+            Command::Quit => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn pause(&mut self) {
+        if !self.paused {
+            self.anchor = (Instant::now(), self.position());
+            self.paused = true;
+        }
+    }
+
+    fn resume(&mut self) {
+        if self.paused {
+            self.anchor.0 = Instant::now();
+            self.paused = false;
+        }
+    }
+
+    /// Returns the current playback position in the recording.
+    fn position(&self) -> Duration {
+        if self.paused {
+            self.anchor.1
+        } else {
+            self.anchor.1 + self.anchor.0.elapsed().mul_f64(self.speed)
+        }
+    }
+
+    /// Returns the point in time the message at recording time `time` is due.
+    fn deadline(&self, time: Duration) -> Instant {
+        self.anchor.0 + time.saturating_sub(self.anchor.1).div_f64(self.speed)
+    }
+
+    fn seek(
+        &mut self,
+        time: Duration,
+        output: &mut impl FnMut(Duration, TrackingMessage),
+    ) -> io::Result<()> {
+        self.reader.seek(time)?;
+        self.anchor = (Instant::now(), time);
+        self.load()?;
+        if self.paused {
+            // Show the message we've seeked to.
+            self.advance(output)?;
+        }
+        Ok(())
+    }
+
+    /// Publishes the pending message, and loads the one after it.
+    fn advance(&mut self, output: &mut impl FnMut(Duration, TrackingMessage)) -> io::Result<()> {
+        if let Some((number, record)) = self.pending.take() {
+            if self.paused {
+                self.anchor.1 = record.time;
+            }
+            self.current = Some(number);
+
+            let mut message = record.message;
+            message.set_timing(Timing {
+                timestamp: self.start.elapsed().as_micros() as u64,
+                epoch_offset: self.epoch_offset,
+            });
+            output(record.time, message);
+        }
+        self.load()
+    }
+
+    /// Returns the time between the end of the loop range and its start: the average interval
+    /// between the messages of the recording, but at least [`MIN_LOOP_GAP`].
+    fn loop_gap(&self) -> Duration {
+        let interval = match self.reader.len() {
+            0 | 1 => Duration::ZERO,
+            len => self.reader.duration() / (len - 1) as u32,
+        };
+        interval.max(MIN_LOOP_GAP)
+    }
+
+    /// Reads the next message into `pending`, looping back to the start of the loop range when
+    /// its end is reached.
+    fn load(&mut self) -> io::Result<()> {
+        let mut record = self.reader.next_message()?;
+        if record
+            .as_ref()
+            .is_none_or(|record| record.time > self.range.1)
+        {
+            if self.paused {
+                self.anchor.1 = self.range.0;
+            } else {
+                // The start of the range is due one message interval after the end of the range,
+                // like the next message would have been.
+                let gap = self.loop_gap().div_f64(self.speed);
+                self.anchor = (self.deadline(self.range.1) + gap, self.range.0);
+            }
+            self.reader.seek(self.range.0)?;
+            record = self
+                .reader
+                .next_message()?
+                .filter(|record| record.time <= self.range.1);
+        }
+        // `next_message` leaves the position right after the message it returned.
+        self.pending = record.map(|record| (self.reader.position() - 1, record));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::mpsc::channel, thread};
+
+    use crate::recording::{tests::header, RecordingWriter};
+
+    use super::*;
+
+    /// Creates a [`Replayer`] for a recording of `count` messages, 10 ms apart.
+    fn replayer(count: u32) -> Replayer<Cursor<Vec<u8>>> {
+        let mut writer = RecordingWriter::new(Vec::new(), &header()).unwrap();
+        for i in 0..count {
+            let message = TrackingMessage {
+                timestamp: 0,
+                timing: None,
+                faces: Vec::new(),
+            };
+            writer
+                .write(Duration::from_millis(i.into()) * 10, &message)
+                .unwrap();
+        }
+        let buf = writer.finish().unwrap();
+        Replayer::new(RecordingReader::new(Cursor::new(buf)).unwrap())
+    }
+
+    /// Runs the `commands`, then collects `count` published messages, returning the numbers of
+    /// the messages along with the time they were published at.
+    fn run(
+        replayer: &mut Replayer<Cursor<Vec<u8>>>,
+        commands: &[Command],
+        count: usize,
+    ) -> Vec<(u32, Duration)> {
+        let controls = replayer.controls();
+        for &command in commands {
+            controls.send(command);
+        }
+        if count == 0 {
+            controls.send(Command::Quit);
+        }
+
+        let start = Instant::now();
+        let mut published = Vec::new();
+        replayer
+            .run_with(|time, _| {
+                let number = (time.as_millis() / 10) as u32;
+                published.push((number, start.elapsed()));
+                if published.len() == count {
+                    controls.send(Command::Quit);
+                }
+            })
+            .unwrap();
+        published
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("play".parse(), Ok(Command::Play));
+        assert_eq!(" speed  0.25 ".parse(), Ok(Command::Speed(0.25)));
+        assert_eq!("speed 2x".parse(), Ok(Command::Speed(2.0)));
+        assert_eq!(
+            "seek 1:02.5".parse(),
+            Ok(Command::Seek(Duration::from_millis(62_500)))
+        );
+        assert_eq!(
+            "seek 1:00:00".parse(),
+            Ok(Command::Seek(Duration::from_secs(3600)))
+        );
+        assert_eq!(
+            "loop 10 20.5".parse(),
+            Ok(Command::Loop(Some((
+                Duration::from_secs(10),
+                Duration::from_millis(20_500)
+            ))))
+        );
+        assert_eq!("loop".parse(), Ok(Command::Loop(None)));
+
+        for invalid in [
+            "",
+            "jump",
+            "play now",
+            "speed",
+            "speed 0",
+            "speed -1",
+            "speed inf",
+            "seek -1",
+            "seek 1:2:3:4",
+            "seek x:00",
+            "loop 20 10",
+            "loop 10 10",
+            "loop 10",
+        ] {
+            assert!(invalid.parse::<Command>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn step_and_seek() {
+        let mut replayer = replayer(5);
+        let commands = [
+            Command::Pause,
+            Command::Step,
+            Command::Step,
+            Command::StepBack,
+            Command::Seek(Duration::from_millis(25)),
+            Command::Step,
+            Command::Step,
+        ];
+        let published = run(&mut replayer, &commands, 0);
+        let numbers = published.iter().map(|&(n, _)| n).collect::<Vec<_>>();
+        let expected = [0, 1, 0, 3, 4, 0];
+        assert_eq!(numbers, expected);
+    }
+
+    #[test]
+    fn loop_range() {
+        let mut replayer = replayer(5);
+        let commands = [
+            Command::Speed(10.0),
+            Command::Loop(Some((Duration::from_millis(10), Duration::from_millis(25)))),
+        ];
+        let published = run(&mut replayer, &commands, 6);
+        let numbers = published.iter().map(|&(n, _)| n).collect::<Vec<_>>();
+        assert_eq!(numbers, [1, 2, 1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn timing() {
+        for speed in [0.5, 2.0] {
+            let mut replayer = replayer(5);
+            // After looping, the first message is due one interval after the last.
+            let published = run(&mut replayer, &[Command::Speed(speed)], 7);
+            for (i, &(_, time)) in published.iter().enumerate() {
+                // Messages must not be published before they are due.
+                let due = Duration::from_millis(i as u64 * 10).div_f64(speed);
+                assert!(
+                    time >= due,
+                    "message {i} published at {time:?}, due at {due:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn single_message() {
+        // Looping a single message must not flood clients with it.
+        let mut replayer = replayer(1);
+        let controls = replayer.controls();
+        let thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            controls.send(Command::Quit);
+        });
+        let mut count = 0;
+        replayer.run_with(|_, _| count += 1).unwrap();
+        thread.join().unwrap();
+        assert!(count <= 200, "published {count} messages in 100 ms");
+    }
+
+    #[test]
+    fn restamp() {
+        let mut replayer = replayer(3);
+        let controls = replayer.controls();
+        let (sender, receiver) = channel();
+        let thread = thread::spawn(move || {
+            replayer.run_with(move |_, message| sender.send(message).unwrap())
+        });
+        let timestamps = receiver
+            .iter()
+            .take(6)
+            .map(|message| message.timing.unwrap().timestamp)
+            .collect::<Vec<_>>();
+        controls.send(Command::Quit);
+        thread.join().unwrap().unwrap();
+
+        // Timestamps keep increasing when playback loops.
+        assert!(
+            timestamps.windows(2).all(|w| w[0] <= w[1]),
+            "{timestamps:?}"
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Encoders and decoders for the [`ImageEncoding`]s of eye textures.
+
+use std::{error::Error, io};
+
+use crate::data::{Image, ImageEncoding};
+
+/// JPEG quality setting (1-100) used when encoding eye textures.
+#[cfg(feature = "jpeg")]
+const JPEG_QUALITY: u8 = 90;
+
+/// Encodes an [`ImageEncoding::Rgba`] image with `encoding`, returning the encoded data.
+pub(crate) fn encode(image: &Image, encoding: ImageEncoding) -> io::Result<Vec<u8>> {
+    debug_assert_eq!(image.encoding, ImageEncoding::Rgba);
+    check_size(image, 4)?;
+
+    match encoding {
+        ImageEncoding::Rgba => Ok(image.data.clone()),
+        ImageEncoding::Gray => Ok(image
+            .data
+            .chunks_exact(4)
+            .map(|px| {
+                // ITU-R BT.601 luma
+                let [r, g, b] = [px[0], px[1], px[2]].map(u32::from);
+                ((r * 77 + g * 150 + b * 29) >> 8) as u8
+            })
+            .collect()),
+        ImageEncoding::Qoi => {
+            qoi::encode_to_vec(&image.data, image.width, image.height).map_err(invalid_input)
+        }
+        #[cfg(feature = "png")]
+        ImageEncoding::Png => {
+            let mut buf = Vec::new();
+            let mut encoder = png::Encoder::new(&mut buf, image.width, image.height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().map_err(invalid_input)?;
+            writer
+                .write_image_data(&image.data)
+                .map_err(invalid_input)?;
+            writer.finish().map_err(invalid_input)?;
+            Ok(buf)
+        }
+        #[cfg(feature = "jpeg")]
+        ImageEncoding::Jpeg => {
+            let (Ok(width), Ok(height)) = (image.width.try_into(), image.height.try_into()) else {
+                return Err(invalid_input("image is too large to be encoded as JPEG"));
+            };
+            let mut buf = Vec::new();
+            jpeg_encoder::Encoder::new(&mut buf, JPEG_QUALITY)
+                .encode(&image.data, width, height, jpeg_encoder::ColorType::Rgba)
+                .map_err(invalid_input)?;
+            Ok(buf)
+        }
+        #[allow(unreachable_patterns)]
+        encoding => Err(unsupported(encoding)),
+    }
+}
+
+/// Decodes an [`Image`] to RGBA, returning the decoded pixel data.
+///
+/// The caller is responsible for checking the image size against the [`DecodeLimits`].
+///
+/// [`DecodeLimits`]: crate::data::DecodeLimits
+pub(crate) fn decode(image: &Image) -> io::Result<Vec<u8>> {
+    match image.encoding {
+        ImageEncoding::Rgba => {
+            check_size(image, 4)?;
+            Ok(image.data.clone())
+        }
+        ImageEncoding::Gray => {
+            check_size(image, 1)?;
+            Ok(gray_to_rgba(&image.data))
+        }
+        ImageEncoding::Qoi => {
+            let mut decoder = qoi::Decoder::new(&image.data)
+                .map_err(invalid_data)?
+                .with_channels(qoi::Channels::Rgba);
+            let header = decoder.header();
+            check_dimensions(image, header.width, header.height)?;
+            decoder.decode_to_vec().map_err(invalid_data)
+        }
+        #[cfg(feature = "png")]
+        ImageEncoding::Png => {
+            let mut decoder = png::Decoder::new(&*image.data);
+            decoder.set_transformations(png::Transformations::normalize_to_color8());
+            let mut reader = decoder.read_info().map_err(invalid_data)?;
+            let info = reader.info();
+            check_dimensions(image, info.width, info.height)?;
+
+            let mut buf = vec![0; reader.output_buffer_size()];
+            let output = reader.next_frame(&mut buf).map_err(invalid_data)?;
+            buf.truncate(output.buffer_size());
+            match output.color_type {
+                png::ColorType::Rgba => Ok(buf),
+                png::ColorType::Rgb => Ok(rgb_to_rgba(&buf)),
+                png::ColorType::Grayscale => Ok(gray_to_rgba(&buf)),
+                png::ColorType::GrayscaleAlpha => Ok(buf
+                    .chunks_exact(2)
+                    .flat_map(|px| [px[0], px[0], px[0], px[1]])
+                    .collect()),
+                png::ColorType::Indexed => Err(invalid_data("unexpected indexed PNG output")),
+            }
+        }
+        #[cfg(feature = "jpeg")]
+        ImageEncoding::Jpeg => {
+            let mut decoder = jpeg_decoder::Decoder::new(&*image.data);
+            decoder.read_info().map_err(invalid_data)?;
+            let info = decoder.info().unwrap();
+            check_dimensions(image, info.width.into(), info.height.into())?;
+
+            let pixels = decoder.decode().map_err(invalid_data)?;
+            match info.pixel_format {
+                jpeg_decoder::PixelFormat::RGB24 => Ok(rgb_to_rgba(&pixels)),
+                jpeg_decoder::PixelFormat::L8 => Ok(gray_to_rgba(&pixels)),
+                format => Err(invalid_data(format!(
+                    "unsupported JPEG pixel format {format:?}"
+                ))),
+            }
+        }
+        #[allow(unreachable_patterns)]
+        encoding => Err(unsupported(encoding)),
+    }
+}
+
+/// Checks that an uncompressed image has `bytes_per_pixel * width * height` bytes of data.
+pub(crate) fn check_size(image: &Image, bytes_per_pixel: u64) -> io::Result<()> {
+    let expected = u64::from(image.width) * u64::from(image.height) * bytes_per_pixel;
+    if image.data.len() as u64 != expected {
+        return Err(invalid_data(format!(
+            "{:?} image of size {}x{} has {} bytes of data (expected {})",
+            image.encoding,
+            image.width,
+            image.height,
+            image.data.len(),
+            expected,
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that the size stored in a compressed image matches the [`Image`]'s size.
+///
+/// This has to happen before any pixel data is decoded, since the [`Image`]'s size is what the
+/// [`DecodeLimits`] are checked against.
+///
+/// [`DecodeLimits`]: crate::data::DecodeLimits
+fn check_dimensions(image: &Image, width: u32, height: u32) -> io::Result<()> {
+    if (width, height) != (image.width, image.height) {
+        return Err(invalid_data(format!(
+            "{:?} image has size {}x{}, but was declared as {}x{}",
+            image.encoding, width, height, image.width, image.height,
+        )));
+    }
+    Ok(())
+}
+
+fn gray_to_rgba(gray: &[u8]) -> Vec<u8> {
+    gray.iter().flat_map(|&l| [l, l, l, 0xff]).collect()
+}
+
+#[cfg(any(feature = "png", feature = "jpeg"))]
+fn rgb_to_rgba(rgb: &[u8]) -> Vec<u8> {
+    rgb.chunks_exact(3)
+        .flat_map(|px| [px[0], px[1], px[2], 0xff])
+        .collect()
+}
+
+fn unsupported(encoding: ImageEncoding) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("image encoding {encoding:?} is not supported by this build"),
+    )
+}
+
+fn invalid_data(e: impl Into<Box<dyn Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn invalid_input(e: impl Into<Box<dyn Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> Image {
+        let data = (0..height)
+            .flat_map(|y| (0..width).flat_map(move |x| [x as u8 * 16, y as u8 * 16, 0x80, 0xff]))
+            .collect();
+        Image::from_rgba(width, height, data)
+    }
+
+    #[test]
+    fn lossless_roundtrip() {
+        let image = gradient(13, 7);
+        for encoding in ImageEncoding::ALL {
+            if encoding.is_lossy() || !encoding.is_supported() {
+                continue;
+            }
+            let encoded = image.encode(encoding).unwrap();
+            assert_eq!(encoded.encoding, encoding);
+            let decoded = encoded.to_rgba().unwrap();
+            assert_eq!(decoded.data, image.data, "{encoding:?}");
+        }
+    }
+
+    #[test]
+    fn gray() {
+        let image = Image::from_rgba(2, 1, vec![0, 0, 0, 0, 0xff, 0xff, 0xff, 0x80]);
+        let gray = image.encode(ImageEncoding::Gray).unwrap();
+        assert_eq!(gray.data, [0, 0xff]);
+        let rgba = gray.to_rgba().unwrap();
+        assert_eq!(rgba.data, [0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn jpeg() {
+        let image = gradient(16, 16);
+        let decoded = image
+            .encode(ImageEncoding::Jpeg)
+            .unwrap()
+            .to_rgba()
+            .unwrap();
+        assert_eq!(decoded.data.len(), image.data.len());
+        let max_error = decoded
+            .data
+            .iter()
+            .zip(&image.data)
+            .map(|(a, b)| a.abs_diff(*b))
+            .max();
+        assert!(max_error.unwrap() < 16, "{max_error:?}");
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let mut image = gradient(4, 4).encode(ImageEncoding::Qoi).unwrap();
+        image.width = 5;
+        let err = image.to_rgba().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut image = gradient(4, 4);
+        image.data.pop();
+        let err = image.to_rgba().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn pixel_limit() {
+        let image = gradient(8, 8).encode(ImageEncoding::Qoi).unwrap();
+        let limits = crate::data::DecodeLimits::new().with_max_image_pixels(63);
+        let err = image.to_rgba_with_limits(&limits).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn garbage() {
+        for encoding in ImageEncoding::ALL {
+            let image = Image {
+                width: 4,
+                height: 4,
+                encoding,
+                data: vec![0xAB; 13],
+            };
+            let err = image.to_rgba().unwrap_err();
+            assert!(
+                matches!(
+                    err.kind(),
+                    io::ErrorKind::InvalidData | io::ErrorKind::Unsupported
+                ),
+                "{encoding:?}: {err}",
+            );
+        }
+    }
+}
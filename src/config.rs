@@ -0,0 +1,654 @@
+//! Tracker configuration, from the command line and a TOML configuration file.
+//!
+//! Every setting has a default, which can be overridden by the configuration file, which in turn
+//! can be overridden by command-line flags.
+
+use std::{
+    env, fmt, fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{bail, ensure, Context as _};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+
+/// Maximum frame rate that can be requested from the camera.
+const MAX_FPS: u32 = 240;
+
+/// Maximum frame rate of image sequences.
+const MAX_IMAGE_FPS: f32 = 1000.0;
+
+/// Maximum interval between latency reports, in seconds (one day).
+const MAX_LATENCY_REPORT: f32 = 86_400.0;
+
+/// Eye and face tracker that publishes its results on the local network.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Path to the configuration file [default: $XDG_CONFIG_HOME/providence/config.toml]
+    #[arg(long, short, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Name of the camera to open [default: the first camera found]
+    #[arg(long, value_name = "NAME")]
+    pub camera: Option<String>,
+
+    /// Camera resolution to request, as `<width>x<height>`
+    #[arg(long, value_name = "WxH")]
+    pub resolution: Option<Resolution>,
+
+    /// Camera frame rate to request
+    #[arg(long)]
+    pub fps: Option<u32>,
+
+    /// Video file or directory of images to track instead of the camera
+    #[arg(long, short, value_name = "PATH")]
+    pub input: Option<PathBuf>,
+
+    /// How quickly to read frames from `--input`
+    #[arg(long)]
+    pub pacing: Option<Pacing>,
+
+    /// Frame rate of the image sequence in an `--input` directory
+    #[arg(long, value_name = "FPS")]
+    pub image_fps: Option<f32>,
+
+    /// TCP port to accept connections on [default: any free port]
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// IP address to accept connections on [default: all IPv4 and IPv6 addresses]
+    #[arg(long, value_name = "ADDR")]
+    pub bind: Option<IpAddr>,
+
+    /// Name to advertise the tracker as
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Human-readable name to advertise the tracker with (for example, whose tracker it is)
+    #[arg(long, value_name = "NAME")]
+    pub friendly_name: Option<String>,
+
+    /// Don't advertise the tracker via mDNS (clients have to connect to its address directly)
+    #[arg(long)]
+    pub no_mdns: bool,
+
+    /// Don't send eye textures to clients
+    #[arg(long)]
+    pub no_eye_textures: bool,
+
+    /// Apply gamma correction to the eye textures
+    #[arg(long)]
+    pub postprocess: bool,
+
+    /// Identify tracked faces using the enrolled identities
+    #[arg(long)]
+    pub recognize: bool,
+
+    /// Run without network access, and write all tracking results to a recording at PATH
+    ///
+    /// The whole input is processed as fast as possible, regardless of `--pacing`.
+    #[arg(long, short, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Enroll the person in front of the camera for face recognition, or manage enrolled
+    /// identities
+    Enroll {
+        /// Name to enroll the person as
+        #[arg(required_unless_present_any = ["list", "remove"])]
+        name: Option<String>,
+
+        /// List the enrolled identities
+        #[arg(long, conflicts_with_all = ["name", "remove"])]
+        list: bool,
+
+        /// Remove an enrolled identity
+        #[arg(long, value_name = "NAME", conflicts_with = "name")]
+        remove: Option<String>,
+    },
+}
+
+/// The complete tracker configuration.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub camera: CameraConfig,
+    pub input: InputConfig,
+    pub filter: FilterConfig,
+    /// Whether to apply gamma correction to the eye textures.
+    pub postprocess: bool,
+    pub network: NetworkConfig,
+    pub recognition: RecognitionConfig,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraConfig {
+    /// Name of the camera to open. If `None`, the first camera found is used.
+    pub device: Option<String>,
+    /// Resolution to request. If `None`, the camera's highest resolution is used.
+    pub resolution: Option<Resolution>,
+    pub fps: u32,
+    /// Which parameter to prioritize if the camera doesn't support the requested combination.
+    pub prefer: Preference,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            device: None,
+            resolution: None,
+            fps: 30,
+            prefer: Preference::Resolution,
+        }
+    }
+}
+
+/// A camera resolution, written as `<width>x<height>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid resolution `{s}` (expected `<width>x<height>`)");
+        let (width, height) = s.split_once('x').ok_or_else(err)?;
+        let (Ok(width), Ok(height)) = (width.trim().parse(), height.trim().parse()) else {
+            return Err(err());
+        };
+        Ok(Self { width, height })
+    }
+}
+
+impl TryFrom<String> for Resolution {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preference {
+    Resolution,
+    Framerate,
+}
+
+/// Offline input, read instead of the camera.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    /// Video file or directory of images to read frames from. If `None`, the camera is used.
+    pub path: Option<PathBuf>,
+    pub pacing: Pacing,
+    /// Frame rate of image sequences, which don't store the time their frames were captured at.
+    pub image_fps: f32,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            pacing: Pacing::RealTime,
+            image_fps: 30.0,
+        }
+    }
+}
+
+/// How quickly frames are read from files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pacing {
+    /// Deliver frames at the rate they were recorded at.
+    RealTime,
+    /// Deliver frames as fast as the tracker can process them.
+    Fast,
+}
+
+/// Parameters of the One Euro Filter that smoothes the face landmarks.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    /// Minimum cutoff frequency. Lower values reduce jitter when the face is still.
+    pub min_cutoff: f32,
+    /// Speed coefficient. Higher values reduce lag when the face moves quickly.
+    pub beta: f32,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            min_cutoff: 0.0001,
+            beta: 0.3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// TCP port to accept connections on. 0 picks any free port.
+    pub port: u16,
+    /// IP address to accept connections on. If `None`, all IPv4 and IPv6 addresses are used.
+    pub bind: Option<IpAddr>,
+    /// Names of the network interfaces to advertise the tracker on. If empty, the interfaces are
+    /// picked automatically.
+    pub interfaces: Vec<String>,
+    /// Names of network interfaces to never advertise the tracker on.
+    pub exclude_interfaces: Vec<String>,
+    /// Name to advertise the tracker as. If `None`, it is derived from its address.
+    pub name: Option<String>,
+    /// Human-readable name to advertise along with the tracker, up to 250 bytes long.
+    pub friendly_name: Option<String>,
+    /// Whether to advertise the tracker via mDNS.
+    pub mdns: bool,
+    /// Whether to send eye textures to clients.
+    pub eye_textures: bool,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            port: 0,
+            bind: None,
+            interfaces: Vec::new(),
+            exclude_interfaces: Vec::new(),
+            name: None,
+            friendly_name: None,
+            mdns: true,
+            eye_textures: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecognitionConfig {
+    pub enabled: bool,
+    /// Path to the identity database. If `None`, the default location is used.
+    pub database: Option<PathBuf>,
+}
+
+/// Logging settings.
+///
+/// There is no log level setting: the logger is installed by `#[zaru::main]` before `main` runs
+/// (and thus before the configuration is loaded), and it can't be reconfigured afterwards.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Interval (in seconds) at which pipeline latency statistics are logged. 0 disables them.
+    pub latency_report: f32,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            latency_report: 10.0,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Returns the latency report interval, or `None` if latency reports are disabled.
+    pub fn latency_report_interval(&self) -> Option<Duration> {
+        (self.latency_report > 0.0).then(|| Duration::from_secs_f32(self.latency_report))
+    }
+}
+
+impl Config {
+    /// Returns the default configuration file location, `$XDG_CONFIG_HOME/providence/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_home.join("providence").join("config.toml"))
+    }
+
+    /// Loads the configuration file selected by `cli`, applies the command-line overrides, and
+    /// validates the result.
+    ///
+    /// A missing configuration file is only an error if it was explicitly passed via `--config`.
+    pub fn load(cli: &Cli) -> anyhow::Result<Self> {
+        let mut config = match (&cli.config, Self::default_path()) {
+            (Some(path), _) => Self::from_file(path)?,
+            (None, Some(path)) => match Self::from_file(&path) {
+                Err(e) if is_not_found(&e) => Self::default(),
+                res => res?,
+            },
+            (None, None) => Self::default(),
+        };
+        config.apply_cli(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let s = fs::read_to_string(path)
+            .with_context(|| format!("failed to read configuration file {}", path.display()))?;
+        toml::from_str(&s)
+            .with_context(|| format!("failed to parse configuration file {}", path.display()))
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(camera) = &cli.camera {
+            self.camera.device = Some(camera.clone());
+        }
+        if let Some(resolution) = cli.resolution {
+            self.camera.resolution = Some(resolution);
+        }
+        if let Some(fps) = cli.fps {
+            self.camera.fps = fps;
+        }
+        if let Some(path) = &cli.input {
+            self.input.path = Some(path.clone());
+        }
+        if let Some(pacing) = cli.pacing {
+            self.input.pacing = pacing;
+        }
+        if let Some(fps) = cli.image_fps {
+            self.input.image_fps = fps;
+        }
+        if let Some(port) = cli.port {
+            self.network.port = port;
+        }
+        if let Some(bind) = cli.bind {
+            self.network.bind = Some(bind);
+        }
+        if let Some(name) = &cli.name {
+            self.network.name = Some(name.clone());
+        }
+        if let Some(name) = &cli.friendly_name {
+            self.network.friendly_name = Some(name.clone());
+        }
+        self.network.mdns &= !cli.no_mdns;
+        self.network.eye_textures &= !cli.no_eye_textures;
+        self.postprocess |= cli.postprocess;
+        self.recognition.enabled |= cli.recognize;
+    }
+
+    /// Checks that all settings are within their valid ranges.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let camera = &self.camera;
+        if camera.device.as_deref() == Some("") {
+            bail!("invalid configuration: `camera.device` must not be empty");
+        }
+        if let Some(res) = camera.resolution {
+            ensure!(
+                res.width > 0 && res.height > 0,
+                "invalid configuration: `camera.resolution` must not be zero (got {res})",
+            );
+        }
+        ensure!(
+            (1..=MAX_FPS).contains(&camera.fps),
+            "invalid configuration: `camera.fps` must be between 1 and {MAX_FPS} (got {})",
+            camera.fps,
+        );
+
+        let input = &self.input;
+        if let Some(path) = &input.path {
+            ensure!(
+                path.exists(),
+                "invalid configuration: `input.path` {} does not exist",
+                path.display(),
+            );
+        }
+        ensure!(
+            input.image_fps > 0.0 && input.image_fps <= MAX_IMAGE_FPS,
+            "invalid configuration: `input.image_fps` must be between 0 and {MAX_IMAGE_FPS} (got {})",
+            input.image_fps,
+        );
+
+        let filter = &self.filter;
+        ensure!(
+            filter.min_cutoff.is_finite() && filter.min_cutoff > 0.0,
+            "invalid configuration: `filter.min_cutoff` must be positive (got {})",
+            filter.min_cutoff,
+        );
+        ensure!(
+            filter.beta.is_finite() && filter.beta >= 0.0,
+            "invalid configuration: `filter.beta` must not be negative (got {})",
+            filter.beta,
+        );
+
+        if let Some(name) = &self.network.name {
+            ensure!(
+                !name.is_empty() && name.len() <= 63 && !name.contains('.'),
+                "invalid configuration: `network.name` must be 1-63 bytes long, without dots (got `{name}`)",
+            );
+        }
+        if let Some(name) = &self.network.friendly_name {
+            ensure!(
+                name.len() <= 250,
+                "invalid configuration: `network.friendly_name` must be at most 250 bytes long",
+            );
+        }
+
+        let latency_report = self.logging.latency_report;
+        ensure!(
+            (0.0..=MAX_LATENCY_REPORT).contains(&latency_report),
+            "invalid configuration: `logging.latency_report` must be between 0 and {MAX_LATENCY_REPORT} (got {latency_report})",
+        );
+        Ok(())
+    }
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> anyhow::Result<Config> {
+        let config: Config = toml::from_str(s)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn full() {
+        let config = parse(
+            r#"
+            postprocess = true
+
+            [camera]
+            device = "HD Webcam"
+            resolution = "1280x720"
+            fps = 60
+            prefer = "framerate"
+
+            [input]
+            path = "/"
+            pacing = "fast"
+            image_fps = 25
+
+            [filter]
+            min_cutoff = 0.01
+            beta = 0.5
+
+            [network]
+            port = 7123
+            bind = "127.0.0.1"
+            interfaces = ["tun0"]
+            name = "desk"
+            friendly_name = "Alex's desk"
+            mdns = false
+            eye_textures = false
+
+            [recognition]
+            enabled = true
+            database = "/tmp/identities.txt"
+
+            [logging]
+            latency_report = 0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.camera.device.as_deref(), Some("HD Webcam"));
+        assert_eq!(
+            config.camera.resolution,
+            Some(Resolution {
+                width: 1280,
+                height: 720
+            }),
+        );
+        assert_eq!(config.camera.fps, 60);
+        assert_eq!(config.camera.prefer, Preference::Framerate);
+        assert_eq!(config.input.pacing, Pacing::Fast);
+        assert_eq!(config.input.image_fps, 25.0);
+        assert_eq!(config.filter.min_cutoff, 0.01);
+        assert!(config.postprocess);
+        assert_eq!(config.network.port, 7123);
+        assert_eq!(config.network.bind, Some(IpAddr::from([127, 0, 0, 1])));
+        assert_eq!(config.network.interfaces, ["tun0"]);
+        assert_eq!(config.network.name.as_deref(), Some("desk"));
+        assert_eq!(config.network.friendly_name.as_deref(), Some("Alex's desk"));
+        assert!(!config.network.mdns);
+        assert!(!config.network.eye_textures);
+        assert!(config.recognition.enabled);
+        assert_eq!(config.logging.latency_report_interval(), None);
+    }
+
+    #[test]
+    fn invalid() {
+        for (toml, expected) in [
+            (
+                "[camera]\nfps = 0",
+                "`camera.fps` must be between 1 and 240",
+            ),
+            ("[camera]\nresolution = \"0x720\"", "`camera.resolution`"),
+            ("[camera]\nresolution = \"big\"", "invalid resolution `big`"),
+            (
+                "[camera]\nprefer = \"quality\"",
+                "unknown variant `quality`",
+            ),
+            (
+                "[filter]\nbeta = -1.0",
+                "`filter.beta` must not be negative",
+            ),
+            (
+                "[filter]\nmin_cutoff = 0.0",
+                "`filter.min_cutoff` must be positive",
+            ),
+            ("[network]\nport = 70000", "port"),
+            (
+                "[network]\nbind = \"localhost\"",
+                "invalid IP address syntax",
+            ),
+            (
+                "[network]\nname = \"my.tracker\"",
+                "`network.name` must be 1-63 bytes long, without dots",
+            ),
+            (
+                "[input]\npath = \"/nonexistent\"",
+                "`input.path` /nonexistent does not exist",
+            ),
+            (
+                "[input]\nimage_fps = 0",
+                "`input.image_fps` must be between 0 and 1000",
+            ),
+            ("[input]\npacing = \"slow\"", "unknown variant `slow`"),
+            (
+                "[logging]\nlatency_report = -1",
+                "`logging.latency_report` must be between 0 and 86400",
+            ),
+            (
+                "[logging]\nlatency_report = 1e30",
+                "`logging.latency_report` must be between 0 and 86400",
+            ),
+            ("[camera]\nfsp = 30", "unknown field `fsp`"),
+        ] {
+            let err = format!("{:#}", parse(toml).unwrap_err());
+            assert!(err.contains(expected), "{toml:?}: {err}");
+        }
+
+        let toml = format!("[network]\nfriendly_name = \"{}\"", "x".repeat(251));
+        let err = format!("{:#}", parse(&toml).unwrap_err());
+        assert!(
+            err.contains("`network.friendly_name` must be at most 250 bytes"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn cli_overrides() {
+        let cli = Cli::try_parse_from([
+            "providence",
+            "--config",
+            "/nonexistent/config.toml",
+            "--resolution",
+            "640x480",
+            "--fps",
+            "15",
+            "--pacing",
+            "fast",
+        ])
+        .unwrap();
+        let err = Config::load(&cli).unwrap_err();
+        assert!(is_not_found(&err), "{err:#}");
+
+        let mut config = parse("[camera]\nfps = 60\ndevice = \"cam\"").unwrap();
+        config.apply_cli(&cli);
+        assert_eq!(config.camera.fps, 15);
+        assert_eq!(config.camera.device.as_deref(), Some("cam"));
+        assert_eq!(config.camera.resolution.unwrap().to_string(), "640x480");
+        assert_eq!(config.input.pacing, Pacing::Fast);
+
+        assert!(Cli::try_parse_from(["providence", "--resolution", "640"]).is_err());
+        assert!(Cli::try_parse_from(["providence", "enroll"]).is_err());
+        assert!(Cli::try_parse_from(["providence", "enroll", "--list"]).is_ok());
+
+        let cli = Cli::try_parse_from(["providence", "-i", "in.mp4", "-o", "out.rec"]).unwrap();
+        assert_eq!(cli.output.as_deref(), Some(Path::new("out.rec")));
+
+        let cli = Cli::try_parse_from([
+            "providence",
+            "--bind",
+            "::1",
+            "--name",
+            "desk",
+            "--friendly-name",
+            "Desk cam",
+            "--no-mdns",
+            "--no-eye-textures",
+        ])
+        .unwrap();
+        let mut config = Config::default();
+        config.apply_cli(&cli);
+        assert_eq!(config.network.bind, Some("::1".parse().unwrap()));
+        assert_eq!(config.network.name.as_deref(), Some("desk"));
+        assert_eq!(config.network.friendly_name.as_deref(), Some("Desk cam"));
+        assert!(!config.network.mdns);
+        assert!(!config.network.eye_textures);
+    }
+}
@@ -0,0 +1,66 @@
+//! Enrollment of identities for face recognition.
+
+use std::io::{self, Write};
+
+use zaru::detection::Detector;
+use zaru::face::detection::ShortRangeNetwork;
+use zaru::face::landmark::mediapipe::{self, FaceMeshV2};
+use zaru::image::rect::RotatedRect;
+use zaru::landmark::{Estimator, LandmarkTracker};
+use zaru::linalg::vec3;
+use zaru::num::TotalF32;
+use zaru::procrustes::ProcrustesAnalyzer;
+use zaru::video::webcam::Webcam;
+
+use crate::config::CameraConfig;
+use crate::identity::{face_sample, Database, Embedding};
+
+/// Number of samples averaged into an enrolled identity.
+///
+/// This is higher than what is used to recognize a face, since the enrolled identity is the
+/// reference all future recognitions are compared against.
+const ENROLLMENT_SAMPLES: usize = 30;
+
+/// Captures the face of the person in front of the webcam, and enrolls it as `name`.
+///
+/// If several faces are in view, the one detected with the highest confidence is used.
+pub fn enroll(database: &mut Database, name: &str, camera: &CameraConfig) -> anyhow::Result<()> {
+    let mut detector = Detector::new(ShortRangeNetwork);
+    let mut tracker = LandmarkTracker::new(Estimator::new(FaceMeshV2));
+    let mut procrustes_analyzer = ProcrustesAnalyzer::new(mediapipe::reference_positions());
+    let input_ratio = detector.input_resolution().aspect_ratio().unwrap();
+
+    let mut webcam = Webcam::open(crate::webcam_opts(camera))?;
+    eprintln!("enrolling `{name}`, please look straight into the camera");
+
+    let mut embeddings = Vec::new();
+    while embeddings.len() < ENROLLMENT_SAMPLES {
+        let image = webcam.read()?;
+        if let Some(res) = tracker.track(&image) {
+            let landmarks = res.estimate();
+            let procrustes_result = procrustes_analyzer
+                .analyze(landmarks.mesh_landmarks().map(|lm| vec3(lm.x, -lm.y, lm.z)));
+            if let Some(points) = face_sample(landmarks, procrustes_result.rotation()) {
+                embeddings.push(Embedding::from_points(points));
+                eprint!(".");
+                io::stderr().flush()?;
+            }
+        } else {
+            let view_rect = image.resolution().fit_aspect_ratio(input_ratio);
+            let detections = detector.detect(&image.view(view_rect));
+            if let Some(detection) = detections
+                .iter()
+                .max_by_key(|det| TotalF32(det.confidence()))
+            {
+                let rect = detection.bounding_rect().move_by(view_rect.top_left());
+                tracker.set_roi(RotatedRect::new(rect, detection.angle()));
+            }
+        }
+    }
+    eprintln!();
+
+    database.enroll(name, Embedding::average(&embeddings).unwrap())?;
+    database.save()?;
+    eprintln!("enrolled `{name}` in {}", database.path().display());
+    Ok(())
+}
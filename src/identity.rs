@@ -0,0 +1,484 @@
+//! Persistent identity recognition.
+//!
+//! Faces are identified by a geometric descriptor (an [`Embedding`]) of their landmarks in a
+//! frontal, pose-normalized orientation. The [`Recognizer`] collects a number of these per tracked
+//! face in a background thread, and matches their average against the enrolled identities in a
+//! [`Database`] stored on disk.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::{env, thread};
+
+use providence_io::data::PersistentId;
+use zaru::face::landmark::mediapipe::LandmarkResultV2;
+use zaru::linalg::{vec3, Quat};
+
+/// Number of samples that are averaged before a face is matched against the [`Database`].
+pub const SAMPLES_PER_FACE: usize = 10;
+
+/// Maximum [`Embedding::distance`] at which a face is considered to match an enrolled identity.
+const MATCH_THRESHOLD: f32 = 0.06;
+
+/// Maximum number of samples waiting for the recognizer thread; further samples are dropped.
+const SAMPLE_QUEUE_SIZE: usize = 16;
+
+/// Maximum head rotation around the X and Y axes (in radians) at which a face is sampled.
+const MAX_SAMPLE_ANGLE: f32 = 0.25;
+
+/// Only every `LANDMARK_STRIDE`-th mesh landmark is used, to keep the embeddings small.
+const LANDMARK_STRIDE: usize = 4;
+
+/// Extracts the landmark positions used for recognition from a face.
+///
+/// `rotation` is the head rotation computed by Procrustes analysis; it is undone so that the
+/// positions are in a canonical frontal orientation. Returns `None` if the face is turned too far
+/// away from the camera for a reliable sample.
+pub fn face_sample(landmarks: &LandmarkResultV2, rotation: Quat<f32>) -> Option<Vec<[f32; 3]>> {
+    let [x, y, _] = rotation.to_rotation_xyz();
+    if x.abs() > MAX_SAMPLE_ANGLE || y.abs() > MAX_SAMPLE_ANGLE {
+        return None;
+    }
+
+    let rotation_inv = rotation.conjugate();
+    let points = landmarks
+        .mesh_landmarks()
+        .into_iter()
+        .step_by(LANDMARK_STRIDE)
+        // Flip Y to match the coordinates used for Procrustes analysis.
+        .map(|lm| (rotation_inv * vec3(lm.x, -lm.y, lm.z)).into_array())
+        .collect();
+    Some(points)
+}
+
+/// A pose-normalized geometric descriptor of a face.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Embedding(Vec<[f32; 3]>);
+
+impl Embedding {
+    /// Computes an [`Embedding`] from frontal face landmark positions.
+    ///
+    /// The points are centered on their centroid and scaled to unit RMS radius, so the result does
+    /// not depend on where in the image the face is, or on its distance to the camera.
+    pub fn from_points(mut points: Vec<[f32; 3]>) -> Self {
+        let n = points.len().max(1) as f32;
+        let centroid = points.iter().fold([0.0; 3], |acc, p| {
+            [acc[0] + p[0] / n, acc[1] + p[1] / n, acc[2] + p[2] / n]
+        });
+        for p in &mut points {
+            *p = [p[0] - centroid[0], p[1] - centroid[1], p[2] - centroid[2]];
+        }
+        let radius = (points.iter().map(|p| dot(*p, *p)).sum::<f32>() / n).sqrt();
+        if radius > 0.0 {
+            for p in &mut points {
+                *p = p.map(|c| c / radius);
+            }
+        }
+        Self(points)
+    }
+
+    /// Computes the (renormalized) mean of several embeddings of the same face.
+    ///
+    /// Returns `None` if `embeddings` is empty or the embeddings don't have the same size.
+    pub fn average(embeddings: &[Embedding]) -> Option<Self> {
+        let first = embeddings.first()?;
+        if embeddings.iter().any(|e| e.0.len() != first.0.len()) {
+            return None;
+        }
+        let n = embeddings.len() as f32;
+        let points = (0..first.0.len())
+            .map(|i| {
+                embeddings.iter().fold([0.0; 3], |acc, e| {
+                    let p = e.0[i];
+                    [acc[0] + p[0] / n, acc[1] + p[1] / n, acc[2] + p[2] / n]
+                })
+            })
+            .collect();
+        Some(Self::from_points(points))
+    }
+
+    /// Returns the RMS distance between corresponding points of two embeddings.
+    ///
+    /// Embeddings of different sizes are infinitely far apart.
+    pub fn distance(&self, other: &Embedding) -> f32 {
+        if self.0.len() != other.0.len() || self.0.is_empty() {
+            return f32::INFINITY;
+        }
+        let sum = self
+            .0
+            .iter()
+            .zip(&other.0)
+            .map(|(a, b)| {
+                let d = [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+                dot(d, d)
+            })
+            .sum::<f32>();
+        (sum / self.0.len() as f32).sqrt()
+    }
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// An enrolled identity.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub name: String,
+    pub embedding: Embedding,
+}
+
+/// The on-disk database of enrolled identities.
+///
+/// The file contains one identity per line: its name, a tab character, and the space-separated
+/// coordinates of its [`Embedding`].
+#[derive(Debug)]
+pub struct Database {
+    path: PathBuf,
+    identities: Vec<Identity>,
+}
+
+impl Database {
+    /// Returns the default database location, `$XDG_DATA_HOME/providence/identities.txt`.
+    pub fn default_path() -> Option<PathBuf> {
+        let data_home = match env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+        };
+        Some(data_home.join("providence").join("identities.txt"))
+    }
+
+    /// Opens the database at `path`.
+    ///
+    /// If the file does not exist, an empty database is returned, and the file will be created by
+    /// [`Database::save`].
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self {
+                    path,
+                    identities: Vec::new(),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut identities = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let identity = parse_identity(&line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed identity in {} line {}", path.display(), i + 1),
+                )
+            })?;
+            identities.push(identity);
+        }
+        Ok(Self { path, identities })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn identities(&self) -> &[Identity] {
+        &self.identities
+    }
+
+    /// Adds an identity to the database, replacing any existing identity with the same name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidInput`] if `name` is empty or contains
+    /// control characters.
+    pub fn enroll(&mut self, name: &str, embedding: Embedding) -> io::Result<()> {
+        if name.is_empty() || name.chars().any(char::is_control) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid identity name {name:?}"),
+            ));
+        }
+        self.remove(name);
+        self.identities.push(Identity {
+            name: name.to_string(),
+            embedding,
+        });
+        Ok(())
+    }
+
+    /// Removes the identity called `name`, returning whether it was enrolled.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.identities.len();
+        self.identities.retain(|identity| identity.name != name);
+        self.identities.len() != len
+    }
+
+    /// Returns the name of the enrolled identity closest to `embedding`, if it is close enough.
+    pub fn identify(&self, embedding: &Embedding) -> Option<&str> {
+        self.identities
+            .iter()
+            .map(|identity| (identity.embedding.distance(embedding), identity))
+            .filter(|(distance, _)| *distance <= MATCH_THRESHOLD)
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, identity)| &*identity.name)
+    }
+
+    /// Writes the database back to disk, creating its parent directory if necessary.
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Write to a temporary file first, so that a crash can't leave a truncated database.
+        let tmp = self.path.with_extension("tmp");
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        for identity in &self.identities {
+            write!(file, "{}\t", identity.name)?;
+            let coords = identity.embedding.0.iter().flatten();
+            for (i, coord) in coords.enumerate() {
+                if i != 0 {
+                    write!(file, " ")?;
+                }
+                write!(file, "{coord}")?;
+            }
+            writeln!(file)?;
+        }
+        file.into_inner()?.sync_all()?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+fn parse_identity(line: &str) -> Option<Identity> {
+    let (name, coords) = line.split_once('\t')?;
+    let coords = coords
+        .split_ascii_whitespace()
+        .map(|c| c.parse::<f32>().ok().filter(|c| c.is_finite()))
+        .collect::<Option<Vec<_>>>()?;
+    if name.is_empty() || coords.is_empty() || coords.len() % 3 != 0 {
+        return None;
+    }
+    Some(Identity {
+        name: name.to_string(),
+        embedding: Embedding(coords.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect()),
+    })
+}
+
+/// Recognizes tracked faces in a background thread.
+///
+/// Faces start out as [`PersistentId::InProgress`]. Once [`SAMPLES_PER_FACE`] samples of a face
+/// have been [submitted][Recognizer::submit], it either becomes [`PersistentId::Available`] with
+/// the name of the matching identity, or [`PersistentId::Unknown`].
+#[derive(Clone)]
+pub struct Recognizer {
+    samples: SyncSender<(u32, Vec<[f32; 3]>)>,
+    states: Arc<Mutex<HashMap<u32, PersistentId>>>,
+}
+
+impl Recognizer {
+    /// Spawns the recognizer thread, matching faces against the identities in `database`.
+    pub fn spawn(database: Database) -> io::Result<Self> {
+        let (samples, recv) = mpsc::sync_channel(SAMPLE_QUEUE_SIZE);
+        let states = Arc::new(Mutex::new(HashMap::new()));
+        let states2 = states.clone();
+        thread::Builder::new()
+            .name("recognizer".into())
+            .spawn(move || recognizer(database, recv, states2))?;
+
+        Ok(Self { samples, states })
+    }
+
+    /// Returns the current [`PersistentId`] of the face with the given ephemeral ID.
+    pub fn persistent_id(&self, ephemeral_id: u32) -> PersistentId {
+        let states = self.states.lock().unwrap();
+        states
+            .get(&ephemeral_id)
+            .cloned()
+            .unwrap_or(PersistentId::InProgress)
+    }
+
+    /// Submits the frontal landmark positions of a face for recognition.
+    ///
+    /// Samples of faces that have already been recognized are ignored, as are samples submitted
+    /// while the recognizer thread is busy.
+    pub fn submit(&self, ephemeral_id: u32, points: Vec<[f32; 3]>) {
+        {
+            let mut states = self.states.lock().unwrap();
+            let state = states
+                .entry(ephemeral_id)
+                .or_insert(PersistentId::InProgress);
+            if !matches!(state, PersistentId::InProgress) {
+                return;
+            }
+        }
+        self.samples.try_send((ephemeral_id, points)).ok();
+    }
+
+    /// Forgets the faces with the given ephemeral IDs, once they are no longer tracked.
+    pub fn forget(&self, ephemeral_ids: &[u32]) {
+        let mut states = self.states.lock().unwrap();
+        for id in ephemeral_ids {
+            states.remove(id);
+        }
+    }
+}
+
+fn recognizer(
+    database: Database,
+    recv: Receiver<(u32, Vec<[f32; 3]>)>,
+    states: Arc<Mutex<HashMap<u32, PersistentId>>>,
+) {
+    let mut pending = HashMap::<u32, Vec<Embedding>>::new();
+    for (id, points) in recv {
+        let embeddings = pending.entry(id).or_default();
+        embeddings.push(Embedding::from_points(points));
+        if embeddings.len() < SAMPLES_PER_FACE {
+            continue;
+        }
+
+        let embedding = Embedding::average(embeddings);
+        pending.remove(&id);
+        let state = match embedding.as_ref().and_then(|e| database.identify(e)) {
+            Some(name) => PersistentId::Available(name.to_string()),
+            None => PersistentId::Unknown,
+        };
+        tracing::info!("face {id} identified as {state:?}");
+
+        let mut states = states.lock().unwrap();
+        if let Some(s) = states.get_mut(&id) {
+            *s = state;
+        }
+        // Drop the samples of faces that were forgotten before they could be identified.
+        pending.retain(|id, _| states.contains_key(id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn face(scale: f32) -> Vec<[f32; 3]> {
+        (0..30)
+            .map(|i| {
+                let i = i as f32;
+                [
+                    i.sin() * scale,
+                    i.cos() * scale * 1.5,
+                    (i * 0.3).sin() * scale,
+                ]
+            })
+            .collect()
+    }
+
+    fn other_face() -> Vec<[f32; 3]> {
+        face(1.0)
+            .into_iter()
+            .map(|[x, y, z]| [x * 1.3, y, z])
+            .collect()
+    }
+
+    #[test]
+    fn embedding_normalization() {
+        let a = Embedding::from_points(face(1.0));
+        let b = Embedding::from_points(
+            face(3.0)
+                .into_iter()
+                .map(|[x, y, z]| [x + 10.0, y - 5.0, z + 1.0])
+                .collect(),
+        );
+        assert!(a.distance(&b) < 1e-4, "{}", a.distance(&b));
+        assert!(a.distance(&Embedding::from_points(other_face())) > MATCH_THRESHOLD);
+        assert_eq!(a.distance(&Embedding(Vec::new())), f32::INFINITY);
+
+        let avg = Embedding::average(&[a.clone(), b]).unwrap();
+        assert!(a.distance(&avg) < 1e-4);
+        assert_eq!(Embedding::average(&[]), None);
+    }
+
+    #[test]
+    fn database() {
+        let path = env::temp_dir().join(format!("providence-identities-{}", std::process::id()));
+        let mut db = Database::open(&path).unwrap();
+        assert!(db.identities().is_empty());
+        db.enroll("alice", Embedding::from_points(face(1.0)))
+            .unwrap();
+        db.enroll("bob", Embedding::from_points(other_face()))
+            .unwrap();
+        db.enroll("alice", Embedding::from_points(face(2.0)))
+            .unwrap();
+        assert_eq!(
+            db.enroll("a\nb", Embedding::from_points(face(1.0)))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        db.save().unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        assert_eq!(db.identities().len(), 2);
+        assert_eq!(
+            db.identify(&Embedding::from_points(face(0.5))),
+            Some("alice")
+        );
+        assert_eq!(
+            db.identify(&Embedding::from_points(other_face())),
+            Some("bob")
+        );
+        assert!(db.remove("bob"));
+        assert!(!db.remove("bob"));
+        assert_eq!(db.identify(&Embedding::from_points(other_face())), None);
+
+        fs::write(&path, "carol\t1 2\n").unwrap();
+        let err = Database::open(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Keeps submitting `points` until the face has been recognized.
+    fn recognize(recognizer: &Recognizer, id: u32, points: Vec<[f32; 3]>) -> PersistentId {
+        let start = Instant::now();
+        loop {
+            match recognizer.persistent_id(id) {
+                PersistentId::InProgress => recognizer.submit(id, points.clone()),
+                state => return state,
+            }
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn recognizer() {
+        let mut db = Database::open(env::temp_dir().join("providence-unused")).unwrap();
+        db.enroll("alice", Embedding::from_points(face(1.0)))
+            .unwrap();
+        let recognizer = Recognizer::spawn(db).unwrap();
+
+        assert!(matches!(
+            recognizer.persistent_id(0),
+            PersistentId::InProgress
+        ));
+        assert!(matches!(
+            recognize(&recognizer, 0, face(2.0)),
+            PersistentId::Available(name) if name == "alice"
+        ));
+        assert!(matches!(
+            recognize(&recognizer, 1, other_face()),
+            PersistentId::Unknown
+        ));
+
+        recognizer.forget(&[0]);
+        assert!(matches!(
+            recognizer.persistent_id(0),
+            PersistentId::InProgress
+        ));
+    }
+}
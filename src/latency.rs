@@ -0,0 +1,93 @@
+//! Per-stage latency measurement of the tracking pipeline.
+
+use std::time::{Duration, Instant};
+
+/// The points in time a frame passes on its way through the tracking pipeline.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTimes {
+    /// The webcam frame was captured.
+    pub captured: Instant,
+    /// The face tracker computed landmarks (or a detection) for the frame.
+    pub tracked: Instant,
+    /// The assembler turned the tracker output into a `TrackingMessage`.
+    pub assembled: Instant,
+    /// The message was handed to the `Publisher`.
+    pub published: Instant,
+}
+
+/// Accumulates [`FrameTimes`] and periodically logs per-stage latency statistics.
+pub struct LatencyReport {
+    interval: Duration,
+    last_report: Instant,
+    frames: u32,
+    stages: [Stage; 4],
+}
+
+#[derive(Default)]
+struct Stage {
+    total: Duration,
+    max: Duration,
+}
+
+impl Stage {
+    fn add(&mut self, latency: Duration) {
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+}
+
+impl LatencyReport {
+    const STAGE_NAMES: [&'static str; 4] = [
+        "capture->landmarks",
+        "landmarks->assembly",
+        "assembly->publish",
+        "total",
+    ];
+
+    /// Creates a [`LatencyReport`] that logs its statistics every `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_report: Instant::now(),
+            frames: 0,
+            stages: Default::default(),
+        }
+    }
+
+    /// Records the [`FrameTimes`] of a frame that has made it through the whole pipeline.
+    pub fn record(&mut self, times: &FrameTimes) {
+        let latencies = [
+            times.tracked.saturating_duration_since(times.captured),
+            times.assembled.saturating_duration_since(times.tracked),
+            times.published.saturating_duration_since(times.assembled),
+            times.published.saturating_duration_since(times.captured),
+        ];
+        for (stage, latency) in self.stages.iter_mut().zip(latencies) {
+            stage.add(latency);
+        }
+        self.frames += 1;
+
+        if self.last_report.elapsed() >= self.interval {
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        let stats = Self::STAGE_NAMES
+            .iter()
+            .zip(&self.stages)
+            .map(|(name, stage)| {
+                format!(
+                    "{name} {:.1?} avg / {:.1?} max",
+                    stage.total / self.frames,
+                    stage.max,
+                )
+            })
+            .collect::<Vec<_>>();
+        tracing::info!("latency over {} frames: {}", self.frames, stats.join(", "));
+
+        self.frames = 0;
+        self.stages = Default::default();
+        self.last_report = Instant::now();
+    }
+}
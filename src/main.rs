@@ -1,12 +1,28 @@
+mod config;
+mod enroll;
+mod identity;
+mod latency;
+mod source;
+mod tracks;
 mod triangulate;
 
+use std::cmp;
 use std::collections::VecDeque;
-use std::time::Instant;
-use std::{cmp, io};
-
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context as _;
+use clap::Parser as _;
+use config::{CameraConfig, Cli, Command, Config, FilterConfig, NetworkConfig, Pacing, Preference};
+use identity::{face_sample, Database, Recognizer};
+use latency::{FrameTimes, LatencyReport};
 use pawawwewism::{promise, Promise, PromiseHandle, Worker};
-use providence_io::data::{FaceData, PersistentId, TrackingMessage};
-use providence_io::net::Publisher;
+use providence_io::data::{FaceData, PersistentId, Timing, TrackingMessage};
+use providence_io::net::{CameraInfo, Publisher};
+use providence_io::recording::{Header, RecordingWriter, TrackerInfo};
+use source::{Frame, FrameSource, ImageDir, VideoFile, WebcamSource};
+use tracks::{Association, Region, Tracks, MAX_FACES};
 use triangulate::{Side, Triangulator};
 use zaru::detection::{Detection, Detector};
 use zaru::face::detection::ShortRangeNetwork;
@@ -15,152 +31,436 @@ use zaru::filter::one_euro::OneEuroFilter;
 use zaru::filter::{TimeBasedFilter, TimedFilterAdapter};
 use zaru::image::histogram::Histogram;
 use zaru::image::lut::Lut;
-use zaru::image::{rect::RotatedRect, Image};
+use zaru::image::{rect::RotatedRect, Image, Resolution};
 use zaru::landmark::{Estimator, LandmarkFilter, LandmarkTracker};
 use zaru::linalg::{vec3, Quat};
 use zaru::num::TotalF32;
 use zaru::procrustes::ProcrustesAnalyzer;
 use zaru::profile;
-use zaru::video::webcam::{ParamPreference, Webcam, WebcamOptions};
+use zaru::video::webcam::{ParamPreference, WebcamOptions};
 
 const TIMESTAMP_OFFSET: u32 = u32::MAX - 10_000_000; // 10 seconds before overflow
 
-const ENABLE_POSTPROC: bool = false;
-
-fn webcam_opts() -> WebcamOptions {
-    WebcamOptions::default()
-        .fps(30)
-        .prefer(ParamPreference::Resolution)
+/// Maximum number of frames being processed at once.
+///
+/// If the pipeline falls this far behind the input (which only happens when reading files as fast
+/// as possible), we wait for it to catch up before reading more frames.
+const MAX_FRAMES_IN_FLIGHT: usize = 4;
+
+/// Number of frames after which face detection is rerun to look for faces entering the view.
+const DETECTION_INTERVAL: u32 = 15;
+
+fn webcam_opts(config: &CameraConfig) -> WebcamOptions {
+    let mut opts = WebcamOptions::default()
+        .fps(config.fps)
+        .prefer(match config.prefer {
+            Preference::Resolution => ParamPreference::Resolution,
+            Preference::Framerate => ParamPreference::Framerate,
+        });
+    if let Some(device) = &config.device {
+        opts = opts.name(device);
+    }
+    if let Some(res) = config.resolution {
+        opts = opts.resolution(Resolution::new(res.width, res.height));
+    }
+    opts
 }
 
 #[zaru::main]
 fn main() -> anyhow::Result<()> {
-    let mut face_tracker = face_track_worker()?;
-    let mut assembler = assembler()?;
+    let cli = Cli::parse();
+    let config = Config::load(&cli)?;
+
+    match cli.command {
+        None => {
+            let recognizer = if config.recognition.enabled {
+                let database = open_database(&config)?;
+                if database.identities().is_empty() {
+                    tracing::warn!(
+                        "no identities are enrolled in {}, all faces will be unknown",
+                        database.path().display(),
+                    );
+                }
+                Some(Recognizer::spawn(database)?)
+            } else {
+                None
+            };
+            match &cli.output {
+                Some(path) => batch(&config, recognizer, path),
+                None => run(&config, recognizer),
+            }
+        }
+        Some(Command::Enroll { list: true, .. }) => {
+            let database = open_database(&config)?;
+            for identity in database.identities() {
+                println!("{}", identity.name);
+            }
+            Ok(())
+        }
+        Some(Command::Enroll {
+            remove: Some(name), ..
+        }) => {
+            let mut database = open_database(&config)?;
+            if !database.remove(&name) {
+                anyhow::bail!("no identity named `{name}` is enrolled");
+            }
+            database.save()?;
+            Ok(())
+        }
+        Some(Command::Enroll {
+            name: Some(name), ..
+        }) => enroll::enroll(&mut open_database(&config)?, &name, &config.camera),
+        Some(Command::Enroll { .. }) => unreachable!("rejected by argument parser"),
+    }
+}
 
-    let mut webcam = Webcam::open(webcam_opts())?;
-    webcam.read()?;
+fn open_database(config: &Config) -> anyhow::Result<Database> {
+    let path = config
+        .recognition
+        .database
+        .clone()
+        .or_else(Database::default_path)
+        .context("could not determine the location of the identity database")?;
+    Ok(Database::open(path)?)
+}
 
-    let reference_time = Instant::now();
-    let mut publisher = Publisher::spawn()?;
-    let mut message_queue = VecDeque::new();
+/// Runs the tracker, publishing the results on the network.
+fn run(config: &Config, recognizer: Option<Recognizer>) -> anyhow::Result<()> {
+    let mut pipeline = Pipeline::new(config, recognizer)?;
+    let mut source = open_source(config, config.input.pacing)?;
+    let mut publisher = spawn_publisher(&config.network, source.camera())?;
     loop {
         // To avoid wasting CPU, we only perform processing when there is a client connected.
         // Ideally we'd also clear the face tracking state, but that's kinda difficult to do.
         if !publisher.has_connection() {
             // Make sure to drop old messages so that we don't sent anything outdated to new clients.
-            message_queue.clear();
+            pipeline.clear();
             publisher.clear();
 
-            // Close the webcam device. We reopen it when a client connects. This allows the webcam
-            // to be idle or even replugged while the tracker is idle, and allows the tracker to
-            // survive system suspend.
-            drop(webcam);
+            // Close the webcam device. We reopen it when a client connects.
+            source.close();
             publisher.block_until_connected();
-
-            webcam = Webcam::open(webcam_opts())?;
         }
 
+        let Some(frame) = source.next_frame()? else {
+            tracing::info!("end of input reached");
+            break;
+        };
+        pipeline.process(frame, |message| {
+            publisher.publish(message);
+            Ok(())
+        })?;
+    }
+
+    pipeline.finish(|message| {
+        publisher.publish(message);
+        Ok(())
+    })
+}
+
+/// Runs the tracker without any network connectivity, writing every result to a recording at
+/// `path`.
+///
+/// The input is read as fast as possible, regardless of the configured pacing.
+fn batch(config: &Config, recognizer: Option<Recognizer>, path: &Path) -> anyhow::Result<()> {
+    let mut pipeline = Pipeline::new(config, recognizer)?;
+    let mut source = open_source(config, Pacing::Fast)?;
+    let tracker = TrackerInfo {
+        name: env!("CARGO_PKG_NAME").into(),
+        version: env!("CARGO_PKG_VERSION").into(),
+    };
+    let mut recording = RecordingWriter::create(path, &Header::new(tracker))
+        .with_context(|| format!("failed to create {}", path.display()))?;
+
+    // Messages are placed in the recording according to their capture time rather than the time
+    // they were produced at, so that the message times don't depend on how fast the input was
+    // processed, and are the same for every recording of the same input.
+    let mut first_timestamp = None;
+    let mut write = |message: TrackingMessage| -> anyhow::Result<()> {
+        let timestamp = message.timing.map_or(0, |timing| timing.timestamp);
+        let first = *first_timestamp.get_or_insert(timestamp);
+        let time = Duration::from_micros(timestamp.saturating_sub(first));
+        recording.write(time, &message)?;
+        // Flush every message, so that the recording stays usable if the tracker is killed (which
+        // is the only way to stop it when reading from a webcam).
+        recording.flush()?;
+        Ok(())
+    };
+
+    let start = Instant::now();
+    while let Some(frame) = source.next_frame()? {
+        pipeline.process(frame, &mut write)?;
+    }
+    pipeline.finish(&mut write)?;
+
+    let messages = recording.len();
+    recording.finish()?;
+    tracing::info!(
+        "wrote {messages} messages to {} in {:.1?}",
+        path.display(),
+        start.elapsed(),
+    );
+    Ok(())
+}
+
+fn spawn_publisher(config: &NetworkConfig, camera: Option<CameraInfo>) -> io::Result<Publisher> {
+    let mut builder = Publisher::builder()
+        .with_port(config.port)
+        .with_interfaces(&config.interfaces)
+        .without_interfaces(&config.exclude_interfaces)
+        .with_eye_textures(config.eye_textures)
+        .with_mdns(config.mdns);
+    if let Some(ip) = config.bind {
+        builder = builder.with_bind_address(ip);
+    }
+    if let Some(name) = &config.name {
+        builder = builder.with_name(name);
+    }
+    if let Some(name) = &config.friendly_name {
+        builder = builder.with_friendly_name(name);
+    }
+    if let Some(camera) = camera {
+        builder = builder.with_camera(camera);
+    }
+    builder.spawn()
+}
+
+fn open_source(config: &Config, pacing: Pacing) -> anyhow::Result<Box<dyn FrameSource>> {
+    let input = &config.input;
+    Ok(match &input.path {
+        None => Box::new(WebcamSource::open(&config.camera)?),
+        Some(path) if path.is_dir() => Box::new(ImageDir::open(path, input.image_fps, pacing)?),
+        Some(path) => Box::new(VideoFile::open(path, pacing)?),
+    })
+}
+
+/// The face tracking pipeline, turning [`Frame`]s into [`TrackingMessage`]s.
+///
+/// Frames are processed by a chain of worker threads, so several frames can be in flight at once.
+struct Pipeline {
+    face_tracker: Worker<FaceTrackParams>,
+    assembler: Worker<AssemblerParams>,
+    queue: VecDeque<QueuedFrame>,
+    latency: Option<LatencyReport>,
+    epoch_offset: i64,
+}
+
+impl Pipeline {
+    fn new(config: &Config, recognizer: Option<Recognizer>) -> anyhow::Result<Self> {
+        Ok(Self {
+            face_tracker: face_track_worker(config.filter, recognizer.clone())?,
+            assembler: assembler(config.postprocess, recognizer)?,
+            queue: VecDeque::new(),
+            latency: config
+                .logging
+                .latency_report_interval()
+                .map(LatencyReport::new),
+            // Determined once, so that adjustments to the system clock don't disturb the timeline.
+            epoch_offset: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_micros() as i64)
+                - i64::from(TIMESTAMP_OFFSET),
+        })
+    }
+
+    /// Feeds a frame into the pipeline, and passes the messages of all frames that have finished
+    /// processing to `output`, in order.
+    fn process(
+        &mut self,
+        frame: Frame,
+        mut output: impl FnMut(TrackingMessage) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
         // NB: the non-flipped webcam image is "the wrong way around" - we flip the data/sprites in
         // the assembler.
-        let image = webcam.read()?;
-        let timestamp = Instant::now().duration_since(reference_time).as_micros()
-            + u128::from(TIMESTAMP_OFFSET);
-
-        let (output, landmarks_handle) = promise();
+        let Frame { image, timestamp } = frame;
+        let captured = Instant::now();
+        let timing = Timing {
+            timestamp: timestamp.as_micros() as u64 + u64::from(TIMESTAMP_OFFSET),
+            epoch_offset: self.epoch_offset,
+        };
+
+        let (output_promise, landmarks_handle) = promise();
         let (message, message_handle) = promise();
-        face_tracker.send(FaceTrackParams { image, output });
-        assembler.send(AssemblerParams {
+        self.face_tracker.send(FaceTrackParams {
+            image,
+            output: output_promise,
+        });
+        self.assembler.send(AssemblerParams {
             landmarks: landmarks_handle,
             message,
         });
-        message_queue.push_back(message_handle);
-
-        if let Some(handle) = message_queue.front() {
-            if !handle.will_block() {
-                let mut message = match message_queue.pop_front().unwrap().block() {
-                    Ok(msg) => msg,
-                    Err(_) => {
-                        // If this promise was dropped, no face was detected.
-                        TrackingMessage {
-                            timestamp: 0,
-                            faces: Vec::new(),
-                        }
-                    }
-                };
+        self.queue.push_back(QueuedFrame {
+            captured,
+            timing,
+            message: message_handle,
+        });
+
+        while let Some(frame) = self.queue.front() {
+            if frame.message.will_block() && self.queue.len() < MAX_FRAMES_IN_FLIGHT {
+                break;
+            }
+            let frame = self.queue.pop_front().unwrap();
+            self.output(frame, &mut output)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for all frames in the pipeline to finish, and passes their messages to `output`.
+    fn finish(
+        &mut self,
+        mut output: impl FnMut(TrackingMessage) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        while let Some(frame) = self.queue.pop_front() {
+            self.output(frame, &mut output)?;
+        }
+        Ok(())
+    }
+
+    /// Discards the frames that are still being processed.
+    fn clear(&mut self) {
+        self.queue.clear();
+    }
 
-                message.timestamp = timestamp as u32;
-                publisher.publish(message);
+    fn output(
+        &mut self,
+        frame: QueuedFrame,
+        output: &mut impl FnMut(TrackingMessage) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let (mut message, stages) = match frame.message.block() {
+            Ok(Assembled {
+                message,
+                tracked,
+                assembled,
+            }) => (message, Some((tracked, assembled))),
+            Err(_) => {
+                // If this promise was dropped, no face was detected.
+                let message = TrackingMessage {
+                    timestamp: 0,
+                    timing: None,
+                    faces: Vec::new(),
+                };
+                (message, None)
             }
+        };
+
+        // The frame may have spent several iterations in the queue, so use the time it was
+        // captured at, not the current one.
+        message.set_timing(frame.timing);
+        output(message)?;
+
+        if let (Some(latency), Some((tracked, assembled))) = (&mut self.latency, stages) {
+            latency.record(&FrameTimes {
+                captured: frame.captured,
+                tracked,
+                assembled,
+                published: Instant::now(),
+            });
         }
+        Ok(())
     }
 }
 
+/// A frame that is making its way through the pipeline.
+struct QueuedFrame {
+    /// When the frame was read from the [`FrameSource`].
+    captured: Instant,
+    /// Protocol timestamp of the frame.
+    timing: Timing,
+    message: PromiseHandle<Assembled>,
+}
+
+/// The assembler's output for a frame.
+struct Assembled {
+    message: TrackingMessage,
+    /// When the face tracker was done with the frame.
+    tracked: Instant,
+    /// When `message` was finished.
+    assembled: Instant,
+}
+
 struct AssemblerParams {
-    landmarks: PromiseHandle<(TrackerOutput, Image)>,
-    message: Promise<TrackingMessage>,
+    landmarks: PromiseHandle<(Vec<(u32, TrackerOutput)>, Image, Instant)>,
+    message: Promise<Assembled>,
 }
 
-fn assembler() -> Result<Worker<AssemblerParams>, io::Error> {
+fn assembler(
+    postprocess: bool,
+    recognizer: Option<Recognizer>,
+) -> Result<Worker<AssemblerParams>, io::Error> {
     let mut procrustes_analyzer = ProcrustesAnalyzer::new(mediapipe::reference_positions());
     let mut tri = Triangulator::new();
 
     Worker::builder()
         .name("assembler")
         .spawn(move |AssemblerParams { landmarks, message }| {
-            let Ok((output, image)) = landmarks.block() else {
+            let Ok((outputs, image, tracked)) = landmarks.block() else {
                 return;
             };
 
-            match output {
-                TrackerOutput::Landmarks(mut face_landmark) => {
-                    let procrustes_result = profile::scope("procrustes", || {
-                        procrustes_analyzer.analyze(face_landmark.mesh_landmarks().map(|lm| {
-                            // Flip Y to bring us to canonical 3D coordinates (where Y points up).
-                            // Only rotation matters, so we don't have to correct for the added
-                            // translation.
-                            vec3(lm.x, -lm.y, lm.z)
-                        }))
-                    });
-
-                    let [x, y, z] = procrustes_result.rotation().to_rotation_xyz();
-                    // Invert the angles so that the reported head rotation matches what looking in a mirror
-                    // is like.
-                    let head_rotation = Quat::from_rotation_xyz(-x, y, -z);
-                    let head_rotation_inv = head_rotation.conjugate();
-
-                    let (left_eye, right_eye) = profile::scope("triangulate", || {
-                        (
-                            tri.triangulate_eye(
-                                &face_landmark,
-                                &image,
-                                Side::Left,
-                                head_rotation_inv,
-                            ),
-                            tri.triangulate_eye(
-                                &face_landmark,
-                                &image,
-                                Side::Right,
-                                head_rotation_inv,
-                            ),
-                        )
-                    });
-
-                    // Mirror the whole image, so that the eyes match what the user does.
-                    let (mut right_eye, mut left_eye) =
-                        (left_eye.flip_horizontal(), right_eye.flip_horizontal());
-                    postprocess_eye_sprites(&mut left_eye.texture, &mut right_eye.texture);
-
-                    // Map all landmarks into range 0..=1 for computing the head position
-                    let max = cmp::max(image.width(), image.height()) as f32;
-                    face_landmark.landmarks_mut().map_positions(|p| p / max);
-                    let avg = face_landmark.landmarks().average_position();
-
-                    message.fulfill(TrackingMessage {
-                        timestamp: 0, // filled in later
-                        faces: vec![FaceData {
-                            ephemeral_id: 0,
-                            persistent_id: PersistentId::Unavailable,
+            // Map all landmarks into range 0..=1 for computing the head position
+            let max = cmp::max(image.width(), image.height()) as f32;
+
+            let persistent_id = |ephemeral_id| match &recognizer {
+                Some(recognizer) => recognizer.persistent_id(ephemeral_id),
+                None => PersistentId::Unavailable,
+            };
+
+            let faces = outputs
+                .into_iter()
+                .map(|(ephemeral_id, output)| match output {
+                    TrackerOutput::Landmarks(mut face_landmark) => {
+                        let procrustes_result = profile::scope("procrustes", || {
+                            procrustes_analyzer.analyze(face_landmark.mesh_landmarks().map(|lm| {
+                                // Flip Y to bring us to canonical 3D coordinates (where Y points
+                                // up). Only rotation matters, so we don't have to correct for the
+                                // added translation.
+                                vec3(lm.x, -lm.y, lm.z)
+                            }))
+                        });
+
+                        if let Some(recognizer) = &recognizer {
+                            let rotation = procrustes_result.rotation();
+                            if let Some(points) = face_sample(&face_landmark, rotation) {
+                                recognizer.submit(ephemeral_id, points);
+                            }
+                        }
+
+                        let [x, y, z] = procrustes_result.rotation().to_rotation_xyz();
+                        // Invert the angles so that the reported head rotation matches what looking
+                        // in a mirror is like.
+                        let head_rotation = Quat::from_rotation_xyz(-x, y, -z);
+                        let head_rotation_inv = head_rotation.conjugate();
+
+                        let (left_eye, right_eye) = profile::scope("triangulate", || {
+                            (
+                                tri.triangulate_eye(
+                                    &face_landmark,
+                                    &image,
+                                    Side::Left,
+                                    head_rotation_inv,
+                                ),
+                                tri.triangulate_eye(
+                                    &face_landmark,
+                                    &image,
+                                    Side::Right,
+                                    head_rotation_inv,
+                                ),
+                            )
+                        });
+
+                        // Mirror the whole image, so that the eyes match what the user does.
+                        let (mut right_eye, mut left_eye) =
+                            (left_eye.flip_horizontal(), right_eye.flip_horizontal());
+                        if postprocess {
+                            postprocess_eye_sprites(&mut left_eye.texture, &mut right_eye.texture);
+                        }
+
+                        face_landmark.landmarks_mut().map_positions(|p| p / max);
+                        let avg = face_landmark.landmarks().average_position();
+
+                        FaceData {
+                            ephemeral_id,
+                            persistent_id: persistent_id(ephemeral_id),
                             head_position: [1.0 - avg.x, avg.y],
                             head_rotation: [
                                 head_rotation.i,
@@ -170,20 +470,15 @@ fn assembler() -> Result<Worker<AssemblerParams>, io::Error> {
                             ],
                             left_eye: Some(left_eye.into_message()),
                             right_eye: Some(right_eye.into_message()),
-                        }],
-                    });
-                }
-                TrackerOutput::Detection(det) => {
-                    // Map all landmarks into range 0..=1 for computing the head position
-                    let max = cmp::max(image.width(), image.height()) as f32;
-                    let pos = det.bounding_rect().center() / max;
-
-                    let head_rotation = Quat::from_rotation_z(det.angle());
-                    message.fulfill(TrackingMessage {
-                        timestamp: 0, // filled in later
-                        faces: vec![FaceData {
-                            ephemeral_id: 0,
-                            persistent_id: PersistentId::Unavailable,
+                        }
+                    }
+                    TrackerOutput::Detection(det) => {
+                        let pos = det.bounding_rect().center() / max;
+
+                        let head_rotation = Quat::from_rotation_z(det.angle());
+                        FaceData {
+                            ephemeral_id,
+                            persistent_id: persistent_id(ephemeral_id),
                             head_position: [1.0 - pos.x, pos.y],
                             head_rotation: [
                                 head_rotation.i,
@@ -193,17 +488,24 @@ fn assembler() -> Result<Worker<AssemblerParams>, io::Error> {
                             ],
                             left_eye: None,
                             right_eye: None,
-                        }],
-                    })
-                }
-            }
+                        }
+                    }
+                })
+                .collect();
+
+            message.fulfill(Assembled {
+                message: TrackingMessage {
+                    timestamp: 0, // filled in later
+                    timing: None,
+                    faces,
+                },
+                tracked,
+                assembled: Instant::now(),
+            });
         })
 }
 
 fn postprocess_eye_sprites(left: &mut Image, right: &mut Image) {
-    if !ENABLE_POSTPROC {
-        return;
-    }
     profile::scope("postprocess", || {
         postprocess_eye_sprite(left);
         postprocess_eye_sprite(right);
@@ -224,17 +526,19 @@ fn postprocess_eye_sprite(image: &mut Image) {
 
 struct FaceTrackParams {
     image: Image,
-    output: Promise<(TrackerOutput, Image)>,
+    /// Receives the output for every face in view (along with its ephemeral ID), the image, and
+    /// the time the tracker was done with it.
+    output: Promise<(Vec<(u32, TrackerOutput)>, Image, Instant)>,
 }
 
 /// Per-face face tracker output.
 ///
-/// The face tracker can be in 3 different "modes":
+/// The tracker of each face can be in 3 different "modes":
 /// - normal mode: the face is fully visible and landmarks are available.
 /// - degraded mode: the face is too obscured to compute landmarks on, but is still detected in the
 ///   image.
-/// - "none" mode: no face is in view at all; if the tracker is in this mode the `Promise` will
-///   simply be dropped.
+/// - "none" mode: the face isn't in view; it is left out of the output. If no face is in view at
+///   all, the `Promise` will simply be dropped.
 enum TrackerOutput {
     /// Landmarks are available.
     Landmarks(LandmarkResultV2),
@@ -245,55 +549,114 @@ enum TrackerOutput {
 
 /// The face track worker is sent the decoded webcam image and does the following:
 ///
-/// - Detect faces (if none are currently tracked)
-/// - Compute facial landmarks, track their positions across frames, and send them to the recipient
-fn face_track_worker() -> Result<Worker<FaceTrackParams>, io::Error> {
+/// - Compute facial landmarks of every tracked face, track their positions across frames, and send
+///   them to the recipient
+/// - Detect faces (if tracking of a face was lost, or periodically to find new faces), and start
+///   tracking any faces that aren't tracked yet
+fn face_track_worker(
+    filter_config: FilterConfig,
+    recognizer: Option<Recognizer>,
+) -> Result<Worker<FaceTrackParams>, io::Error> {
     let mut detector = Detector::new(ShortRangeNetwork);
-    let mut estimator = Estimator::new(FaceMeshV2);
-    estimator.set_filter(LandmarkFilter::new(
-        filter(),
-        LandmarkResultV2::NUM_LANDMARKS,
-    ));
-    let mut tracker = LandmarkTracker::new(estimator);
+    // Every face gets its own tracker, since the landmark filter has per-face state.
+    let mut tracks = Tracks::new(move || {
+        let mut estimator = Estimator::new(FaceMeshV2);
+        estimator.set_filter(LandmarkFilter::new(
+            filter(filter_config),
+            LandmarkResultV2::NUM_LANDMARKS,
+        ));
+        LandmarkTracker::new(estimator)
+    });
+    let mut frames_since_detection = 0;
     let input_ratio = detector.input_resolution().aspect_ratio().unwrap();
 
     Worker::builder()
         .name("face tracker")
         .spawn(move |FaceTrackParams { image, output }| {
-            if let Some(res) = tracker.track(&image) {
-                output.fulfill((TrackerOutput::Landmarks(res.estimate().clone()), image));
-            } else {
-                // No ROI set, or tracking was lost. Run detection.
+            let mut faces = Vec::new();
+
+            tracks.begin_frame();
+            for track in tracks.iter_mut() {
+                if let Some(res) = track.state.track(&image) {
+                    let landmarks = res.estimate().clone();
+                    track.found(landmark_region(&landmarks));
+                    faces.push((track.id(), TrackerOutput::Landmarks(landmarks)));
+                }
+            }
+
+            frames_since_detection += 1;
+            let look_for_new_faces =
+                tracks.len() < MAX_FACES && frames_since_detection >= DETECTION_INTERVAL;
+            if tracks.is_empty() || tracks.any_missing() || look_for_new_faces {
+                // Tracking was lost, or there might be new faces in view. Run detection.
+                frames_since_detection = 0;
 
                 // Zoom into the camera image and perform detection there. This makes outer
                 // edges of the camera view unusable, but significantly improves the tracking
                 // distance.
                 let view_rect = image.resolution().fit_aspect_ratio(input_ratio);
                 let view = image.view(view_rect);
-                let detections = detector.detect(&view);
+                let mut detections = detector.detect(&view).iter().collect::<Vec<_>>();
+                detections.sort_by_key(|det| cmp::Reverse(TotalF32(det.confidence())));
 
-                if let Some(detection) = detections
-                    .iter()
-                    .max_by_key(|det| TotalF32(det.confidence()))
-                {
+                for detection in detections {
                     // Adjust detection to be in the full image's coordinate space.
                     let mut detection = detection.clone();
                     detection
                         .set_bounding_rect(detection.bounding_rect().move_by(view_rect.top_left()));
 
+                    let rect = detection.bounding_rect();
+                    let region = Region {
+                        center: [rect.center().x, rect.center().y],
+                        size: rect.width().max(rect.height()),
+                    };
+                    let track = match tracks.associate(region) {
+                        Association::Reacquired(track) => track,
+                        Association::New(track) => {
+                            tracing::debug!("new face {}", track.id());
+                            track
+                        }
+                        // Already tracked, or no room for more faces.
+                        Association::Duplicate | Association::Full => continue,
+                    };
+
                     // Tell tracker where to look.
                     let rect = RotatedRect::new(detection.bounding_rect(), detection.angle());
-                    tracing::trace!("start tracking face at {:?}", rect);
-                    tracker.set_roi(rect);
+                    tracing::trace!("start tracking face {} at {:?}", track.id(), rect);
+                    track.state.set_roi(rect);
 
                     // Provide "degraded" tracking output to next stage.
-                    output.fulfill((TrackerOutput::Detection(detection), image));
+                    faces.push((track.id(), TrackerOutput::Detection(detection)));
+                }
+            }
+
+            let retired = tracks.end_frame();
+            if !retired.is_empty() {
+                tracing::debug!("lost faces {:?}", retired);
+                faces.retain(|(id, _)| !retired.contains(id));
+                if let Some(recognizer) = &recognizer {
+                    recognizer.forget(&retired);
                 }
             }
+
+            if !faces.is_empty() {
+                output.fulfill((faces, image, Instant::now()));
+            }
         })
 }
 
+/// Computes the image region covered by a face's landmarks.
+fn landmark_region(landmarks: &LandmarkResultV2) -> Region {
+    let mut min = [f32::MAX; 2];
+    let mut max = [f32::MIN; 2];
+    for lm in landmarks.mesh_landmarks() {
+        min = [min[0].min(lm.x), min[1].min(lm.y)];
+        max = [max[0].max(lm.x), max[1].max(lm.y)];
+    }
+    Region::bounding(min, max)
+}
+
 type Filt = OneEuroFilter;
-fn filter() -> TimedFilterAdapter<Filt> {
-    Filt::new(0.0001, 0.3).real_time()
+fn filter(config: FilterConfig) -> TimedFilterAdapter<Filt> {
+    Filt::new(config.min_cutoff, config.beta).real_time()
 }
@@ -0,0 +1,388 @@
+//! Sources of the camera frames fed into the tracking pipeline.
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdout, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context as _};
+use providence_io::net::CameraInfo;
+use zaru::{
+    image::{Image, Resolution},
+    video::webcam::Webcam,
+};
+
+use crate::config::{CameraConfig, Pacing};
+
+/// File extensions of the images an [`ImageDir`] picks up.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg"];
+
+/// If a [`Pacing::RealTime`] source falls behind its schedule by more than this, it stops trying
+/// to catch up and restarts the schedule from the current frame instead.
+const MAX_PACING_LAG: Duration = Duration::from_secs(1);
+
+/// Number of lines of `ffmpeg` output included in the error if decoding fails.
+const FFMPEG_LOG_LINES: usize = 10;
+
+/// A frame read from a [`FrameSource`].
+pub struct Frame {
+    pub image: Image,
+    /// Capture time of the frame, relative to the start of the source.
+    pub timestamp: Duration,
+}
+
+/// A source of camera frames.
+pub trait FrameSource {
+    /// Reads the next frame.
+    ///
+    /// Returns `None` once the source has run out of frames.
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>>;
+
+    /// Releases the underlying device while the tracker is idle.
+    ///
+    /// The next call to [`FrameSource::next_frame`] reopens it. Timestamps continue where they left
+    /// off.
+    fn close(&mut self) {}
+
+    /// Returns the camera the frames are captured from, if they come from one.
+    fn camera(&self) -> Option<CameraInfo> {
+        None
+    }
+}
+
+/// Reads frames from a webcam.
+pub struct WebcamSource {
+    config: CameraConfig,
+    webcam: Option<Webcam>,
+    /// Resolution of the first frame the webcam delivered, and the requested frame rate (the
+    /// webcam doesn't report the one it actually delivers).
+    camera: CameraInfo,
+    start: Instant,
+}
+
+impl WebcamSource {
+    pub fn open(config: &CameraConfig) -> anyhow::Result<Self> {
+        let mut webcam = Webcam::open(crate::webcam_opts(config))?;
+        let image = webcam.read()?;
+        let camera = CameraInfo {
+            width: image.width(),
+            height: image.height(),
+            fps: config.fps as f32,
+        };
+        Ok(Self {
+            config: config.clone(),
+            webcam: Some(webcam),
+            camera,
+            start: Instant::now(),
+        })
+    }
+}
+
+impl FrameSource for WebcamSource {
+    /// Reads the next webcam frame.
+    ///
+    /// Ideally the timestamp would be the one of the video buffer, but `zaru`'s `Webcam` doesn't
+    /// expose it. The time `read` returns at is the earliest point available to us, before any
+    /// tracking work happens (though after the frame has been decoded).
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        let webcam = match &mut self.webcam {
+            Some(webcam) => webcam,
+            None => self
+                .webcam
+                .insert(Webcam::open(crate::webcam_opts(&self.config))?),
+        };
+        let image = webcam.read()?;
+        Ok(Some(Frame {
+            image,
+            timestamp: self.start.elapsed(),
+        }))
+    }
+
+    /// Closes the webcam device.
+    ///
+    /// This allows the webcam to be idle or even replugged while the tracker is idle, and allows
+    /// the tracker to survive system suspend.
+    fn close(&mut self) {
+        self.webcam = None;
+    }
+
+    fn camera(&self) -> Option<CameraInfo> {
+        Some(self.camera)
+    }
+}
+
+/// Reads frames from a directory of still images, in file name order.
+///
+/// Since images carry no capture time, frames are assumed to be evenly spaced at a fixed frame
+/// rate.
+pub struct ImageDir {
+    paths: Vec<PathBuf>,
+    index: usize,
+    frame_interval: Duration,
+    pacer: Pacer,
+}
+
+impl ImageDir {
+    pub fn open(dir: &Path, fps: f32, pacing: Pacing) -> anyhow::Result<Self> {
+        let mut paths = Vec::new();
+        let entries =
+            fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if has_image_extension(&path) {
+                paths.push(path);
+            }
+        }
+        if paths.is_empty() {
+            bail!("no images found in {}", dir.display());
+        }
+        paths.sort();
+
+        Ok(Self {
+            paths,
+            index: 0,
+            frame_interval: Duration::from_secs_f32(1.0 / fps),
+            pacer: Pacer::new(pacing),
+        })
+    }
+}
+
+impl FrameSource for ImageDir {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        let Some(path) = self.paths.get(self.index) else {
+            return Ok(None);
+        };
+        let image =
+            Image::load(path).with_context(|| format!("failed to load {}", path.display()))?;
+        let timestamp = self.frame_interval * self.index as u32;
+        self.index += 1;
+
+        self.pacer.wait(timestamp);
+        Ok(Some(Frame { image, timestamp }))
+    }
+
+    fn close(&mut self) {
+        self.pacer.reset();
+    }
+}
+
+fn has_image_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+}
+
+/// Reads frames from a video file, decoded by an `ffmpeg` subprocess.
+///
+/// Frame timestamps are the presentation timestamps stored in the file.
+pub struct VideoFile {
+    ffmpeg: Child,
+    frames: ChildStdout,
+    timestamps: Receiver<Duration>,
+    stderr: Option<thread::JoinHandle<Vec<String>>>,
+    resolution: Resolution,
+    buf: Vec<u8>,
+    pacer: Pacer,
+}
+
+impl VideoFile {
+    pub fn open(path: &Path, pacing: Pacing) -> anyhow::Result<Self> {
+        let (width, height) = probe_size(path)?;
+
+        let mut ffmpeg = Command::new("ffmpeg")
+            .args(["-hide_banner", "-nostats", "-nostdin", "-i"])
+            .arg(path)
+            // `showinfo` logs the timestamp of every frame, `passthrough` makes sure that frames
+            // are neither dropped nor duplicated, so that the timestamps line up with the frames.
+            .args(["-map", "0:v:0", "-vsync", "passthrough", "-vf", "showinfo"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("failed to run `ffmpeg` (is it installed?)")?;
+        let frames = ffmpeg.stdout.take().unwrap();
+        let stderr = ffmpeg.stderr.take().unwrap();
+
+        let (sender, timestamps) = mpsc::channel();
+        let stderr = thread::Builder::new()
+            .name("ffmpeg stderr".into())
+            .spawn(move || {
+                // Forward the frame timestamps, and keep the other messages in case `ffmpeg` fails.
+                let mut log = Vec::new();
+                for line in BufReader::new(stderr).lines() {
+                    let Ok(line) = line else { break };
+                    if let Some(pts) = parse_pts_time(&line) {
+                        sender.send(pts).ok();
+                    } else {
+                        if log.len() == FFMPEG_LOG_LINES {
+                            log.remove(0);
+                        }
+                        log.push(line);
+                    }
+                }
+                log
+            })?;
+
+        Ok(Self {
+            ffmpeg,
+            frames,
+            timestamps,
+            stderr: Some(stderr),
+            resolution: Resolution::new(width, height),
+            buf: vec![0; width as usize * height as usize * 4],
+            pacer: Pacer::new(pacing),
+        })
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let status = self.ffmpeg.wait()?;
+        if !status.success() {
+            let log = match self.stderr.take() {
+                Some(stderr) => stderr.join().unwrap_or_default(),
+                None => Vec::new(),
+            };
+            bail!("ffmpeg failed ({status}):\n{}", log.join("\n"));
+        }
+        Ok(())
+    }
+}
+
+impl FrameSource for VideoFile {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        match self.frames.read_exact(&mut self.buf) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.finish()?;
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let timestamp = self
+            .timestamps
+            .recv()
+            .context("ffmpeg did not report the timestamp of a frame")?;
+
+        let image = Image::from_rgba8(self.resolution, &self.buf);
+        self.pacer.wait(timestamp);
+        Ok(Some(Frame { image, timestamp }))
+    }
+
+    fn close(&mut self) {
+        self.pacer.reset();
+    }
+}
+
+impl Drop for VideoFile {
+    fn drop(&mut self) {
+        self.ffmpeg.kill().ok();
+        self.ffmpeg.wait().ok();
+    }
+}
+
+/// Determines the frame size of the first video stream in a file with `ffprobe`.
+fn probe_size(path: &Path) -> anyhow::Result<(u32, u32)> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=width,height", "-of", "csv=s=x:p=0"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .context("failed to run `ffprobe` (is it installed?)")?;
+    if !output.status.success() {
+        bail!(
+            "could not read video file {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim(),
+        );
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let size = stdout.lines().next().unwrap_or_default().trim();
+    match size.split_once('x').map(|(w, h)| (w.parse(), h.parse())) {
+        Some((Ok(width), Ok(height))) if width > 0 && height > 0 => Ok((width, height)),
+        _ => bail!("{} does not contain a video stream", path.display()),
+    }
+}
+
+/// Extracts the frame timestamp from a line logged by `ffmpeg`'s `showinfo` filter.
+fn parse_pts_time(line: &str) -> Option<Duration> {
+    if !line.contains("showinfo") {
+        return None;
+    }
+    let (_, rest) = line.split_once("pts_time:")?;
+    let secs = rest.split_whitespace().next()?.parse::<f64>().ok()?;
+    // Streams may start at a slightly negative timestamp.
+    Some(Duration::try_from_secs_f64(secs.max(0.0)).unwrap_or_default())
+}
+
+/// Delays frames of file-based sources according to the [`Pacing`].
+struct Pacer {
+    pacing: Pacing,
+    /// The time at which the frame with the given timestamp was due.
+    anchor: Option<(Instant, Duration)>,
+}
+
+impl Pacer {
+    fn new(pacing: Pacing) -> Self {
+        Self {
+            pacing,
+            anchor: None,
+        }
+    }
+
+    /// Blocks until the frame with the given `timestamp` is due.
+    fn wait(&mut self, timestamp: Duration) {
+        if self.pacing == Pacing::Fast {
+            return;
+        }
+
+        let now = Instant::now();
+        let &mut (start, start_timestamp) = self.anchor.get_or_insert((now, timestamp));
+        // Deadlines are computed from the anchor rather than from the previous frame, so that
+        // errors don't accumulate.
+        let deadline = start + timestamp.saturating_sub(start_timestamp);
+        if deadline > now {
+            thread::sleep(deadline - now);
+        } else if now - deadline > MAX_PACING_LAG {
+            self.anchor = Some((now, timestamp));
+        }
+    }
+
+    /// Restarts the schedule at the next frame, after the source was paused.
+    fn reset(&mut self) {
+        self.anchor = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pts_time() {
+        let line = "[Parsed_showinfo_0 @ 0x55d0c1f2c6c0] n:  12 pts:  12288 pts_time:0.4     \
+                    duration:   1024 duration_time:0.0333333 fmt:yuv420p";
+        assert_eq!(parse_pts_time(line), Some(Duration::from_millis(400)));
+        let line = "[Parsed_showinfo_0 @ 0x55d0c1f2c6c0] n:   0 pts:   -512 pts_time:-0.0166667";
+        assert_eq!(parse_pts_time(line), Some(Duration::ZERO));
+        assert_eq!(parse_pts_time("Stream #0:0: Video: h264, pts_time:1"), None);
+        assert_eq!(
+            parse_pts_time("[Parsed_showinfo_0 @ 0x0] config in time_base: 1/30720"),
+            None
+        );
+    }
+
+    #[test]
+    fn image_extensions() {
+        assert!(has_image_extension(Path::new("frames/0001.png")));
+        assert!(has_image_extension(Path::new("frames/0001.JPG")));
+        assert!(!has_image_extension(Path::new("frames/notes.txt")));
+        assert!(!has_image_extension(Path::new("frames/png")));
+    }
+}
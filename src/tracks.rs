@@ -0,0 +1,326 @@
+//! Bookkeeping for tracking several faces at once.
+//!
+//! Every face in view gets its own [`Track`], identified by an ephemeral ID that stays the same
+//! for as long as the face is being tracked. Face detections are associated with the existing
+//! tracks by their distance, and only detections that don't belong to any track start a new one.
+
+/// Maximum number of faces that are tracked at the same time.
+pub const MAX_FACES: usize = 4;
+
+/// Number of consecutive frames a face can go missing before its track (and ID) is retired.
+///
+/// This bridges short dropouts, like the face being briefly occluded or turned away.
+const MAX_MISSED_FRAMES: u32 = 10;
+
+/// Maximum distance between the centers of two regions, relative to the size of the larger one,
+/// for them to be considered the same face.
+const MAX_DISTANCE: f32 = 0.5;
+
+/// The rectangular region of the image a face occupies.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub center: [f32; 2],
+    /// Length of the longer side of the region.
+    pub size: f32,
+}
+
+impl Region {
+    /// Creates a [`Region`] from the corners of its bounding box.
+    pub fn bounding(min: [f32; 2], max: [f32; 2]) -> Self {
+        Self {
+            center: [(min[0] + max[0]) * 0.5, (min[1] + max[1]) * 0.5],
+            size: f32::max(max[0] - min[0], max[1] - min[1]),
+        }
+    }
+
+    /// Returns the distance to `other`, relative to the size of the larger region.
+    fn distance(&self, other: &Region) -> f32 {
+        let [dx, dy] = [
+            self.center[0] - other.center[0],
+            self.center[1] - other.center[1],
+        ];
+        dx.hypot(dy) / f32::max(self.size, other.size)
+    }
+}
+
+/// A single tracked face.
+pub struct Track<T> {
+    id: u32,
+    region: Region,
+    /// Number of frames since the face was last found.
+    missed: u32,
+    /// Per-face tracker state.
+    pub state: T,
+}
+
+impl<T> Track<T> {
+    /// Returns the ephemeral ID of this track.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns whether the face was found in the current frame.
+    pub fn is_found(&self) -> bool {
+        self.missed == 0
+    }
+
+    /// Records that the face was found at `region` in the current frame.
+    pub fn found(&mut self, region: Region) {
+        self.region = region;
+        self.missed = 0;
+    }
+}
+
+/// The result of [`Tracks::associate`].
+pub enum Association<'a, T> {
+    /// The detection belongs to a face that was already found in this frame.
+    Duplicate,
+    /// The detection belongs to a face that had gone missing.
+    Reacquired(&'a mut Track<T>),
+    /// The detection is a new face, and a new track was started for it.
+    New(&'a mut Track<T>),
+    /// The detection is a new face, but [`MAX_FACES`] faces are already being tracked.
+    Full,
+}
+
+/// The set of tracked faces.
+///
+/// Each frame should be processed as follows:
+///
+/// 1. Call [`Tracks::begin_frame`].
+/// 2. Call [`Track::found`] for every track whose face could be tracked directly.
+/// 3. Optionally, run face detection and pass the detections to [`Tracks::associate`], in order of
+///    decreasing confidence.
+/// 4. Call [`Tracks::end_frame`] to retire the faces that have gone missing.
+pub struct Tracks<T> {
+    tracks: Vec<Track<T>>,
+    next_id: u32,
+    new_state: Box<dyn FnMut() -> T + Send>,
+}
+
+impl<T> Tracks<T> {
+    /// Creates an empty set of tracks.
+    ///
+    /// `new_state` is invoked to create the per-face tracker state whenever a new face is found.
+    pub fn new(new_state: impl FnMut() -> T + Send + 'static) -> Self {
+        Self {
+            tracks: Vec::new(),
+            next_id: 0,
+            new_state: Box::new(new_state),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    /// Returns whether any track has missed its face in the current frame.
+    pub fn any_missing(&self) -> bool {
+        self.tracks.iter().any(|track| !track.is_found())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Track<T>> {
+        self.tracks.iter_mut()
+    }
+
+    /// Marks all faces as missing until they are [`found`][Track::found] again.
+    pub fn begin_frame(&mut self) {
+        for track in &mut self.tracks {
+            track.missed += 1;
+        }
+    }
+
+    /// Associates a face detected at `region` with the nearest track.
+    ///
+    /// If there is no track close enough, the detection is a new face, and a track is started for
+    /// it.
+    pub fn associate(&mut self, region: Region) -> Association<'_, T> {
+        let nearest = self
+            .tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| (i, track.region.distance(&region)))
+            .filter(|(_, distance)| *distance <= MAX_DISTANCE)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        match nearest {
+            Some((i, _)) if self.tracks[i].is_found() => Association::Duplicate,
+            Some((i, _)) => {
+                let track = &mut self.tracks[i];
+                track.found(region);
+                Association::Reacquired(track)
+            }
+            None => match self.insert(region) {
+                Some(track) => Association::New(track),
+                None => Association::Full,
+            },
+        }
+    }
+
+    /// Starts tracking a new face at `region`, allocating a fresh ID for it.
+    ///
+    /// Returns `None` if [`MAX_FACES`] faces are already being tracked.
+    fn insert(&mut self, region: Region) -> Option<&mut Track<T>> {
+        if self.tracks.len() >= MAX_FACES {
+            return None;
+        }
+
+        // IDs are handed out sequentially, so that a face that is lost and regained is
+        // distinguishable from the old one. Skip any IDs that are still in use after wrapping.
+        let mut id = self.next_id;
+        while self.tracks.iter().any(|track| track.id == id) {
+            id = id.wrapping_add(1);
+        }
+        self.next_id = id.wrapping_add(1);
+
+        self.tracks.push(Track {
+            id,
+            region,
+            missed: 0,
+            state: (self.new_state)(),
+        });
+        self.tracks.last_mut()
+    }
+
+    /// Retires the faces that have been missing for too long, as well as tracks that have
+    /// converged onto the same face as an older one.
+    ///
+    /// Returns the IDs of the retired tracks.
+    pub fn end_frame(&mut self) -> Vec<u32> {
+        let mut retired = Vec::new();
+        let mut i = 0;
+        while i < self.tracks.len() {
+            let track = &self.tracks[i];
+            let duplicate = track.is_found()
+                && self.tracks[..i].iter().any(|older| {
+                    older.is_found() && older.region.distance(&track.region) <= MAX_DISTANCE
+                });
+            if duplicate || track.missed > MAX_MISSED_FRAMES {
+                retired.push(self.tracks.remove(i).id);
+            } else {
+                i += 1;
+            }
+        }
+        retired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(x: f32, y: f32) -> Region {
+        Region {
+            center: [x, y],
+            size: 10.0,
+        }
+    }
+
+    /// Associates a detection, returning the ID of the track it was assigned to.
+    fn detect(tracks: &mut Tracks<()>, x: f32, y: f32) -> Option<u32> {
+        match tracks.associate(region(x, y)) {
+            Association::Reacquired(track) | Association::New(track) => Some(track.id()),
+            Association::Duplicate | Association::Full => None,
+        }
+    }
+
+    #[test]
+    fn association() {
+        let mut tracks = Tracks::new(|| ());
+        tracks.begin_frame();
+        assert_eq!(detect(&mut tracks, 0.0, 0.0), Some(0));
+        assert_eq!(detect(&mut tracks, 50.0, 0.0), Some(1));
+        assert!(tracks.end_frame().is_empty());
+
+        // Faces move a little, and are associated with their existing tracks.
+        tracks.begin_frame();
+        assert!(tracks.any_missing());
+        assert_eq!(detect(&mut tracks, 48.0, 2.0), Some(1));
+        assert_eq!(detect(&mut tracks, 3.0, 0.0), Some(0));
+        assert!(!tracks.any_missing());
+
+        // A duplicate detection of an already found face.
+        assert!(matches!(
+            tracks.associate(region(49.0, 2.0)),
+            Association::Duplicate
+        ));
+
+        // A new face.
+        assert!(matches!(
+            tracks.associate(region(25.0, 0.0)),
+            Association::New(_)
+        ));
+        assert!(tracks.end_frame().is_empty());
+        assert_eq!(tracks.len(), 3);
+    }
+
+    #[test]
+    fn retirement() {
+        let mut tracks = Tracks::new(|| ());
+        tracks.begin_frame();
+        detect(&mut tracks, 0.0, 0.0);
+        detect(&mut tracks, 50.0, 0.0);
+        tracks.end_frame();
+
+        // Face 0 goes missing, but its ID survives a short dropout.
+        for _ in 0..MAX_MISSED_FRAMES {
+            tracks.begin_frame();
+            assert_eq!(detect(&mut tracks, 50.0, 0.0), Some(1));
+            assert!(tracks.end_frame().is_empty());
+        }
+        tracks.begin_frame();
+        assert_eq!(detect(&mut tracks, 50.0, 0.0), Some(1));
+        assert_eq!(tracks.end_frame(), [0]);
+        assert_eq!(tracks.len(), 1);
+
+        // A new face doesn't reuse the retired ID.
+        tracks.begin_frame();
+        assert_eq!(detect(&mut tracks, 50.0, 0.0), Some(1));
+        assert_eq!(detect(&mut tracks, 0.0, 0.0), Some(2));
+        assert!(tracks.end_frame().is_empty());
+    }
+
+    #[test]
+    fn converged_tracks() {
+        let mut tracks = Tracks::new(|| ());
+        tracks.begin_frame();
+        detect(&mut tracks, 0.0, 0.0);
+        detect(&mut tracks, 50.0, 0.0);
+        tracks.end_frame();
+
+        // Both trackers end up following the same face; the newer track is retired.
+        tracks.begin_frame();
+        for track in tracks.iter_mut() {
+            track.found(region(1.0, 1.0));
+        }
+        assert_eq!(tracks.end_frame(), [1]);
+    }
+
+    #[test]
+    fn id_allocation() {
+        let mut tracks = Tracks::new(|| ());
+        tracks.next_id = u32::MAX;
+        tracks.begin_frame();
+        for i in 0..MAX_FACES {
+            detect(&mut tracks, i as f32 * 100.0, 0.0);
+        }
+        assert!(matches!(
+            tracks.associate(region(1000.0, 0.0)),
+            Association::Full
+        ));
+        let ids = tracks
+            .iter_mut()
+            .map(|track| track.id())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [u32::MAX, 0, 1, 2]);
+
+        // After wrapping, IDs that are still in use are skipped.
+        tracks.tracks.remove(2);
+        tracks.next_id = u32::MAX;
+        assert_eq!(detect(&mut tracks, 1000.0, 0.0), Some(1));
+    }
+}
@@ -156,11 +156,11 @@ impl TriangulatedEye {
 
     pub fn into_message(self) -> data::Eye {
         data::Eye {
-            texture: data::Image {
-                width: self.texture.width(),
-                height: self.texture.height(),
-                data: self.texture.with_data(|data| data.to_vec()),
-            },
+            texture: data::Image::from_rgba(
+                self.texture.width(),
+                self.texture.height(),
+                self.texture.with_data(|data| data.to_vec()),
+            ),
             mesh: self.mesh,
             iris_center: self.iris_center,
             iris_radius: self.iris_radius,